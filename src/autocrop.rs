@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use color_eyre::Result;
+use imgref::ImgRef;
+use rgb::RGBA8;
+
+use crate::decoder::WebmContext;
+use crate::filter::CropRect;
+
+const SAMPLES: u32 = 8;
+/// Rows and columns with an average luma at or below this are treated as border, like ffmpeg's cropdetect.
+const LUMA_LIMIT: u32 = 24;
+
+/// Samples frames spread over the input in `ctx` and returns the smallest rectangle that contains all non-black
+/// content, or `None` if there are no borders to remove.
+pub fn detect(mut ctx: WebmContext, forced_decoder: Option<&str>, decoder_priority: &[String])
+        -> Result<Option<CropRect>> {
+    let duration = ctx.duration() as i64;
+    let mut stream = ctx.best_stream()?;
+    let decoder = stream.find_decoder(forced_decoder, decoder_priority)?;
+    let mut decoder = stream.decode(decoder)?;
+
+    let mut size = None;
+    let mut content: Option<CropRect> = None;
+    for i in 0..SAMPLES {
+        if duration > 0 {
+            let at = Duration::from_micros(duration as u64).mul_f64((i as f64 + 0.5) / SAMPLES as f64);
+            decoder.seek_keyframe(at)?;
+        }
+        let Some((frame, _)) = decoder.next_frame()? else { break };
+
+        size = Some((frame.width(), frame.height()));
+        if let Some(rect) = content_rect(frame.as_ref()) {
+            content = Some(match content {
+                Some(c) => c.union(rect),
+                None => rect,
+            });
+        }
+    }
+
+    Ok(match (content, size) {
+        (Some(rect), Some((width, height))) if rect.width < width || rect.height < height => Some(rect),
+        _ => None,
+    })
+}
+
+fn content_rect(frame: ImgRef<RGBA8>) -> Option<CropRect> {
+    let is_content = |sum: u32, count: usize| sum > LUMA_LIMIT * count as u32;
+
+    let rows = frame.rows()
+        .map(|row| is_content(row.iter().map(|&px| luma(px)).sum(), row.len()))
+        .collect::<Vec<_>>();
+    let top = rows.iter().position(|&c| c)?;
+    let bottom = rows.iter().rposition(|&c| c)? + 1;
+
+    let mut column_sums = vec![0; frame.width()];
+    for row in frame.sub_image(0, top, frame.width(), bottom - top).rows() {
+        for (sum, &px) in column_sums.iter_mut().zip(row) {
+            *sum += luma(px);
+        }
+    }
+    let left = column_sums.iter().position(|&sum| is_content(sum, bottom - top))?;
+    let right = column_sums.iter().rposition(|&sum| is_content(sum, bottom - top))? + 1;
+
+    Some(CropRect { left, top, width: right - left, height: bottom - top })
+}
+
+fn luma(px: RGBA8) -> u32 {
+    (px.r as u32 * 299 + px.g as u32 * 587 + px.b as u32 * 114) / 1000
+}
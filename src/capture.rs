@@ -0,0 +1,50 @@
+use std::env;
+use std::ffi::CString;
+
+use color_eyre::Result;
+
+use crate::decoder::WebmContext;
+use crate::filter::CropRect;
+
+/// Opens the platform's screen grabber at `fps` frames per second.
+///
+/// Returns the crop rectangle that still has to be applied to the frames when the grabber cannot capture
+/// `region` by itself.
+pub fn open_screen(region: Option<CropRect>, fps: u32) -> Result<(WebmContext, Option<CropRect>)> {
+    let mut options = vec![("framerate", fps.to_string())];
+
+    if cfg!(target_os = "windows") {
+        if let Some(r) = region {
+            options.push(("offset_x", r.left.to_string()));
+            options.push(("offset_y", r.top.to_string()));
+            options.push(("video_size", format!("{}x{}", r.width, r.height)));
+        }
+        options.push(("draw_mouse", "1".to_owned()));
+        let ctx = WebmContext::open_device("gdigrab", &CString::new("desktop")?, &options)?;
+        Ok((ctx, None))
+    } else if cfg!(target_os = "macos") {
+        options.push(("capture_cursor", "1".to_owned()));
+        let ctx = WebmContext::open_device("avfoundation", &CString::new("Capture screen 0:none")?, &options)?;
+        Ok((ctx, region))
+    } else {
+        let mut display = env::var("DISPLAY").unwrap_or_else(|_| ":0".to_owned());
+        if let Some(r) = region {
+            display = format!("{display}+{},{}", r.left, r.top);
+            options.push(("video_size", format!("{}x{}", r.width, r.height)));
+        }
+        let ctx = WebmContext::open_device("x11grab", &CString::new(display)?, &options)?;
+        Ok((ctx, None))
+    }
+}
+
+/// Opens a camera or other video device at `fps` frames per second.
+pub fn open_camera(device: &str, fps: u32) -> Result<WebmContext> {
+    let options = [("framerate", fps.to_string())];
+    if cfg!(target_os = "windows") {
+        WebmContext::open_device("dshow", &CString::new(format!("video={device}"))?, &options)
+    } else if cfg!(target_os = "macos") {
+        WebmContext::open_device("avfoundation", &CString::new(format!("{device}:none"))?, &options)
+    } else {
+        WebmContext::open_device("v4l2", &CString::new(device)?, &options)
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader};
+use std::str::FromStr;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::Result;
+use color_eyre::eyre::{ensure, Context};
+use gif::DecodeOptions;
+
+/// What to do with an input once its gif has been written.
+#[derive(Clone)]
+pub enum OnSuccess {
+    /// Move the input into this directory.
+    Move(Utf8PathBuf),
+    Delete,
+    /// Send the input to the recycle bin.
+    Trash,
+}
+
+/// Parses `move:DIR`, `delete` or `trash`.
+impl FromStr for OnSuccess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("move", dir)) if !dir.is_empty() => Ok(OnSuccess::Move(dir.into())),
+            None if s == "delete" => Ok(OnSuccess::Delete),
+            None if s == "trash" => Ok(OnSuccess::Trash),
+            _ => Err(format!("expected move:DIR, delete or trash, got {s:?}")),
+        }
+    }
+}
+
+/// Makes sure `output` is on disk, optionally checks that it decodes, and then moves or deletes `input`.
+pub fn consume_input(action: &OnSuccess, input: &Utf8Path, output: &Utf8Path, validate: bool) -> Result<()> {
+    sync(output).wrap_err_with(|| format!("failed to flush {output}"))?;
+    // other formats cannot be decoded without going through ffmpeg again
+    if validate && output.extension() == Some("gif") {
+        validate_gif(output).wrap_err_with(|| format!("{output} is not a valid gif, keeping {input}"))?;
+    }
+
+    match action {
+        OnSuccess::Move(dir) => {
+            fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {dir}"))?;
+            let target = dir.join(input.file_name().unwrap_or_else(|| unreachable!()));
+            ensure!(!target.exists(), "{target} already exists, keeping {input}");
+            match fs::rename(input, &target) {
+                Ok(()) => Ok(()),
+                // rename does not work across file systems
+                Err(e) if crosses_devices(&e) => {
+                    fs::copy(input, &target).wrap_err_with(|| format!("failed to move {input} to {dir}"))?;
+                    sync(&target).wrap_err_with(|| format!("failed to flush {target}"))?;
+                    fs::remove_file(input).wrap_err_with(|| format!("failed to remove {input}"))
+                },
+                Err(e) => Err(e).wrap_err_with(|| format!("failed to move {input} to {dir}")),
+            }
+        },
+        OnSuccess::Delete => fs::remove_file(input).wrap_err_with(|| format!("failed to delete {input}")),
+        OnSuccess::Trash => trash::delete(input).wrap_err_with(|| format!("failed to move {input} to the trash")),
+    }
+}
+
+/// Flushes `path` to disk; Windows only does that through a handle that can write.
+fn sync(path: &Utf8Path) -> io::Result<()> {
+    OpenOptions::new().write(true).open(path)?.sync_all()
+}
+
+/// Whether a rename failed because the paths are on different file systems.
+fn crosses_devices(e: &io::Error) -> bool {
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(windows)]
+    const CODE: i32 = 17;
+    #[cfg(not(windows))]
+    const CODE: i32 = libc::EXDEV;
+    e.raw_os_error() == Some(CODE)
+}
+
+/// Decodes every frame of the gif.
+fn validate_gif(path: &Utf8Path) -> Result<()> {
+    let mut decoder = DecodeOptions::new().read_info(BufReader::new(File::open(path)?))?;
+    let mut frames = 0;
+    while decoder.read_next_frame()?.is_some() {
+        frames += 1;
+    }
+    ensure!(frames > 0, "no frames");
+    Ok(())
+}
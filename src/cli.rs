@@ -0,0 +1,630 @@
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use clap::{Parser, Subcommand};
+use gifski::Repeat;
+use rgb::RGB8;
+
+use crate::cleanup::OnSuccess;
+use crate::decoder::{ScaleFilter, ToneMap, DEFAULT_DECODER_PRIORITY};
+use crate::encoder::Format;
+use crate::filter::{AlphaMode, CropRect, SelectExpr, SpeedRampSpec, ZoomPanSpec};
+use crate::grid::GridLayout;
+use crate::progress::ProgressFormat;
+use crate::scan::NameTemplate;
+
+#[derive(Parser)]
+#[clap(version, about = "Convert webm files to gif")]
+pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input files or directories; the current directory is scanned if none are given
+    pub inputs: Vec<Utf8PathBuf>,
+
+    /// Also convert files in subdirectories of the scanned directories
+    #[clap(short, long)]
+    pub recursive: bool,
+
+    /// Extensions of the files picked up when scanning directories, e.g. webm,mkv,mp4, in any case
+    #[clap(long, visible_alias = "ext", value_name = "EXTENSIONS", use_value_delimiter = true, default_value = "webm")]
+    pub scan_ext: Vec<String>,
+
+    /// Also convert the files and directories listed in this file, one per line, or read the list from stdin if it
+    /// is -, e.g. `find . -name '*.webm' -print0 | webm2gif --files-from - -0`
+    #[clap(long, value_name = "FILE", conflicts_with_all = &["output", "grid"])]
+    pub files_from: Option<Utf8PathBuf>,
+
+    /// The list of --files-from is separated by NUL characters, as printed by `find -print0`, instead of newlines
+    #[clap(short = '0', long, requires = "files-from")]
+    pub null: bool,
+
+    /// Only pick up files matching this glob when scanning directories, e.g. '*.webm' or 'clips/**'; may be given
+    /// several times
+    #[clap(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files and directories matching this glob when scanning directories, e.g. 'raw_*'; may be given several
+    /// times and wins over --include
+    #[clap(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Keep running and convert files that appear in this directory, once they are no longer being written to;
+    /// files already in it are converted first. Stop with Ctrl-C
+    #[clap(long, value_name = "DIR", conflicts_with_all = &["output", "grid", "workers", "save-palette"])]
+    pub watch: Option<Utf8PathBuf>,
+
+    /// Take new files from the incoming folder of --watch and move each into processing while it is converted, then
+    /// into done, along with its output unless --output-dir is given, or into failed
+    #[clap(long, requires = "watch", conflicts_with_all = &["inputs", "files-from", "jobs", "on-success"])]
+    pub stages: bool,
+
+    /// Print details such as the decoder used for each input; -vv also prints every frame decoded and everything
+    /// ffmpeg reports
+    #[clap(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Print only errors
+    #[clap(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Read default options from this TOML file instead of webm2gif/config.toml in the user's config directory;
+    /// top-level keys apply to every format, a [gif], [webp], ... table only to that format, and the command line
+    /// overrides both
+    #[clap(long, value_name = "FILE")]
+    pub config: Option<Utf8PathBuf>,
+
+    /// Write the result of a single input to this file, or to stdout if it is -; the extension picks the format
+    #[clap(short, long, value_name = "FILE", conflicts_with = "output-dir")]
+    pub output: Option<Utf8PathBuf>,
+
+    /// Output format regardless of the output name: gif, apng, webp, avif, frames (a directory of PNGs) or spritesheet
+    #[clap(short, long, value_name = "FORMAT")]
+    pub format: Option<Format>,
+
+    /// Write gifs into this directory, recreating the structure of the scanned directories
+    #[clap(long, value_name = "DIR")]
+    pub output_dir: Option<Utf8PathBuf>,
+
+    /// Name outputs after this template instead of the input, e.g. '{stem}_{width}x{height}.gif'; can use {stem} and
+    /// {ext} of the input file and the {width}, {height} and {fps} of its video
+    #[clap(long, value_name = "TEMPLATE", conflicts_with = "output")]
+    pub name: Option<NameTemplate>,
+
+    /// Put all gifs directly into --output-dir instead of recreating subdirectories
+    #[clap(long, requires = "output-dir")]
+    pub flatten: bool,
+
+    /// Replace existing outputs, also those of inputs found by scanning directories or listed with --files-from,
+    /// which are skipped by default
+    #[clap(long, conflicts_with_all = &["skip-existing", "ask"])]
+    pub force: bool,
+
+    /// Keep existing outputs, also those of input files given directly, which are replaced by default; outputs older
+    /// than their input are still replaced unless --no-freshness-check is given
+    #[clap(long, conflicts_with = "ask")]
+    pub skip_existing: bool,
+
+    /// Ask before replacing each existing output
+    #[clap(long)]
+    pub ask: bool,
+
+    /// Treat outputs older than their input as up to date; by default they are converted again, e.g. after the
+    /// input was edited or downloaded again
+    #[clap(long)]
+    pub no_freshness_check: bool,
+
+    /// List which inputs would be converted to which outputs and which would be skipped and why, without converting
+    /// anything
+    #[clap(long, conflicts_with_all = &["ask", "watch", "grid", "workers", "save-palette"])]
+    pub dry_run: bool,
+
+    /// Encoding quality: the palette and dithering quality for gifs, the encoder quality for webp and avif
+    #[clap(long, value_name = "1-100", parse(try_from_str = parse_quality))]
+    pub quality: Option<u8>,
+
+    /// Quality of the temporal denoising and frame merging
+    #[clap(long, value_name = "1-100", parse(try_from_str = parse_quality))]
+    pub motion_quality: Option<u8>,
+
+    /// Lossy LZW compression quality; lower values give smaller files with more noise
+    #[clap(long, value_name = "1-100", parse(try_from_str = parse_quality))]
+    pub lossy_quality: Option<u8>,
+
+    /// Encode about 3 times faster at the cost of quality
+    #[clap(long)]
+    pub fast: bool,
+
+    /// Scale frames down to at most this many pixels wide, keeping the aspect ratio
+    #[clap(short = 'W', long, value_name = "PIXELS", parse(try_from_str = parse_pixels))]
+    pub width: Option<u32>,
+
+    /// Scale frames down to at most this many pixels high, keeping the aspect ratio
+    #[clap(short = 'H', long, value_name = "PIXELS", parse(try_from_str = parse_pixels))]
+    pub height: Option<u32>,
+
+    /// How many times the animation repeats: 0 loops forever, -1 plays it only once
+    #[clap(long, visible_alias = "loop", value_name = "COUNT", allow_hyphen_values = true,
+        parse(try_from_str = parse_repeat))]
+    pub repeat: Option<Repeat>,
+
+    /// Play the animation only once, the same as --repeat -1
+    #[clap(long, conflicts_with = "repeat")]
+    pub no_loop: bool,
+
+    /// Background color blended into semi-transparent pixels, e.g. #FFFFFF
+    #[clap(long, value_name = "COLOR", parse(try_from_str = parse_color))]
+    pub matte: Option<RGB8>,
+
+    /// Run gifsicle over the produced gif to further reduce its size
+    #[clap(long)]
+    pub optimize: bool,
+
+    /// Decode each gif again and report how close it is to the frames that were encoded, as SSIM and PSNR
+    #[clap(long)]
+    pub metrics: bool,
+
+    /// Build one palette from the colors of all gifs written and save it to this PNG, for --use-palette
+    #[clap(long, value_name = "FILE")]
+    pub save_palette: Option<Utf8PathBuf>,
+
+    /// Remap every gif to the colors of this PNG, e.g. one written by --save-palette, so related gifs share a
+    /// palette; at most 256 colors, transparent pixels stand for the transparent entry
+    #[clap(long, value_name = "FILE")]
+    pub use_palette: Option<Utf8PathBuf>,
+
+    /// Write interlaced frames so that partially downloaded gifs render progressively
+    #[clap(long)]
+    pub interlace: bool,
+
+    /// Whether the colors of transparent inputs are straight or premultiplied by alpha
+    #[clap(long, value_name = "MODE", default_value = "straight")]
+    pub alpha: AlphaMode,
+
+    /// Make pixels matching this color transparent, e.g. #00FF00 for a green screen
+    #[clap(long, value_name = "COLOR", parse(try_from_str = parse_color))]
+    pub chroma_key: Option<RGB8>,
+
+    /// How far a color may be from the chroma key and still become transparent
+    #[clap(long, value_name = "0-1", default_value = "0.1", parse(try_from_str = parse_fraction))]
+    pub key_tolerance: f32,
+
+    /// Run every frame through the process_frame function of this WebAssembly module; may be given several times
+    #[clap(long, value_name = "FILE")]
+    pub plugin: Vec<Utf8PathBuf>,
+
+    /// Keep only the frames for which this expression is true, e.g. 'not(mod(n,2)) and gte(t,1.5)'; can use n (frame
+    /// index), t (seconds), scene (0-1 change from the previous frame) and selected_n, with ffmpeg's select functions
+    #[clap(long, value_name = "EXPR")]
+    pub select: Option<SelectExpr>,
+
+    /// Rhai script whose fn frame(info) can drop frames, change how long they are shown or crop them
+    #[clap(long, value_name = "FILE")]
+    pub script: Option<Utf8PathBuf>,
+
+    /// Detect black borders on a few sampled frames and crop them away
+    #[clap(long)]
+    pub autocrop: bool,
+
+    /// Keep frames the way they are stored instead of turning them upright as the rotation metadata phones write says
+    #[clap(long)]
+    pub no_autorotate: bool,
+
+    /// Crop every frame to this rectangle, e.g. 640x360+0+60 for 640x360 pixels starting 60 pixels from the top
+    #[clap(long, value_name = "WxH+X+Y", conflicts_with = "autocrop")]
+    pub crop: Option<CropRect>,
+
+    /// Remove camera shake; keeps all decoded frames in memory until the end of the input
+    #[clap(long)]
+    pub stabilize: bool,
+
+    /// Animate a crop window over the clip, e.g. 'start=0,0,100%;end=20,10,80%' (top-left corner in pixels, size in percent)
+    #[clap(long, value_name = "SPEC")]
+    pub zoompan: Option<ZoomPanSpec>,
+
+    /// Combine all inputs into one gif, each playing in a cell of a COLUMNSxROWS grid
+    #[clap(long, value_name = "LAYOUT")]
+    pub grid: Option<GridLayout>,
+
+    /// Restart inputs that end early in grid mode instead of freezing on their last frame
+    #[clap(long, requires = "grid")]
+    pub grid_loop: bool,
+
+    /// Show a title card with this text before the clip
+    #[clap(long, value_name = "TEXT")]
+    pub title: Option<String>,
+
+    /// How long the title card is shown
+    #[clap(long, value_name = "DURATION", default_value = "1.5s", parse(try_from_str = parse_duration))]
+    pub title_duration: Duration,
+
+    /// Show this image after the clip
+    #[clap(long, value_name = "IMAGE")]
+    pub outro: Option<Utf8PathBuf>,
+
+    /// How long the outro image is shown
+    #[clap(long, value_name = "DURATION", default_value = "1.5s", parse(try_from_str = parse_duration))]
+    pub outro_duration: Duration,
+
+    /// Fade in from black over this long at the start of the clip
+    #[clap(long, value_name = "DURATION", default_value = "0s", parse(try_from_str = parse_duration))]
+    pub fade_in: Duration,
+
+    /// Fade out to black over this long at the end of the clip
+    #[clap(long, value_name = "DURATION", default_value = "0s", parse(try_from_str = parse_duration))]
+    pub fade_out: Duration,
+
+    /// Blend this much of the end of the clip into its start to hide the loop point
+    #[clap(long, value_name = "DURATION", default_value = "0s", parse(try_from_str = parse_duration))]
+    pub loop_crossfade: Duration,
+
+    /// Play the clip this many times as fast, e.g. 2 or 0.5; the same frames are decoded either way
+    #[clap(long, value_name = "FACTOR", default_value = "1", parse(try_from_str = parse_speed))]
+    pub speed: f64,
+
+    /// Play the clip backwards; keeps all decoded frames in memory until the end of the input
+    #[clap(long, conflicts_with = "boomerang")]
+    pub reverse: bool,
+
+    /// Play the clip forwards and then backwards; keeps all decoded frames in memory until the end of the input
+    #[clap(long)]
+    pub boomerang: bool,
+
+    /// Play parts of the clip at different speeds, e.g. '0-2s:1.0,2-4s:0.25,4s-:1.0'
+    #[clap(long, value_name = "SEGMENTS")]
+    pub speed_ramp: Option<SpeedRampSpec>,
+
+    /// Keep the last frame on screen this much longer before the gif ends or loops
+    #[clap(long, value_name = "DURATION", default_value = "0s", parse(try_from_str = parse_duration))]
+    pub hold_last: Duration,
+
+    /// Drop frames to show at most this many per second, e.g. 15 for a 60 fps input; the timing stays the same
+    #[clap(long, value_name = "FPS", parse(try_from_str = parse_fps))]
+    pub fps: Option<f64>,
+
+    /// Start converting at this time in each input, e.g. 3, 1m20s or 00:00:03
+    #[clap(long, value_name = "TIME", parse(try_from_str = parse_duration))]
+    pub start: Option<Duration>,
+
+    /// Stop converting at this time in each input
+    #[clap(long, value_name = "TIME", parse(try_from_str = parse_duration), conflicts_with = "duration")]
+    pub end: Option<Duration>,
+
+    /// Convert only this long from --start on
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+
+    /// Stop after this many frames have been encoded, regardless of the clip length
+    #[clap(long, value_name = "COUNT")]
+    pub max_frames: Option<usize>,
+
+    /// Drop near-uniform frames, such as black ones, at the start of the clip
+    #[clap(long)]
+    pub skip_blank_start: bool,
+
+    /// Maximum standard deviation of the brightness (0-255) for a frame to count as blank
+    #[clap(long, value_name = "DEVIATION", default_value = "8", requires = "skip-blank-start")]
+    pub blank_threshold: f64,
+
+    /// Skip inputs shorter than this
+    #[clap(long, visible_alias = "skip-shorter-than", value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub min_duration: Option<Duration>,
+
+    /// Skip inputs longer than this
+    #[clap(long, visible_alias = "skip-longer-than", value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub max_duration: Option<Duration>,
+
+    /// Skip inputs narrower than this many pixels
+    #[clap(long, value_name = "PIXELS")]
+    pub min_width: Option<u32>,
+
+    /// Skip inputs with fewer rows of pixels than this
+    #[clap(long, value_name = "PIXELS")]
+    pub min_height: Option<u32>,
+
+    /// Skip input files smaller than this, e.g. 100KB
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub min_size: Option<u64>,
+
+    /// Skip input files larger than this, e.g. 200MB
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub max_size: Option<u64>,
+
+    /// Warn when a gif ends up larger than this, e.g. 15MB
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub max_output_size: Option<u64>,
+
+    /// Retry gifs larger than --max-output-size at a smaller size, frame rate and quality until they fit
+    #[clap(long, requires = "max-output-size")]
+    pub auto_shrink: bool,
+
+    /// How progress is shown: bar, or json for one JSON object per line on stdout when a file is started, skipped,
+    /// finished or fails and for every frame encoded, for front-ends
+    #[clap(long, value_name = "FORMAT", default_value = "bar")]
+    pub progress: ProgressFormat,
+
+    /// Layout of the progress bar, see the indicatif docs; e.g. add {elapsed_precise}, {eta} or {fps}
+    #[clap(long, value_name = "TEMPLATE", default_value = " {prefix:.green.bright} {msg} [{bar:50}]{percent:>3}%")]
+    pub progress_template: String,
+
+    /// Characters used for the filled part, the tip and the empty part of the progress bar
+    #[clap(long, value_name = "CHARS", default_value = "=> ", parse(try_from_str = parse_progress_chars))]
+    pub progress_chars: String,
+
+    /// Limit the CPU time of the converter and any tools it runs, in percent of all cores together, e.g. 50%
+    #[clap(long, value_name = "PERCENT", parse(try_from_str = parse_percent))]
+    pub cpu_limit: Option<f64>,
+
+    /// Limit the memory of the converter and any tools it runs, e.g. 2G
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    pub memory_limit: Option<u64>,
+
+    /// Limit reading inputs and writing outputs to this many bytes per second in total, e.g. 50MB/s, to leave
+    /// bandwidth for others on network drives
+    #[clap(long, value_name = "RATE", parse(try_from_str = parse_rate))]
+    pub io_limit: Option<u64>,
+
+    /// Frames decoding may get ahead of encoding before it waits; more smooths out uneven frames at the cost of memory
+    #[clap(long, value_name = "FRAMES", default_value = "4")]
+    pub queue: usize,
+
+    /// Convert this many files at the same time, each with its own progress bar
+    #[clap(short, long, value_name = "N", default_value = "1", parse(try_from_str = parse_jobs),
+        conflicts_with_all = &["grid", "workers"])]
+    pub jobs: usize,
+
+    /// Hand the files of the batch to workers started with 'worker --connect' on other machines, listening for them
+    /// on this address, e.g. 0.0.0.0:7878 with --worker-token; files named in options such as --script must exist on
+    /// the workers too
+    #[clap(long, value_name = "ADDR", conflicts_with_all = &["grid", "save-palette"])]
+    pub workers: Option<String>,
+
+    /// Secret the workers have to send to get files from --workers, given to 'worker' the same way; without it
+    /// --workers only listens on loopback addresses
+    #[clap(long, value_name = "TOKEN")]
+    pub worker_token: Option<String>,
+
+    /// Decode with this libavcodec decoder instead of picking one from --decoder-priority
+    #[clap(long, value_name = "NAME")]
+    pub decoder: Option<String>,
+
+    /// List the video decoders compiled into ffmpeg and exit
+    #[clap(long)]
+    pub list_decoders: bool,
+
+    /// Decoders to try in order, e.g. to prefer libaom-av1 or the native vp9 decoder
+    #[clap(long, value_name = "NAMES", use_value_delimiter = true, default_value = DEFAULT_DECODER_PRIORITY)]
+    pub decoder_priority: Vec<String>,
+
+    /// How the decoded frames are converted to RGBA: fast-bilinear, bilinear, bicubic, lanczos or spline
+    #[clap(long, value_name = "FILTER", default_value = "fast-bilinear")]
+    pub scale_filter: ScaleFilter,
+
+    /// Curve bringing HDR (PQ or HLG) video down to the range of a gif: hable, reinhard or none
+    #[clap(long, value_name = "CURVE", default_value = "hable")]
+    pub tonemap: ToneMap,
+
+    /// What to do with an input after it has been converted: move:DIR, delete or trash
+    #[clap(long, value_name = "ACTION")]
+    pub on_success: Option<OnSuccess>,
+
+    /// Decode the written gif again before moving, deleting or trashing its input
+    #[clap(long, requires = "on-success")]
+    pub validate: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Record the screen or a camera straight into a gif
+    Record(RecordArgs),
+    /// Convert a gif, or anything else ffmpeg can decode, back into a regular video
+    ToVideo(ToVideoArgs),
+    /// Convert files sent by a batch started with --workers on another machine
+    Worker(WorkerArgs),
+}
+
+#[derive(clap::Args)]
+pub struct RecordArgs {
+    /// Area to record; the whole screen or camera image if omitted
+    #[clap(long, value_name = "X,Y,WIDTH,HEIGHT", parse(try_from_str = parse_region))]
+    pub region: Option<CropRect>,
+
+    /// Record from a camera instead of the screen, e.g. /dev/video0 on Linux or the device name on Windows and macOS
+    #[clap(long, value_name = "NAME")]
+    pub device: Option<String>,
+
+    /// How long to record; recording stops when Enter is pressed if omitted
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+
+    /// Frames captured per second
+    #[clap(long, default_value = "15")]
+    pub fps: u32,
+
+    pub output: Utf8PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct ToVideoArgs {
+    pub input: Utf8PathBuf,
+
+    /// Video to write; .webm and .mkv are encoded with VP9 keeping transparency, .mp4 and .mov with H.264. The
+    /// quality is taken from --quality if given
+    #[clap(short, long, value_name = "FILE")]
+    pub output: Utf8PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct WorkerArgs {
+    /// Address the batch listens on with --workers
+    #[clap(long, value_name = "HOST:PORT")]
+    pub connect: String,
+}
+
+fn parse_quality(s: &str) -> Result<u8, String> {
+    match s.parse::<u8>() {
+        Ok(q @ 1..=100) => Ok(q),
+        _ => Err(format!("expected a number between 1 and 100, got {s:?}")),
+    }
+}
+
+fn parse_pixels(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(format!("expected a number of pixels above zero, got {s:?}")),
+    }
+}
+
+fn parse_fps(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        _ => Err(format!("expected a frame rate above zero, got {s:?}")),
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("expected a speed factor above zero, got {s:?}")),
+    }
+}
+
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a number of files above zero, got {s:?}")),
+    }
+}
+
+fn parse_repeat(s: &str) -> Result<Repeat, String> {
+    match s.parse::<i32>() {
+        Ok(0) => Ok(Repeat::Infinite),
+        Ok(-1) => Ok(Repeat::Finite(0)),
+        Ok(n) => u16::try_from(n).map(Repeat::Finite).map_err(|_| format!("expected -1, 0 or a repeat count, got {s:?}")),
+        Err(_) => Err(format!("expected -1, 0 or a repeat count, got {s:?}")),
+    }
+}
+
+fn parse_fraction(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if (0.0..=1.0).contains(&v) => Ok(v),
+        _ => Err(format!("expected a number between 0 and 1, got {s:?}")),
+    }
+}
+
+/// Parses durations like `1.5`, `1.5s`, `500ms`, `2m`, `1h`, `1m20s` or `00:01:30.5`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let parse_number = |number: &str| number.parse::<f64>().ok().filter(|v| *v >= 0.0);
+    let seconds = if s.contains(':') {
+        s.split(':')
+            .try_fold(0.0, |acc, part| parse_number(part).map(|v| acc * 60.0 + v))
+            .filter(|_| s.split(':').count() <= 3)
+    } else if s.contains(|c: char| c.is_ascii_alphabetic()) {
+        // numbers each followed by their unit, e.g. 1h30m or 1m20s
+        let mut seconds = Some(0.0);
+        let mut rest = s;
+        while !rest.is_empty() {
+            let (number, tail) = rest.split_at(rest.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(rest.len()));
+            let (unit, tail) = tail.split_at(tail.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(tail.len()));
+            let scale = match unit {
+                "ms" => 0.001,
+                "s" => 1.0,
+                "m" | "min" => 60.0,
+                "h" => 3600.0,
+                // a number without a unit after the first, e.g. 1m20
+                "" => return Err(format!("missing duration unit after {number:?} in {s:?}")),
+                _ => return Err(format!("unknown duration unit {unit:?} in {s:?}")),
+            };
+            seconds = seconds.zip(parse_number(number)).map(|(seconds, v)| seconds + v * scale);
+            rest = tail;
+        }
+        seconds
+    } else {
+        parse_number(s)
+    };
+    seconds.filter(|v| v.is_finite())
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("invalid duration {s:?}, expected e.g. 1.5s, 500ms, 2m or 00:01:30"))
+}
+
+fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.strip_suffix('%').unwrap_or(s).trim().parse::<f64>() {
+        Ok(v) if v > 0.0 && v <= 100.0 => Ok(v),
+        _ => Err(format!("expected a percentage between 0 and 100, got {s:?}")),
+    }
+}
+
+fn parse_progress_chars(s: &str) -> Result<String, String> {
+    match s.chars().count() {
+        2.. => Ok(s.to_owned()),
+        _ => Err(format!("expected at least two characters, got {s:?}")),
+    }
+}
+
+/// Parses sizes like `512`, `100KB`, `1.5MB` or `2GiB`; units are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("unknown size unit {unit:?} in {s:?}")),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(v) if v >= 0.0 && v.is_finite() => Ok((v * scale as f64) as u64),
+        _ => Err(format!("invalid size {s:?}, expected e.g. 100KB or 200MB")),
+    }
+}
+
+/// Parses rates like `50MB/s`, or just `50MB`.
+fn parse_rate(s: &str) -> Result<u64, String> {
+    match parse_size(s.strip_suffix("/s").unwrap_or(s))? {
+        0 => Err(format!("expected a rate above zero, got {s:?}")),
+        rate => Ok(rate),
+    }
+}
+
+fn parse_region(s: &str) -> Result<CropRect, String> {
+    let values = s.split(',').map(|v| v.trim().parse::<usize>()).collect::<Result<Vec<_>, _>>();
+    match values.as_deref() {
+        Ok(&[left, top, width, height]) if width > 0 && height > 0 => Ok(CropRect { left, top, width, height }),
+        _ => Err(format!("expected a region like 0,0,1280,720, got {s:?}")),
+    }
+}
+
+pub fn parse_color(s: &str) -> Result<RGB8, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("expected a color in the form #RRGGBB, got {s:?}"));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_else(|_| unreachable!());
+    Ok(RGB8::new(channel(0), channel(2), channel(4)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let secs = |s: &str| parse_duration(s).unwrap().as_secs_f64();
+        assert_eq!(secs("1.5"), 1.5);
+        assert_eq!(secs("1.5s"), 1.5);
+        assert_eq!(secs("500ms"), 0.5);
+        assert_eq!(secs("2m"), 120.0);
+        assert_eq!(secs("1h"), 3600.0);
+        assert_eq!(secs("1m20s"), 80.0);
+        assert_eq!(secs("1h30m"), 5400.0);
+        assert_eq!(secs("1m500ms"), 60.5);
+        assert_eq!(secs("00:00:03"), 3.0);
+        assert_eq!(secs("01:30.5"), 90.5);
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in ["", "s", "1m20", "1x", "-1s", "1:2:3:4", "1m-2s", "inf"] {
+            assert!(parse_duration(s).is_err(), "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Defaults for command line options read from a TOML file and from environment variables.
+//!
+//! Keys are the long option names, e.g. `lossy-quality = 60` or `optimize = true`. Tables named after an output
+//! format (`[gif]`, `[apng]`, `[webp]`, `[avif]`, `[frames]`, `[spritesheet]`) only apply when writing that
+//! format, which matters for options like `quality` whose meaning differs between encoders.
+//!
+//! Environment variables are the long option names in upper case with `W2G_` in front and `_` in place of `-`, e.g.
+//! `W2G_LOSSY_QUALITY=60`; flags take `1`, `true`, `yes` or `on` and `0`, `false`, `no`, `off` or nothing.
+//! `W2G_CONFIG` names the config file.
+//!
+//! Precedence, from lowest to highest: built-in defaults, top-level keys, the table of the output format,
+//! environment variables, the command line.
+//!
+//! `--watch` reads the config file again when it changes, for the files found after that.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::time::SystemTime;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use color_eyre::Result;
+use color_eyre::eyre::{bail, eyre, Context};
+use toml::value::{Table, Value};
+use tracing::{error, info};
+
+use crate::cli::{Args, Command};
+use crate::encoder::Format;
+
+const ENV_PREFIX: &str = "W2G_";
+/// Environment variable naming the config file, if `--config` is not given.
+const CONFIG_VAR: &str = "W2G_CONFIG";
+const FORMAT_TABLES: &[&str] = &["gif", "apng", "webp", "avif", "frames", "spritesheet"];
+
+/// Parses the command line on top of the environment and the config file given with `--config` or `W2G_CONFIG` or
+/// found in the default location.
+///
+/// Also returns the command line with the options from the environment and config file added, which parses to the
+/// same `Args`.
+pub fn parse_args() -> Result<(Args, Vec<OsString>)> {
+    let cli = env::args_os().collect::<Vec<_>>();
+    let matches = Args::command().get_matches_from(&cli);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let command = Args::command();
+    let env_options = env_options(&command)?;
+    let path = match config_path(&args)? {
+        // only a file named with --config or W2G_CONFIG has to exist
+        Some(path) if path.is_file() || args.config.is_some() || env::var_os(CONFIG_VAR).is_some() => Some(path),
+        _ => None,
+    };
+    let mut defaults = match path {
+        Some(path) => config_file_args(&path, &args, &command, &matches, &env_options)?,
+        None => Vec::new(),
+    };
+    for (key, mut option) in env_options {
+        // the command line wins
+        if matches.occurrences_of(key.as_str()) == 0 {
+            defaults.append(&mut option);
+        }
+    }
+    if defaults.is_empty() {
+        return Ok((args, cli));
+    }
+
+    // the options go before any subcommand
+    let mut full = vec![cli[0].clone()];
+    full.extend(defaults);
+    full.extend(cli[1..].iter().cloned());
+    match Args::try_parse_from(&full) {
+        Ok(args) => Ok((args, full)),
+        Err(e) if e.use_stderr() => Err(eyre!("{e}")).wrap_err("invalid options in the config file or environment"),
+        Err(e) => e.exit(),
+    }
+}
+
+/// Notices changes to the config file while `--watch` runs, so that the files found next are converted with the
+/// new options. What was set up once at the start, such as the directory watched or the number of --jobs, stays.
+pub struct ConfigWatcher {
+    path: Utf8PathBuf,
+    modified: Option<SystemTime>,
+    /// The command line the current options were parsed from.
+    argv: Vec<OsString>,
+}
+
+impl ConfigWatcher {
+    /// Watches the config file `args` was read from, or the default one which may be created later; `argv` is the
+    /// command line `parse_args` returned with `args`.
+    pub fn new(args: &Args, argv: Vec<OsString>) -> Result<Option<Self>> {
+        let Some(path) = config_path(args)? else { return Ok(None) };
+        let modified = modified(&path);
+        Ok(Some(Self { path, modified, argv }))
+    }
+
+    /// Parses the options again if the config file changed since the last call and prints the ones that changed.
+    /// A file that fails to parse, e.g. one saved half-way through an edit, keeps the current options.
+    pub fn reload(&mut self) -> Option<Args> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let (args, argv) = match parse_args() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                error!("{e:#}, keeping the previous options");
+                return None;
+            },
+        };
+        let changes = changes(&self.argv, &argv);
+        self.argv = argv;
+        if changes.is_empty() {
+            return None;
+        }
+        info!("Reloaded {}: {}", self.path, changes.join(", "));
+        Some(args)
+    }
+}
+
+fn modified(path: &Utf8Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The options that differ between two command lines from `parse_args`, e.g. `quality 80 -> 90`.
+fn changes(old: &[OsString], new: &[OsString]) -> Vec<String> {
+    let (old, new) = (option_values(old), option_values(new));
+    let describe = |values: Option<&Vec<String>>| values.map_or_else(|| "unset".to_owned(), |values| values.join(", "));
+    old.keys().chain(new.keys()).collect::<BTreeSet<_>>().into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| format!("{key} {} -> {}", describe(old.get(key)), describe(new.get(key))))
+        .collect()
+}
+
+/// Values of the `--key=value` options, and `on` for the `--key` flags, by key. The config file and environment
+/// always pass values this way; options the command line gives differently are the same in both command lines.
+fn option_values(argv: &[OsString]) -> BTreeMap<&str, Vec<String>> {
+    let mut options = BTreeMap::<&str, Vec<String>>::new();
+    for option in argv.iter().filter_map(|option| option.to_str()?.strip_prefix("--")) {
+        let (key, value) = option.split_once('=').unwrap_or((option, "on"));
+        options.entry(key).or_default().push(value.to_owned());
+    }
+    options
+}
+
+/// Options from `W2G_*` environment variables, by the id of their argument.
+fn env_options(command: &clap::Command) -> Result<BTreeMap<String, Vec<OsString>>> {
+    let mut options = BTreeMap::new();
+    for (name, value) in env::vars_os() {
+        let Some(name) = name.to_str().filter(|name| name.starts_with(ENV_PREFIX) && *name != CONFIG_VAR) else {
+            continue;
+        };
+        let key = name[ENV_PREFIX.len()..].to_ascii_lowercase().replace('_', "-");
+        let arg = command.get_arguments()
+            .find(|arg| arg.get_id() == key && arg.get_long().is_some() && !["help", "version"].contains(&arg.get_id()))
+            .ok_or_else(|| eyre!("unknown environment variable {name}, there is no --{key} option"))?;
+        let value = value.into_string().map_err(|_| eyre!("{name} is not valid utf-8"))?;
+        let mut option = Vec::new();
+        if arg.is_takes_value_set() {
+            option.push(format!("--{key}={value}").into());
+        } else {
+            match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => option.push(format!("--{key}").into()),
+                "0" | "false" | "no" | "off" | "" => {},
+                _ => bail!("{name} must be 1 or 0, got {value:?}"),
+            }
+        }
+        options.insert(key, option);
+    }
+    Ok(options)
+}
+
+/// Options from the config file at `path` that neither the command line nor the environment set.
+fn config_file_args(path: &Utf8Path, args: &Args, command: &clap::Command, matches: &ArgMatches,
+        env_options: &BTreeMap<String, Vec<OsString>>) -> Result<Vec<OsString>> {
+    let text = fs::read_to_string(path).wrap_err_with(|| format!("failed to read {path}"))?;
+    let table = toml::from_str::<Table>(&text).wrap_err_with(|| format!("failed to parse {path}"))?;
+
+    // later entries replace earlier ones
+    let mut options = BTreeMap::new();
+    let format = output_format(args);
+    for (key, value) in &table {
+        match value {
+            Value::Table(section) if FORMAT_TABLES.contains(&key.as_str()) => {
+                if format_name(format) == key {
+                    options.extend(section.iter());
+                }
+            },
+            Value::Table(_) => bail!("unknown section [{key}] in {path}, expected one of {}", FORMAT_TABLES.join(", ")),
+            _ => {
+                options.entry(key).or_insert(value);
+            },
+        }
+    }
+
+    let mut config_args = Vec::new();
+    for (key, value) in options {
+        if !command.get_arguments().any(|arg| arg.get_id() == key && arg.get_long().is_some()) {
+            bail!("unknown option {key} in {path}");
+        }
+        // the command line and the environment win
+        if matches.occurrences_of(key.as_str()) > 0 || env_options.contains_key(key) {
+            continue;
+        }
+        to_args(key, value, &mut config_args).wrap_err_with(|| format!("invalid value for {key} in {path}"))?;
+    }
+    Ok(config_args)
+}
+
+/// The config file given with `--config` or `W2G_CONFIG`, otherwise the default one whether it exists or not.
+fn config_path(args: &Args) -> Result<Option<Utf8PathBuf>> {
+    Ok(match &args.config {
+        Some(path) => Some(path.clone()),
+        None => match env::var_os(CONFIG_VAR) {
+            Some(path) => Some(Utf8PathBuf::from(path.into_string().map_err(|_| eyre!("{CONFIG_VAR} is not valid utf-8"))?)),
+            None => default_path(),
+        },
+    })
+}
+
+/// `webm2gif/config.toml` in the platform's config directory, e.g. `~/.config` on Linux.
+fn default_path() -> Option<Utf8PathBuf> {
+    let dir = Utf8PathBuf::from_path_buf(dirs::config_dir()?).ok()?;
+    Some(dir.join("webm2gif").join("config.toml"))
+}
+
+/// The format the outputs will be written in, which picks the table that applies.
+fn output_format(args: &Args) -> Format {
+    let output = match &args.command {
+        Some(Command::Record(record)) => Some(record.output.as_path()),
+        Some(Command::ToVideo(_) | Command::Worker(_)) => None,
+        None => args.output.as_deref(),
+    };
+    args.format
+        .or_else(|| output.and_then(|output| Format::from_path(output).ok()))
+        .unwrap_or(Format::Gif)
+}
+
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::Gif => "gif",
+        Format::Apng => "apng",
+        Format::WebP => "webp",
+        Format::Avif => "avif",
+        Format::Frames => "frames",
+        Format::Spritesheet => "spritesheet",
+    }
+}
+
+fn to_args(key: &str, value: &Value, out: &mut Vec<OsString>) -> Result<()> {
+    match value {
+        Value::Boolean(true) => out.push(format!("--{key}").into()),
+        // flags cannot be turned off
+        Value::Boolean(false) => {},
+        Value::String(s) => out.push(format!("--{key}={s}").into()),
+        Value::Integer(i) => out.push(format!("--{key}={i}").into()),
+        Value::Float(f) => out.push(format!("--{key}={f}").into()),
+        Value::Array(values) => {
+            for value in values {
+                match value {
+                    Value::Array(_) | Value::Table(_) => bail!("nested lists are not supported"),
+                    value => to_args(key, value, out)?,
+                }
+            }
+        },
+        Value::Datetime(_) | Value::Table(_) => bail!("expected a string, number, boolean or list"),
+    }
+    Ok(())
+}
@@ -1,24 +1,29 @@
-use std::ffi::CStr;
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
+use std::io::{Cursor, Read, Seek};
+use std::str::FromStr;
 use std::marker::PhantomData;
 use std::ptr::NonNull;
 use std::{ptr, mem};
 use std::sync::Once;
+use std::time::Duration;
 
 use color_eyre::Result;
 use color_eyre::eyre::{ensure, eyre, Context};
 use ffmpeg_sys_next as f;
 
 mod error;
+mod io;
+mod log;
+mod tonemap;
 pub use error::*;
+use io::CustomIo;
+pub use tonemap::ToneMap;
+use tonemap::{ToneMapper, Transfer};
+use crate::filter::FpsCap;
 use imgref::ImgVec;
 use rgb::{RGBA8, ComponentBytes};
 
-macro_rules! c_str {
-    ($s:literal) => {
-        concat!($s, "\0").as_ptr() as *const std::os::raw::c_char
-    };
-}
-
 macro_rules! to_str {
     ($ptr:expr) => {
         CStr::from_ptr($ptr).to_string_lossy()
@@ -27,8 +32,13 @@ macro_rules! to_str {
 
 static INIT: Once = Once::new();
 
+/// Error diffusion in swscale's `sws_dither` option, whose enum is not part of the public headers.
+const SWS_DITHER_ED: i64 = 3;
+
 pub struct WebmContext {
     ptr: *mut f::AVFormatContext,
+    /// Set when reading from memory or a reader rather than a file or URL; freed after `ptr`.
+    _io: Option<CustomIo>,
     _marker: PhantomData<&'static f::AVFormatContext>
 }
 
@@ -41,46 +51,245 @@ pub struct WebmDecoder<'ctx> {
     ctx: &'ctx mut WebmContext,
     stream: *mut f::AVStream,
     dec_ctx: *mut f::AVCodecContext,
-    sws_ctx: Option<NonNull<f::SwsContext>>,
+    scaler: Option<Scaler>,
+    scale_filter: ScaleFilter,
+    tone_map: ToneMap,
+    /// Drops frames before they are converted, see [`Decoder::with_fps_cap`].
+    fps_cap: Option<FpsCap>,
+    /// Kept from one HDR frame to the next, with the 16 bit RGBA it is converted from.
+    tone_mapper: Option<ToneMapper>,
+    hdr_buf: Vec<u16>,
     packet: *mut f::AVPacket,
     frame: *mut f::AVFrame,
-    info: Option<StreamInfo>,
+    /// Size of the first frame, which later frames have to match.
+    first_frame: Option<FrameFormat>,
+    /// Set once the iterator returned an error, after which it only returns `None`.
+    failed: bool,
+    /// Frames ending at or before this timestamp, in the time base of the stream, are skipped after a seek.
+    skip_until: Option<i64>,
+    stats: DecodeStats,
     _marker1: PhantomData<&'static f::AVCodecContext>,
     _marker2: PhantomData<&'static f::AVPacket>,
     _marker3: PhantomData<&'static f::AVFrame>
 }
 
+/// A libavcodec decoder, picked for a stream by [`WebmStream::find_decoder`], and how its frames are converted
+/// to RGBA.
+#[derive(Clone, Copy)]
+pub struct Decoder {
+    codec: *const f::AVCodec,
+    scale_filter: ScaleFilter,
+    tone_map: ToneMap,
+    fps_cap: Option<f64>,
+}
+
+/// The swscale algorithm used for the conversion to RGBA, which interpolates the subsampled chroma.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScaleFilter {
+    FastBilinear,
+    Bilinear,
+    Bicubic,
+    Lanczos,
+    Spline,
+}
+
+impl Default for ScaleFilter {
+    fn default() -> Self {
+        ScaleFilter::FastBilinear
+    }
+}
+
+impl ScaleFilter {
+    fn flags(self) -> i64 {
+        (match self {
+            ScaleFilter::FastBilinear => f::SWS_FAST_BILINEAR,
+            ScaleFilter::Bilinear => f::SWS_BILINEAR,
+            ScaleFilter::Bicubic => f::SWS_BICUBIC,
+            ScaleFilter::Lanczos => f::SWS_LANCZOS,
+            ScaleFilter::Spline => f::SWS_SPLINE,
+        }) as i64
+    }
+}
+
+/// Parses the names listed in `--scale-filter`.
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast-bilinear" => Ok(ScaleFilter::FastBilinear),
+            "bilinear" => Ok(ScaleFilter::Bilinear),
+            "bicubic" => Ok(ScaleFilter::Bicubic),
+            "lanczos" => Ok(ScaleFilter::Lanczos),
+            "spline" => Ok(ScaleFilter::Spline),
+            _ => Err(format!("expected fast-bilinear, bilinear, bicubic, lanczos or spline, got {s:?}")),
+        }
+    }
+}
+
 unsafe impl Send for WebmContext {}
 unsafe impl<'ctx> Send for WebmStream<'ctx> {}
+// decoders are static data inside libavcodec
+unsafe impl Send for Decoder {}
+unsafe impl Sync for Decoder {}
+
+/// Decoders tried in this order when several can handle a stream; the first one the linked ffmpeg was built
+/// with wins.
+pub const DEFAULT_DECODER_PRIORITY: &str = "libdav1d,libvpx-vp9,vp9,libvpx,vp8,libaom-av1,av1";
+
+/// The only decoders that read the alpha channel webm stores beside VP8 and VP9 frames; they are tried first for
+/// streams that have one.
+const ALPHA_DECODERS: [&str; 2] = ["libvpx-vp9", "libvpx"];
+
+impl Decoder {
+    fn new(codec: *const f::AVCodec) -> Self {
+        Self { codec, scale_filter: ScaleFilter::default(), tone_map: ToneMap::default(), fps_cap: None }
+    }
 
-#[allow(unused)]
-pub enum VpxCodec {
-    VP8,
-    VP9
+    /// Looks up any video decoder known to libavcodec, whether or not it handles the stream it is used for.
+    pub fn by_name(name: &str) -> Result<Self> {
+        unsafe {
+            let codec = f::avcodec_find_decoder_by_name(CString::new(name)?.as_ptr());
+            ensure!(!codec.is_null(), "decoder {} not found, see --list-decoders", name);
+            ensure!((*codec).type_ == f::AVMediaType::AVMEDIA_TYPE_VIDEO, "{} is not a video decoder", name);
+            Ok(Decoder::new(codec))
+        }
+    }
+
+    /// All video decoders compiled into the linked ffmpeg.
+    pub fn list() -> Vec<Self> {
+        let mut decoders = Vec::new();
+        let mut opaque: *mut c_void = ptr::null_mut();
+        unsafe {
+            loop {
+                let codec = f::av_codec_iterate(&mut opaque);
+                if codec.is_null() {
+                    break;
+                }
+                if f::av_codec_is_decoder(codec) != 0 && (*codec).type_ == f::AVMediaType::AVMEDIA_TYPE_VIDEO {
+                    decoders.push(Decoder::new(codec));
+                }
+            }
+        }
+        decoders
+    }
+
+    /// Converts the frames with `filter` instead of the fast bilinear default.
+    pub fn with_scale_filter(mut self, filter: ScaleFilter) -> Self {
+        self.scale_filter = filter;
+        self
+    }
+
+    /// Tone maps HDR video with `curve` instead of Hable.
+    pub fn with_tone_map(mut self, curve: ToneMap) -> Self {
+        self.tone_map = curve;
+        self
+    }
+
+    /// Decodes at most `fps` frames per second, the way [`FpsCap`] does, but drops the others before they are
+    /// converted to RGBA; `None` keeps every frame.
+    pub fn with_fps_cap(mut self, fps: Option<f64>) -> Self {
+        self.fps_cap = fps;
+        self
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { to_str!((*self.codec).name).into_owned() }
+    }
+
+    /// Whether the decoder keeps the alpha channel of webm inputs, see [`StreamInfo::webm_alpha`].
+    pub fn decodes_webm_alpha(&self) -> bool {
+        ALPHA_DECODERS.contains(&self.name().as_str())
+    }
+
+    pub fn description(&self) -> String {
+        unsafe {
+            let long_name = (*self.codec).long_name;
+            if long_name.is_null() {
+                String::new()
+            } else {
+                to_str!(long_name).into_owned()
+            }
+        }
+    }
 }
 
 impl WebmContext {
     pub fn new(url: &CStr) -> Result<Self> {
-        INIT.call_once(|| unsafe {
-            f::av_log_set_level(f::AV_LOG_WARNING);
-        });
+        unsafe { Self::open(url, ptr::null(), ptr::null_mut(), true, None) }
+    }
+
+    /// Only reads the container header, which is enough for the duration and frame size but not for decoding.
+    pub fn probe(url: &CStr) -> Result<Self> {
+        unsafe { Self::open(url, ptr::null(), ptr::null_mut(), false, None) }
+    }
 
+    /// Demuxes whatever `reader` returns, e.g. a file that is already open or an upload kept in memory.
+    pub fn from_reader(reader: impl Read + Seek + Send + 'static) -> Result<Self> {
+        let io = CustomIo::new(Box::new(reader))?;
+        unsafe { Self::open(&CString::default(), ptr::null(), ptr::null_mut(), true, Some(io)) }
+    }
+
+    /// Demuxes a whole file held in memory, such as a `Vec<u8>` or a `&'static [u8]`.
+    pub fn from_bytes(bytes: impl AsRef<[u8]> + Send + 'static) -> Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
+    /// Opens a capture device such as `x11grab` or `gdigrab`, passing `options` to the device.
+    pub fn open_device(format: &str, url: &CStr, options: &[(&str, String)]) -> Result<Self> {
         unsafe {
-            let mut fmt_ctx: *mut f::AVFormatContext = ptr::null_mut();
-            cvt(f::avformat_open_input(&mut fmt_ctx, url.as_ptr(), ptr::null_mut(), ptr::null_mut()))
-                    .wrap_err("failed to open input")?;
-            ensure!(!fmt_ctx.is_null(), "failed to read input");
+            f::avdevice_register_all();
+            let format_name = CString::new(format)?;
+            let input_format = f::av_find_input_format(format_name.as_ptr());
+            ensure!(!input_format.is_null(), "capture device {} is not supported by this build of ffmpeg", format);
 
+            let mut dict: *mut f::AVDictionary = ptr::null_mut();
+            for (key, value) in options {
+                let ret = f::av_dict_set(&mut dict, CString::new(*key)?.as_ptr(), CString::new(value.as_str())?.as_ptr(), 0);
+                if ret < 0 {
+                    f::av_dict_free(&mut dict);
+                    return Err(AVError::from(ret)).wrap_err("failed to set device option");
+                }
+            }
+            let result = Self::open(url, input_format, &mut dict, true, None);
+            f::av_dict_free(&mut dict);
+            result.wrap_err_with(|| format!("failed to open {format} device"))
+        }
+    }
+
+    unsafe fn open(url: &CStr, format: *const f::AVInputFormat, options: *mut *mut f::AVDictionary, find_stream_info: bool,
+            io: Option<CustomIo>) -> Result<Self> {
+        INIT.call_once(|| {
+            log::install();
+        });
+
+        let mut fmt_ctx: *mut f::AVFormatContext = ptr::null_mut();
+        if let Some(io) = &io {
+            fmt_ctx = f::avformat_alloc_context();
+            ensure!(!fmt_ctx.is_null(), "failed to allocate format context");
+            (*fmt_ctx).pb = io.ctx;
+            // keeps avformat_close_input from closing it
+            (*fmt_ctx).flags |= f::AVFMT_FLAG_CUSTOM_IO as i32;
+        }
+        // frees fmt_ctx on failure
+        cvt(f::avformat_open_input(&mut fmt_ctx, url.as_ptr(), format as _, options))
+                .wrap_err("failed to open input")?;
+        ensure!(!fmt_ctx.is_null(), "failed to read input");
+        let ctx = Self {
+            ptr: fmt_ctx,
+            _io: io,
+            _marker: PhantomData
+        };
+
+        if find_stream_info {
             cvt(f::avformat_find_stream_info(fmt_ctx, ptr::null_mut())).wrap_err("failed to find stream info")?;
-            Ok(Self {
-                ptr: fmt_ctx,
-                _marker: PhantomData
-            })
         }
+        Ok(ctx)
     }
 
+    /// Duration in `AV_TIME_BASE` units, or 0 when the container doesn't say, as with live captures.
     pub fn duration(&self) -> u64 {
-        unsafe { (*self.ptr).duration as u64 }
+        unsafe { (*self.ptr).duration.max(0) as u64 }
     }
 
     pub fn best_stream(&mut self) -> Result<WebmStream> {
@@ -107,28 +316,270 @@ impl<'ctx> WebmStream<'ctx> {
         }
     }
 
-    pub fn decode(&mut self, codec: VpxCodec) -> Result<WebmDecoder> {
+    /// Timestamp of the first frame in seconds, which the frame timestamps include and seeking counts from.
+    pub fn start_time(&self) -> f64 {
+        unsafe {
+            let stream = &*self.ptr;
+            match stream.start_time {
+                f::AV_NOPTS_VALUE => 0.0,
+                start => start as f64 * stream.time_base.num as f64 / stream.time_base.den as f64,
+            }
+        }
+    }
+
+    /// Number of frames the container declares, which webm and matroska leave out.
+    pub fn frame_count(&self) -> Option<u64> {
+        let frames = unsafe { (*self.ptr).nb_frames };
+        (frames > 0).then(|| frames as u64)
+    }
+
+    /// Whether frames come at a varying rate, so that the duration and frame rate don't tell how many there are.
+    pub fn is_variable_rate(&self) -> bool {
+        unsafe {
+            let (max, avg) = ((*self.ptr).r_frame_rate, (*self.ptr).avg_frame_rate);
+            if max.num <= 0 || max.den <= 0 || avg.num <= 0 || avg.den <= 0 {
+                return false;
+            }
+            let (max, avg) = (max.num as f64 / max.den as f64, avg.num as f64 / avg.den as f64);
+            (max - avg).abs() > max * 0.01
+        }
+    }
+
+    /// Counts the packets of the stream between `start` and `end` seconds after the first frame by reading through
+    /// the rest of the input, which is much faster than decoding it. This leaves the input at its end, so it is
+    /// meant for a context opened just for counting.
+    pub fn count_packets(&mut self, start: f64, end: Option<f64>) -> Result<u64> {
+        let start_time = self.start_time();
+        unsafe {
+            let packet = f::av_packet_alloc();
+            ensure!(!packet.is_null(), "failed to allocate packet");
+            let packet = scopeguard::guard(packet, |mut p| f::av_packet_free(&mut p));
+            let time_base = (*self.ptr).time_base;
+            let mut count = 0;
+            loop {
+                let ret = f::av_read_frame(self.ctx.ptr, *packet);
+                if ret == f::AVERROR_EOF {
+                    return Ok(count);
+                }
+                if ret < 0 {
+                    return Err(AVError::from(ret)).wrap_err("failed to read packet");
+                }
+                let _packet_unref = scopeguard::guard(*packet, |p| f::av_packet_unref(p));
+                if (**packet).stream_index != (*self.ptr).index || (**packet).pts == f::AV_NOPTS_VALUE {
+                    continue;
+                }
+                let pts = (**packet).pts as f64 * time_base.num as f64 / time_base.den as f64 - start_time;
+                if pts >= start && end.map_or(true, |end| pts < end) {
+                    count += 1;
+                }
+            }
+        }
+    }
+
+    /// Frame size as declared by the container.
+    pub fn size(&self) -> (u32, u32) {
+        unsafe {
+            let par = &*(*self.ptr).codecpar;
+            (par.width as _, par.height as _)
+        }
+    }
+
+    pub fn info(&self) -> StreamInfo {
         unsafe {
-            let (codec_name, display_name) = match codec {
-                VpxCodec::VP8 => (c_str!("libvpx-8"), "libvpx-vp8"),
-                VpxCodec::VP9 => (c_str!("libvpx-vp9"), "libvpx-vp9"),
+            let stream = &*self.ptr;
+            let par = &*stream.codecpar;
+            let name = |ptr: *const std::os::raw::c_char| if ptr.is_null() { None } else { Some(to_str!(ptr).into_owned()) };
+
+            let duration = if stream.duration != f::AV_NOPTS_VALUE && stream.duration > 0 {
+                let time_base = &stream.time_base;
+                Some(Duration::from_secs_f64(stream.duration as f64 * time_base.num as f64 / time_base.den as f64))
+            } else {
+                // unknown durations are reported as AV_NOPTS_VALUE
+                match (*self.ctx.ptr).duration {
+                    d if d > 0 => Some(Duration::from_micros(d as u64)),
+                    _ => None,
+                }
             };
-            let codec = f::avcodec_find_decoder_by_name(codec_name);
-            ensure!(!codec.is_null(), "decoder {} not found", display_name);
-            WebmDecoder::new(self.ctx, self.ptr, codec)
+
+            let (mut rotation, mut mirrored) = (0.0, false);
+            let mut size = 0;
+            let matrix = f::av_stream_get_side_data(stream, f::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX, &mut size);
+            if !matrix.is_null() && size >= 9 * mem::size_of::<i32>() as _ {
+                let matrix = std::slice::from_raw_parts(matrix as *const i32, 9);
+                // a reflection turns the determinant of the 2x2 part negative
+                mirrored = (matrix[0] as i64 * matrix[4] as i64) < (matrix[1] as i64 * matrix[3] as i64);
+                // counterclockwise in ffmpeg
+                let angle = -f::av_display_rotation_get(matrix.as_ptr());
+                if angle.is_finite() {
+                    rotation = angle.rem_euclid(360.0);
+                }
+            }
+
+            let pixel_format = (par.format >= 0)
+                .then(|| name(f::av_get_pix_fmt_name(mem::transmute::<_, f::AVPixelFormat>(par.format))))
+                .flatten();
+            StreamInfo {
+                width: par.width as _,
+                height: par.height as _,
+                pixel_format,
+                codec: to_str!(f::avcodec_get_name(par.codec_id)).into_owned(),
+                frame_rate: self.fps(),
+                duration,
+                rotation,
+                mirrored,
+                color_space: name(f::av_color_space_name(par.color_space)).unwrap_or_default(),
+                color_range: name(f::av_color_range_name(par.color_range)).unwrap_or_default(),
+                color_primaries: name(f::av_color_primaries_name(par.color_primaries)).unwrap_or_default(),
+                color_transfer: name(f::av_color_transfer_name(par.color_trc)).unwrap_or_default(),
+                webm_alpha: self.has_webm_alpha(),
+            }
+        }
+    }
+
+    /// The `alpha_mode` tag the matroska demuxer copies from the track header.
+    fn has_webm_alpha(&self) -> bool {
+        unsafe {
+            let entry = f::av_dict_get((*self.ptr).metadata, b"alpha_mode\0".as_ptr().cast(), ptr::null(), 0);
+            !entry.is_null() && CStr::from_ptr((*entry).value).to_bytes() == b"1"
+        }
+    }
+
+    /// Returns `forced` if given, otherwise the first decoder in `priority` that is compiled into ffmpeg and
+    /// handles the codec of the stream, or else the one ffmpeg picks for the codec, e.g. the native `vp9` decoder
+    /// when libvpx is missing. Webm inputs with an alpha channel try libvpx before `priority`.
+    pub fn find_decoder(&self, forced: Option<&str>, priority: &[String]) -> Result<Decoder> {
+        if let Some(name) = forced {
+            return Decoder::by_name(name);
+        }
+        let alpha = self.has_webm_alpha();
+        let alpha_decoders = ALPHA_DECODERS.iter().copied().filter(|_| alpha);
+        unsafe {
+            let codec_id = (*(*self.ptr).codecpar).codec_id;
+            for name in alpha_decoders.chain(priority.iter().map(String::as_str)) {
+                let codec = f::avcodec_find_decoder_by_name(CString::new(name)?.as_ptr());
+                if !codec.is_null() && (*codec).id == codec_id {
+                    return Ok(Decoder::new(codec));
+                }
+            }
+            let codec = f::avcodec_find_decoder(codec_id);
+            ensure!(!codec.is_null(), "no decoder found for {}", to_str!(f::avcodec_get_name(codec_id)));
+            Ok(Decoder::new(codec))
+        }
+    }
+
+    pub fn decode(&mut self, decoder: Decoder) -> Result<WebmDecoder> {
+        unsafe { WebmDecoder::new(self.ctx, self.ptr, decoder) }
+    }
+}
+
+/// Problems in the input that decoding got past, see [`WebmDecoder::stats`].
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DecodeStats {
+    /// Frames the decoder flagged as corrupt, which are skipped.
+    pub corrupt_frames: u64,
+    /// Frames the container marks as not meant to be shown, which are skipped.
+    pub discarded_frames: u64,
+    /// Frames shown even though the decoder had to conceal errors in them, e.g. missing references.
+    pub concealed_errors: u64,
+    /// Packets the decoder rejected as invalid data, which are skipped.
+    pub invalid_packets: u64,
+    /// Packets of other streams, such as audio, read past while demuxing.
+    pub other_stream_packets: u64,
+    /// Warnings and errors the decoder printed through av_log, e.g. about a damaged bitstream it recovered from.
+    pub warnings: u64,
+}
+
+impl DecodeStats {
+    /// Whether the input decoded without any damaged or rejected data.
+    pub fn is_clean(&self) -> bool {
+        self.corrupt_frames == 0 && self.concealed_errors == 0 && self.invalid_packets == 0 && self.warnings == 0
+    }
+}
+
+/// Lists the problems found, e.g. `2 corrupt frames skipped, 1 invalid packet skipped`.
+impl fmt::Display for DecodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = [
+            (self.corrupt_frames, "corrupt frame", "skipped"),
+            (self.concealed_errors, "frame", "with concealed errors"),
+            (self.invalid_packets, "invalid packet", "skipped"),
+            (self.discarded_frames, "discarded frame", "skipped"),
+            (self.warnings, "warning", "from the decoder"),
+        ];
+        let mut first = true;
+        for (count, noun, what) in counts.into_iter().filter(|(count, ..)| *count > 0) {
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+            write!(f, "{count} {noun}{} {what}", if count == 1 { "" } else { "s" })?;
         }
+        Ok(())
+    }
+}
+
+/// A scale context converting frames of one kind to RGBA.
+pub struct Scaler {
+    ctx: NonNull<f::SwsContext>,
+    source: ScalerSource,
+}
+
+/// Everything about the frames a scale context was made for.
+#[derive(Clone, Copy, PartialEq)]
+struct ScalerSource {
+    width: i32,
+    height: i32,
+    format: f::AVPixelFormat,
+    chroma_location: f::AVChromaLocation,
+    color_space: f::AVColorSpace,
+    color_range: f::AVColorRange,
+    filter: ScaleFilter,
+    /// Converts to 16 bit RGBA for tone mapping instead of 8 bit.
+    hdr: bool,
+}
+
+unsafe impl Send for Scaler {}
+
+impl Drop for Scaler {
+    fn drop(&mut self) {
+        unsafe { f::sws_freeContext(self.ctx.as_ptr()) }
     }
 }
 
 #[derive(Clone, Copy)]
-struct StreamInfo {
+struct FrameFormat {
     width: i32,
     height: i32,
-    format: f::AVPixelFormat
+}
+
+/// What the container and codec parameters say about a video stream, known without decoding it.
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub width: u32,
+    pub height: u32,
+    /// E.g. `yuv420p`; `None` when only decoding would tell.
+    pub pixel_format: Option<String>,
+    /// E.g. `vp9` or `av1`.
+    pub codec: String,
+    /// Frames per second as a fraction.
+    pub frame_rate: (u32, u32),
+    pub duration: Option<Duration>,
+    /// Clockwise rotation in degrees the frames should be displayed with, from 0 to 360.
+    pub rotation: f64,
+    /// Whether the frames should be flipped horizontally before they are rotated.
+    pub mirrored: bool,
+    pub color_space: String,
+    pub color_range: String,
+    pub color_primaries: String,
+    pub color_transfer: String,
+    /// Whether webm stores an alpha channel beside the VP8 or VP9 frames, which only libvpx decodes. Other codecs
+    /// carry alpha in their pixel format, e.g. `yuva420p`.
+    pub webm_alpha: bool,
 }
 
 impl<'ctx> WebmDecoder<'ctx> {
-    unsafe fn new(ctx: &'ctx mut WebmContext, stream: *mut f::AVStream, codec: *const f::AVCodec) -> Result<Self> {
+    unsafe fn new(ctx: &'ctx mut WebmContext, stream: *mut f::AVStream, decoder: Decoder) -> Result<Self> {
+        let codec = decoder.codec;
         let dec_ctx = f::avcodec_alloc_context3(codec);
         ensure!(!dec_ctx.is_null(), "failed to allocate codec context for {}", to_str!((*codec).name));
 
@@ -144,22 +595,110 @@ impl<'ctx> WebmDecoder<'ctx> {
         let frame = f::av_frame_alloc();
         ensure!(!frame.is_null(), "failed to allocate frame");
 
+        log::count_warnings(dec_ctx as *const c_void);
         Ok(Self {
             ctx,
             stream,
             dec_ctx,
-            sws_ctx: None,
+            scaler: None,
+            scale_filter: decoder.scale_filter,
+            tone_map: decoder.tone_map,
+            fps_cap: decoder.fps_cap.map(FpsCap::new),
+            tone_mapper: None,
+            hdr_buf: Vec::new(),
             packet,
             frame,
-            info: None,
+            first_frame: None,
+            failed: false,
+            skip_until: None,
+            stats: DecodeStats::default(),
             _marker1: PhantomData,
             _marker2: PhantomData,
             _marker3: PhantomData
         })
     }
 
+    /// Seeks so that the next decoded frame is the one shown at `to`, measured from the start of the stream.
+    ///
+    /// Decoding has to restart from the keyframe before `to`; the frames in between are decoded but not converted
+    /// to RGBA or returned.
+    pub fn seek(&mut self, to: Duration) -> Result<()> {
+        self.seek_keyframe(to)?;
+        self.skip_until = Some(self.timestamp(to));
+        if let Some(cap) = &mut self.fps_cap {
+            cap.restart();
+        }
+        Ok(())
+    }
+
+    /// Seeks to the nearest keyframe at or before `to`, which is faster than [`seek`](Self::seek) when any frame
+    /// close to `to` will do.
+    pub fn seek_keyframe(&mut self, to: Duration) -> Result<()> {
+        let ts = self.timestamp(to);
+        unsafe {
+            let ret = f::av_seek_frame(self.ctx.ptr, (*self.stream).index, ts, f::AVSEEK_FLAG_BACKWARD);
+            if ret < 0 {
+                return Err(AVError::from(ret)).wrap_err_with(|| eyre!("failed to seek to {:.3}s", to.as_secs_f64()));
+            }
+            f::avcodec_flush_buffers(self.dec_ctx);
+            self.skip_until = None;
+            Ok(())
+        }
+    }
+
+    /// `to` in the time base of the stream.
+    fn timestamp(&self, to: Duration) -> i64 {
+        unsafe {
+            let stream = &*self.stream;
+            let time_base = &stream.time_base;
+            let ts = (to.as_secs_f64() * time_base.den as f64 / time_base.num as f64) as i64;
+            if stream.start_time != f::AV_NOPTS_VALUE {
+                ts + stream.start_time
+            } else {
+                ts
+            }
+        }
+    }
+
+    /// Problems in the input decoding got past so far.
+    pub fn stats(&self) -> DecodeStats {
+        DecodeStats { warnings: log::warnings(self.dec_ctx as *const c_void), ..self.stats }
+    }
+
+    /// Hands over the scale context of another decoder, which is used if the frames turn out to have the same
+    /// size and format.
+    pub fn set_scaler(&mut self, scaler: Option<Scaler>) {
+        if scaler.is_some() {
+            self.scaler = scaler;
+        }
+    }
+
+    /// Takes the scale context for the next decoder, see [`set_scaler`](Self::set_scaler).
+    pub fn take_scaler(&mut self) -> Option<Scaler> {
+        self.scaler.take()
+    }
+
+    /// Decodes the next frame with its timestamp in seconds, or returns `None` at the end of the stream.
+    pub fn next_frame(&mut self) -> Result<Option<(ImgVec<RGBA8>, f64)>> {
+        let Some(pts) = self.receive_frame()? else { return Ok(None) };
+        unsafe {
+            let image = self.convert_frame();
+            f::av_frame_unref(self.frame);
+            Ok(Some((image?, pts)))
+        }
+    }
+
+    /// Decodes the next frame like [`next_frame`](Self::next_frame) but leaves it in the pixel format of the
+    /// stream, usually YUV, for callers doing their own conversion.
+    pub fn next_raw_frame(&mut self) -> Result<Option<RawFrame<'_>>> {
+        let Some(pts) = self.receive_frame()? else { return Ok(None) };
+        Ok(Some(RawFrame { frame: unsafe { &*self.frame }, pts }))
+    }
+
+    /// Reads packets until the decoder outputs a frame, which stays in `self.frame` until the next call; returns
+    /// its timestamp in seconds.
     #[allow(unused_labels)]
-    pub fn decode_frame(&mut self) -> Result<Option<(ImgVec<RGBA8>, f64)>> {
+    fn receive_frame(&mut self) -> Result<Option<f64>> {
         unsafe {
             'read: loop {
                 let ret = f::av_read_frame(self.ctx.ptr, self.packet);
@@ -172,10 +711,16 @@ impl<'ctx> WebmDecoder<'ctx> {
                 let _packet_unref = scopeguard::guard(self.packet, |p| f::av_packet_unref(p));
 
                 if (*self.packet).stream_index != (*self.stream).index {
+                    self.stats.other_stream_packets += 1;
                     continue;
                 }
 
-                cvt(f::avcodec_send_packet(self.dec_ctx, self.packet)).wrap_err("failed to submit packet for decoding")?;
+                let ret = f::avcodec_send_packet(self.dec_ctx, self.packet);
+                if ret == f::AVERROR_INVALIDDATA {
+                    self.stats.invalid_packets += 1;
+                    continue;
+                }
+                cvt(ret).wrap_err("failed to submit packet for decoding")?;
 
                 'decode: loop {
                     let ret = f::avcodec_receive_frame(self.dec_ctx, self.frame);
@@ -185,20 +730,39 @@ impl<'ctx> WebmDecoder<'ctx> {
                         }
                         return Err(AVError::from(ret)).wrap_err("failed to decode frame");
                     }
-                    let _frame_unref = scopeguard::guard(self.frame, |p| f::av_frame_unref(p));
+                    let frame_unref = scopeguard::guard(self.frame, |p| f::av_frame_unref(p));
                     let frame = &*self.frame;
 
-                    ensure!(frame.flags & f::AV_FRAME_FLAG_CORRUPT == 0, "failed to decode frame (corrupted)");
+                    if frame.flags & f::AV_FRAME_FLAG_CORRUPT != 0 {
+                        self.stats.corrupt_frames += 1;
+                        continue;
+                    }
                     if frame.flags & f::AV_FRAME_FLAG_DISCARD != 0 {
+                        self.stats.discarded_frames += 1;
                         continue;
                     }
+                    if frame.decode_error_flags != 0 {
+                        self.stats.concealed_errors += 1;
+                    }
 
                     ensure!(frame.pts >= 0, "negative pts");
+                    // the frame at the seek target is the last one starting at or before it
+                    if let Some(target) = self.skip_until {
+                        let duration = frame.pkt_duration.max(1);
+                        if frame.pts + duration <= target {
+                            continue;
+                        }
+                        self.skip_until = None;
+                    }
                     let pts = frame.pts as u64;
                     let time_base = &(*self.stream).time_base;
                     let pts = (pts * time_base.num as u64) as f64 / time_base.den as f64;
+                    if !self.fps_cap.as_mut().map_or(true, |cap| cap.keep(pts)) {
+                        continue;
+                    }
 
-                    return Ok(Some((self.convert_frame()?, pts)));
+                    scopeguard::ScopeGuard::into_inner(frame_unref);
+                    return Ok(Some(pts));
                 }
             }
         }
@@ -210,42 +774,79 @@ impl<'ctx> WebmDecoder<'ctx> {
         let height = frame.height;
         let format = mem::transmute::<_, f::AVPixelFormat>(frame.format);
 
-        match self.info.as_ref() {
-            Some(info) => {
-                ensure!(info.width == width && info.height == height, "inconsistent width and height");
-                ensure!(info.format == format, "inconsistent pixel format");
+        match self.first_frame.as_ref() {
+            Some(first) => {
+                // the pixel format may change, e.g. libvpx switches to yuva420p at the first frame that has alpha, and
+                // the scaler follows it
+                ensure!(first.width == width && first.height == height, "inconsistent width and height");
             },
             None => {
-                self.info = Some(StreamInfo { width, height, format });
+                self.first_frame = Some(FrameFormat { width, height });
             },
         };
 
-        let sws_ctx = match self.sws_ctx {
-            Some(ctx) => ctx,
-            None => {
-                let ctx = f::sws_getContext(width, height, format, width, height,
-                    f::AVPixelFormat::AV_PIX_FMT_RGBA, f::SWS_FAST_BILINEAR, ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
-                ensure!(!ctx.is_null(), "failed to create scale context for the conversion {width}x{height} {:?} to {:?}",
-                    to_str!(f::av_get_pix_fmt_name(format)),
-                    to_str!(f::av_get_pix_fmt_name(f::AVPixelFormat::AV_PIX_FMT_RGBA)));
-                let ctx = NonNull::new_unchecked(ctx);
-                self.sws_ctx = Some(ctx);
+        let transfer = match self.tone_map {
+            ToneMap::Off => None,
+            _ => Transfer::of(frame.color_trc),
+        };
+        let source = ScalerSource {
+            width,
+            height,
+            format,
+            chroma_location: frame.chroma_location,
+            color_space: frame.colorspace,
+            color_range: frame.color_range,
+            filter: self.scale_filter,
+            hdr: transfer.is_some(),
+        };
+        let sws_ctx = match &self.scaler {
+            Some(scaler) if scaler.source == source => scaler.ctx,
+            _ => {
+                let ctx = new_scale_context(&source)
+                    .wrap_err_with(|| eyre!("failed to create scale context for the conversion {width}x{height} {:?} to {:?}",
+                        to_str!(f::av_get_pix_fmt_name(format)),
+                        to_str!(f::av_get_pix_fmt_name(source.dst_format()))))?;
+                self.scaler = Some(Scaler { ctx, source });
                 ctx
             },
         }.as_mut();
 
-        let mut rgba = Vec::<RGBA8>::with_capacity(width as usize * height as usize);
-        let ret = f::sws_scale(
-            sws_ctx,
-            frame.data.as_ptr() as _,
-            frame.linesize.as_ptr(),
-            0,
-            height,
-            [rgba.as_bytes_mut().as_mut_ptr()].as_ptr(),
-            [frame.width * 4].as_ptr(),
-        );
-        ensure!(ret > 0, "failed to convert pixel format to RGBA");
-        rgba.set_len(rgba.capacity());
+        let len = width as usize * height as usize;
+        let mut rgba = Vec::<RGBA8>::with_capacity(len);
+        match transfer {
+            Some(transfer) => {
+                self.hdr_buf.resize(len * 4, 0);
+                let ret = f::sws_scale(
+                    sws_ctx,
+                    frame.data.as_ptr() as _,
+                    frame.linesize.as_ptr(),
+                    0,
+                    height,
+                    [self.hdr_buf.as_mut_ptr() as *mut u8].as_ptr(),
+                    [frame.width * 8].as_ptr(),
+                );
+                ensure!(ret > 0, "failed to convert pixel format to RGBA64");
+                let mapper = match self.tone_mapper.take() {
+                    Some(mapper) if mapper.is_for(transfer, self.tone_map) => mapper,
+                    _ => ToneMapper::new(transfer, self.tone_map),
+                };
+                mapper.map(&self.hdr_buf, &mut rgba);
+                self.tone_mapper = Some(mapper);
+            },
+            None => {
+                let ret = f::sws_scale(
+                    sws_ctx,
+                    frame.data.as_ptr() as _,
+                    frame.linesize.as_ptr(),
+                    0,
+                    height,
+                    [rgba.as_bytes_mut().as_mut_ptr()].as_ptr(),
+                    [frame.width * 4].as_ptr(),
+                );
+                ensure!(ret > 0, "failed to convert pixel format to RGBA");
+                rgba.set_len(len);
+            },
+        }
 
         #[cfg(feature = "debug_dump")]
         {
@@ -261,6 +862,206 @@ impl<'ctx> WebmDecoder<'ctx> {
     }
 }
 
+/// Yields the decoded frames with their timestamps in seconds; stops after the first error.
+impl<'ctx> Iterator for WebmDecoder<'ctx> {
+    type Item = Result<(ImgVec<RGBA8>, f64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.failed = matches!(frame, Some(Err(_)));
+        frame
+    }
+}
+
+/// A decoded frame in the pixel format of the stream, borrowed from the decoder until it decodes the next one.
+pub struct RawFrame<'dec> {
+    frame: &'dec f::AVFrame,
+    pts: f64,
+}
+
+/// One plane of a [`RawFrame`], e.g. the Y, U or V samples.
+pub struct Plane<'dec> {
+    /// `height` rows of `stride` bytes, of which the first `width` bytes are samples.
+    pub data: &'dec [u8],
+    pub stride: usize,
+    /// Row length in bytes.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl<'dec> RawFrame<'dec> {
+    pub fn width(&self) -> usize {
+        self.frame.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.frame.height as usize
+    }
+
+    /// Timestamp in seconds.
+    pub fn pts(&self) -> f64 {
+        self.pts
+    }
+
+    pub fn pixel_format(&self) -> f::AVPixelFormat {
+        unsafe { mem::transmute::<_, f::AVPixelFormat>(self.frame.format) }
+    }
+
+    /// Name of the pixel format as ffmpeg spells it, e.g. `yuv420p`.
+    pub fn pixel_format_name(&self) -> String {
+        unsafe {
+            let name = f::av_get_pix_fmt_name(self.pixel_format());
+            if name.is_null() { String::new() } else { to_str!(name).into_owned() }
+        }
+    }
+
+    pub fn color_space(&self) -> f::AVColorSpace {
+        self.frame.colorspace
+    }
+
+    pub fn color_range(&self) -> f::AVColorRange {
+        self.frame.color_range
+    }
+
+    pub fn chroma_location(&self) -> f::AVChromaLocation {
+        self.frame.chroma_location
+    }
+
+    /// The planes of the frame in the order of the pixel format, e.g. Y, U, V for `yuv420p`.
+    pub fn planes(&self) -> Result<Vec<Plane<'dec>>> {
+        unsafe {
+            let format = self.pixel_format();
+            let desc = f::av_pix_fmt_desc_get(format);
+            ensure!(!desc.is_null(), "unknown pixel format");
+            ensure!((*desc).flags & f::AV_PIX_FMT_FLAG_HWACCEL as u64 == 0, "frames in GPU memory have no planes to read");
+
+            let mut widths = [0; 4];
+            let ret = f::av_image_fill_linesizes(widths.as_mut_ptr(), format, self.frame.width);
+            if ret < 0 {
+                return Err(AVError::from(ret)).wrap_err("failed to compute the plane sizes");
+            }
+            let chroma_height = -((-self.frame.height) >> (*desc).log2_chroma_h);
+            let mut planes = Vec::new();
+            for (i, &width) in widths.iter().enumerate() {
+                let data = self.frame.data[i];
+                if data.is_null() || width == 0 {
+                    break;
+                }
+                let stride = self.frame.linesize[i];
+                ensure!(stride >= width, "frames stored bottom-up are not supported");
+                // planes 1 and 2 hold the subsampled chroma of YUV formats
+                let is_chroma = (i == 1 || i == 2) && (*desc).flags & f::AV_PIX_FMT_FLAG_RGB as u64 == 0;
+                let height = if is_chroma { chroma_height } else { self.frame.height } as usize;
+                planes.push(Plane {
+                    data: std::slice::from_raw_parts(data, stride as usize * height),
+                    stride: stride as usize,
+                    width: width as usize,
+                    height,
+                });
+            }
+            Ok(planes)
+        }
+    }
+}
+
+impl ScalerSource {
+    fn dst_format(&self) -> f::AVPixelFormat {
+        match (self.hdr, cfg!(target_endian = "little")) {
+            (false, _) => f::AVPixelFormat::AV_PIX_FMT_RGBA,
+            (true, true) => f::AVPixelFormat::AV_PIX_FMT_RGBA64LE,
+            (true, false) => f::AVPixelFormat::AV_PIX_FMT_RGBA64BE,
+        }
+    }
+}
+
+/// Creates a context converting frames of `source` to full range RGBA at the same size, or to 16 bit RGBA for
+/// tone mapping.
+///
+/// Subsampled chroma is interpolated at the chroma location when the stream declares one; otherwise swscale's
+/// default placement is kept. YUV is converted with the matrix and range of the frames, see
+/// [`yuv_coefficients`]. Formats with more than 8 bits per sample are dithered down.
+unsafe fn new_scale_context(source: &ScalerSource) -> Result<NonNull<f::SwsContext>> {
+    let ScalerSource { width, height, format, chroma_location, filter, .. } = *source;
+    let ctx = NonNull::new(f::sws_alloc_context()).ok_or_else(|| eyre!("failed to allocate scale context"))?;
+    let ctx = scopeguard::guard(ctx, |ctx| f::sws_freeContext(ctx.as_ptr()));
+    let desc = f::av_pix_fmt_desc_get(format);
+    ensure!(!desc.is_null(), "unknown pixel format");
+
+    let mut flags = filter.flags();
+    let mut options = vec![
+        ("srcw", width as i64),
+        ("srch", height as i64),
+        ("src_format", format as i64),
+        ("dstw", width as i64),
+        ("dsth", height as i64),
+        ("dst_format", source.dst_format() as i64),
+    ];
+    if chroma_location != f::AVChromaLocation::AVCHROMA_LOC_UNSPECIFIED {
+        // both in 1/256 of a luma sample
+        let (mut x, mut y) = (0, 0);
+        if f::avcodec_enum_to_chroma_pos(&mut x, &mut y, chroma_location) == 0 {
+            // without full chroma interpolation swscale just repeats each chroma sample
+            flags |= f::SWS_FULL_CHR_H_INT as i64 | f::SWS_ACCURATE_RND as i64;
+            options.push(("src_h_chr_pos", x as i64));
+            options.push(("src_v_chr_pos", y as i64));
+        }
+    }
+    if (*desc).comp[0].depth > 8 && !source.hdr {
+        // truncating 10 and 12 bit video to 8 bits bands smooth gradients;
+        // swscale only diffuses the error when it interpolates the chroma fully
+        flags |= f::SWS_FULL_CHR_H_INT as i64 | f::SWS_ACCURATE_RND as i64;
+        options.push(("sws_dither", SWS_DITHER_ED));
+    }
+    options.push(("sws_flags", flags));
+
+    for (name, value) in options {
+        let ret = f::av_opt_set_int(ctx.as_ptr() as *mut c_void, CString::new(name)?.as_ptr(), value, 0);
+        if ret < 0 {
+            return Err(AVError::from(ret)).wrap_err_with(|| eyre!("failed to set {name}"));
+        }
+    }
+    let ret = f::sws_init_context(ctx.as_ptr(), ptr::null_mut(), ptr::null_mut());
+    if ret < 0 {
+        return Err(AVError::from(ret)).wrap_err("failed to initialize scale context");
+    }
+
+    if (*desc).flags & f::AV_PIX_FMT_FLAG_RGB as u64 == 0 {
+        let (color_space, full_range) = yuv_coefficients(source);
+        // RGBA is always full range; the table for the output only matters for YUV
+        let ret = f::sws_setColorspaceDetails(ctx.as_ptr(), f::sws_getCoefficients(color_space), full_range as i32,
+            f::sws_getCoefficients(color_space), 1, 0, 1 << 16, 1 << 16);
+        if ret < 0 {
+            return Err(AVError::from(ret)).wrap_err("failed to set the color space of the scale context");
+        }
+    }
+    Ok(scopeguard::ScopeGuard::into_inner(ctx))
+}
+
+/// The `SWS_CS_*` matrix and whether the samples use the full range, for YUV frames of `source`.
+///
+/// Streams that don't say are taken to be BT.709 when they are larger than SD and BT.601 otherwise, in limited
+/// range, as players assume.
+fn yuv_coefficients(source: &ScalerSource) -> (i32, bool) {
+    use f::AVColorSpace::*;
+    let color_space = match source.color_space {
+        AVCOL_SPC_BT709 => f::SWS_CS_ITU709,
+        AVCOL_SPC_FCC => f::SWS_CS_FCC,
+        AVCOL_SPC_BT470BG | AVCOL_SPC_SMPTE170M => f::SWS_CS_ITU601,
+        AVCOL_SPC_SMPTE240M => f::SWS_CS_SMPTE240M,
+        AVCOL_SPC_BT2020_NCL | AVCOL_SPC_BT2020_CL => f::SWS_CS_BT2020,
+        _ if source.width > 1024 || source.height > 576 => f::SWS_CS_ITU709,
+        _ => f::SWS_CS_ITU601,
+    };
+    let full_range = source.color_range == f::AVColorRange::AVCOL_RANGE_JPEG
+        // JPEG-style formats are full range whatever the stream says
+        || matches!(source.format, f::AVPixelFormat::AV_PIX_FMT_YUVJ420P | f::AVPixelFormat::AV_PIX_FMT_YUVJ422P
+            | f::AVPixelFormat::AV_PIX_FMT_YUVJ444P);
+    (color_space as i32, full_range)
+}
+
 impl Drop for WebmContext {
     fn drop(&mut self) {
         unsafe {
@@ -271,13 +1072,11 @@ impl Drop for WebmContext {
 
 impl<'ctx> Drop for WebmDecoder<'ctx> {
     fn drop(&mut self) {
+        log::stop_counting(self.dec_ctx as *const c_void);
         unsafe {
             f::avcodec_free_context(&mut self.dec_ctx);
             f::av_packet_free(&mut self.packet);
             f::av_frame_free(&mut self.frame);
-            if let Some(sws_ctx) = self.sws_ctx.take() {
-                f::sws_freeContext(sws_ctx.as_ptr());
-            }
         }
     }
 }
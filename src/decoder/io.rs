@@ -0,0 +1,85 @@
+use std::ffi::c_void;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::raw::c_int;
+use std::slice;
+
+use color_eyre::Result;
+use color_eyre::eyre::ensure;
+use ffmpeg_sys_next as f;
+
+/// Size of the buffer ffmpeg reads into before demuxing.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// An `AVIOContext` reading from a Rust reader instead of a file or URL.
+pub struct CustomIo {
+    pub ctx: *mut f::AVIOContext,
+    /// Boxed twice so that ffmpeg gets a thin pointer as its opaque value.
+    reader: *mut Box<dyn ReadSeek>,
+}
+
+impl CustomIo {
+    pub fn new(reader: Box<dyn ReadSeek>) -> Result<Self> {
+        unsafe {
+            let buffer = f::av_malloc(BUFFER_SIZE) as *mut u8;
+            ensure!(!buffer.is_null(), "failed to allocate the read buffer");
+            let reader = Box::into_raw(Box::new(reader));
+            let ctx = f::avio_alloc_context(buffer, BUFFER_SIZE as c_int, 0, reader as *mut c_void,
+                Some(read_packet), None, Some(seek));
+            if ctx.is_null() {
+                f::av_free(buffer as *mut c_void);
+                drop(Box::from_raw(reader));
+            }
+            ensure!(!ctx.is_null(), "failed to allocate the I/O context");
+            Ok(Self { ctx, reader })
+        }
+    }
+}
+
+impl Drop for CustomIo {
+    fn drop(&mut self) {
+        unsafe {
+            // ffmpeg may have replaced the buffer it was given
+            f::av_freep(&mut (*self.ctx).buffer as *mut *mut u8 as *mut c_void);
+            f::avio_context_free(&mut self.ctx);
+            drop(Box::from_raw(self.reader));
+        }
+    }
+}
+
+unsafe extern "C" fn read_packet(opaque: *mut c_void, buf: *mut u8, size: c_int) -> c_int {
+    let reader = &mut *(opaque as *mut Box<dyn ReadSeek>);
+    let buf = slice::from_raw_parts_mut(buf, size.max(0) as usize);
+    loop {
+        match reader.read(buf) {
+            Ok(0) => return f::AVERROR_EOF,
+            Ok(n) => return n as c_int,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(_) => return f::AVERROR(f::EIO),
+        }
+    }
+}
+
+unsafe extern "C" fn seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let reader = &mut *(opaque as *mut Box<dyn ReadSeek>);
+    let result = if whence & f::AVSEEK_SIZE as c_int != 0 {
+        // asks for the total size without moving
+        reader.stream_position().and_then(|position| {
+            let size = reader.seek(SeekFrom::End(0))?;
+            reader.seek(SeekFrom::Start(position))?;
+            Ok(size)
+        })
+    } else {
+        let to = match whence & !(f::AVSEEK_FORCE as c_int) {
+            libc::SEEK_SET => SeekFrom::Start(offset as u64),
+            libc::SEEK_CUR => SeekFrom::Current(offset),
+            libc::SEEK_END => SeekFrom::End(offset),
+            _ => return f::AVERROR(f::EINVAL) as i64,
+        };
+        reader.seek(to)
+    };
+    result.map_or(f::AVERROR(f::EIO) as i64, |position| position as i64)
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr};
+use std::lazy::SyncOnceCell;
+use std::os::raw::{c_char, c_int};
+use std::sync::{Mutex, MutexGuard};
+
+use ffmpeg_sys_next as f;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, trace, warn, Level};
+
+/// Longest message formatted, longer ones are cut short.
+const LINE_SIZE: usize = 1024;
+
+/// Warnings and errors reported so far about each context passed to `count_warnings`, by its address. Decoders such
+/// as libdav1d report from threads of their own, which rules out a thread local.
+static WARNINGS: SyncOnceCell<Mutex<HashMap<usize, u64>>> = SyncOnceCell::new();
+
+/// What the ffmpeg headers call `va_list`, as bindgen passes it to a callback on this platform.
+#[cfg(all(target_arch = "x86_64", not(target_os = "windows")))]
+type VaList = *mut f::__va_list_tag;
+#[cfg(not(all(target_arch = "x86_64", not(target_os = "windows"))))]
+type VaList = f::va_list;
+
+/// Sends the messages of ffmpeg through tracing. Its errors become warnings, since the ones that matter also come
+/// back from the call that failed; its info and verbose messages are debug, the rest trace.
+pub(super) unsafe fn install() {
+    f::av_log_set_level(f::AV_LOG_DEBUG);
+    f::av_log_set_callback(Some(callback));
+}
+
+/// Starts counting the warnings and errors ffmpeg reports about `avcl`, e.g. a codec context.
+pub(super) fn count_warnings(avcl: *const c_void) {
+    warnings_lock().insert(avcl as usize, 0);
+}
+
+/// Warnings and errors reported about `avcl` since `count_warnings`.
+pub(super) fn warnings(avcl: *const c_void) -> u64 {
+    warnings_lock().get(&(avcl as usize)).copied().unwrap_or(0)
+}
+
+/// Stops counting for `avcl`, which is about to be freed; the address may be reused by the next context.
+pub(super) fn stop_counting(avcl: *const c_void) {
+    warnings_lock().remove(&(avcl as usize));
+}
+
+fn warnings_lock() -> MutexGuard<'static, HashMap<usize, u64>> {
+    WARNINGS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+unsafe extern "C" fn callback(avcl: *mut c_void, level: c_int, fmt: *const c_char, vl: VaList) {
+    if level <= f::AV_LOG_WARNING {
+        // counted even when not printed, for the summary
+        if let Some(count) = warnings_lock().get_mut(&(avcl as usize)) {
+            *count += 1;
+        }
+    }
+    let mapped = match level {
+        _ if level <= f::AV_LOG_WARNING => Level::WARN,
+        _ if level <= f::AV_LOG_VERBOSE => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    // formatting every debug message would slow decoding down for nothing
+    if mapped > LevelFilter::current() {
+        return;
+    }
+    let mut line = [0 as c_char; LINE_SIZE];
+    // the prefix names the component, e.g. [libdav1d @ 0x...]
+    let mut print_prefix = 1;
+    f::av_log_format_line2(avcl, level, fmt, vl, line.as_mut_ptr(), LINE_SIZE as c_int, &mut print_prefix);
+    let line = CStr::from_ptr(line.as_ptr()).to_string_lossy();
+    let line = line.trim_end();
+    if line.is_empty() {
+        return;
+    }
+    match mapped {
+        Level::WARN => warn!(target: "ffmpeg", "{line}"),
+        Level::DEBUG => debug!(target: "ffmpeg", "{line}"),
+        _ => trace!(target: "ffmpeg", "{line}"),
+    }
+}
@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use ffmpeg_sys_next as f;
+use rgb::RGBA8;
+
+/// Brightness of white in SDR video shown in HDR, in nits, see ITU-R BT.2408.
+const SDR_WHITE: f32 = 203.0;
+/// Brightest highlight kept apart from white. HDR video is mostly mastered for displays this bright, in nits.
+const PEAK: f32 = 1000.0;
+/// Entries in the table encoding linear light to sRGB, which is finer than 8 bits in the shadows.
+const SRGB_STEPS: usize = 4096;
+
+/// Linear BT.2020 to linear BT.709, both with a D65 white point.
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+/// How the highlights of HDR video are brought into the range of a gif.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToneMap {
+    /// The filmic curve from Uncharted 2, which rolls off highlights gently and keeps the midtones contrasty.
+    Hable,
+    /// Extended Reinhard, brighter and flatter than Hable.
+    Reinhard,
+    /// Converts HDR video as if it were SDR, which looks grey and washed out.
+    Off,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        ToneMap::Hable
+    }
+}
+
+/// Parses the names listed in `--tonemap`.
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hable" => Ok(ToneMap::Hable),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "none" => Ok(ToneMap::Off),
+            _ => Err(format!("expected hable, reinhard or none, got {s:?}")),
+        }
+    }
+}
+
+impl ToneMap {
+    /// Maps light relative to SDR white, up to `PEAK`, to the range of SDR.
+    fn apply(self, x: f32) -> f32 {
+        let peak = PEAK / SDR_WHITE;
+        match self {
+            ToneMap::Hable => hable(x) / hable(peak),
+            ToneMap::Reinhard => x * (1.0 + x / (peak * peak)) / (1.0 + x),
+            ToneMap::Off => x,
+        }
+    }
+}
+
+fn hable(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+/// The HDR transfer functions, which SDR players show grey and flat.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Transfer {
+    /// SMPTE ST 2084, absolute brightness up to 10000 nits.
+    Pq,
+    /// ARIB STD-B67, relative to the brightness of the display.
+    Hlg,
+}
+
+impl Transfer {
+    pub(super) fn of(transfer: f::AVColorTransferCharacteristic) -> Option<Self> {
+        match transfer {
+            f::AVColorTransferCharacteristic::AVCOL_TRC_SMPTE2084 => Some(Transfer::Pq),
+            f::AVColorTransferCharacteristic::AVCOL_TRC_ARIB_STD_B67 => Some(Transfer::Hlg),
+            _ => None,
+        }
+    }
+
+    /// Decodes a sample from 0 to 1: display light relative to SDR white for PQ, scene light from 0 to 1 for HLG.
+    fn to_linear(self, e: f32) -> f32 {
+        match self {
+            Transfer::Pq => {
+                let (m1, m2) = (0.159_301_76, 78.84375);
+                let (c1, c2, c3) = (0.8359375, 18.851_563, 18.6875);
+                let p = e.powf(1.0 / m2);
+                ((p - c1).max(0.0) / (c2 - c3 * p)).powf(1.0 / m1) * 10000.0 / SDR_WHITE
+            },
+            Transfer::Hlg => {
+                let (a, b, c) = (0.178_832_77, 0.284_668_92, 0.559_910_7);
+                if e <= 0.5 { e * e / 3.0 } else { (((e - c) / a).exp() + b) / 12.0 }
+            },
+        }
+    }
+}
+
+/// Converts frames of one HDR transfer function to 8 bit sRGB, with tables built once for all of them.
+pub(super) struct ToneMapper {
+    transfer: Transfer,
+    curve: ToneMap,
+    /// `Transfer::to_linear` of every 16 bit sample.
+    linear: Vec<f32>,
+    /// sRGB encoding of linear light from 0 to 1.
+    srgb: Vec<u8>,
+}
+
+impl ToneMapper {
+    pub(super) fn new(transfer: Transfer, curve: ToneMap) -> Self {
+        let linear = (0..=u16::MAX).map(|v| transfer.to_linear(v as f32 / u16::MAX as f32)).collect();
+        let srgb = (0..SRGB_STEPS)
+            .map(|i| {
+                let l = i as f32 / (SRGB_STEPS - 1) as f32;
+                let v = if l <= 0.003_130_8 { 12.92 * l } else { 1.055 * l.powf(1.0 / 2.4) - 0.055 };
+                (v * 255.0).round() as u8
+            })
+            .collect();
+        Self { transfer, curve, linear, srgb }
+    }
+
+    pub(super) fn is_for(&self, transfer: Transfer, curve: ToneMap) -> bool {
+        self.transfer == transfer && self.curve == curve
+    }
+
+    /// Appends the pixels of `src`, 16 bit RGBA with BT.2020 primaries, to `dst` as sRGB.
+    pub(super) fn map(&self, src: &[u16], dst: &mut Vec<RGBA8>) {
+        dst.extend(src.chunks_exact(4).map(|px| {
+            let mut rgb = [self.linear[px[0] as usize], self.linear[px[1] as usize], self.linear[px[2] as usize]];
+            if self.transfer == Transfer::Hlg {
+                // the OOTF of BT.2100 for a display of `PEAK` nits, which brightens highlights more than shadows
+                let y = 0.2627 * rgb[0] + 0.6780 * rgb[1] + 0.0593 * rgb[2];
+                let gain = y.powf(0.2) * PEAK / SDR_WHITE;
+                rgb = rgb.map(|v| v * gain);
+            }
+            let rgb = BT2020_TO_BT709.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]);
+            let [r, g, b] = rgb.map(|v| {
+                let v = self.curve.apply(v.max(0.0)).clamp(0.0, 1.0);
+                self.srgb[(v * (SRGB_STEPS - 1) as f32).round() as usize]
+            });
+            RGBA8::new(r, g, b, (px[3] >> 8) as u8)
+        }));
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{bail, eyre, Context};
+use gifski::progress::ProgressReporter;
+use gifski::Repeat;
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::settings::EncodeSettings;
+use crate::throttle::Throttled;
+
+mod ffmpeg;
+mod avio;
+mod stills;
+pub use ffmpeg::VideoEncoder;
+use stills::{FramesWriter, SpritesheetWriter};
+
+/// Default of `EncodeSettings::queue_length`.
+pub const QUEUE_LENGTH: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Gif,
+    /// Animated PNG.
+    Apng,
+    WebP,
+    Avif,
+    /// A directory with one PNG per frame.
+    Frames,
+    /// All frames side by side in a single PNG.
+    Spritesheet,
+}
+
+impl Format {
+    /// Picks the format from the extension of `path`, e.g. `clip.webp`.
+    pub fn from_path(path: &Utf8Path) -> Result<Self> {
+        match path.extension().map(|ext| ext.to_ascii_lowercase()).as_deref() {
+            Some("gif") => Ok(Format::Gif),
+            Some("png" | "apng") => Ok(Format::Apng),
+            Some("webp") => Ok(Format::WebP),
+            Some("avif") => Ok(Format::Avif),
+            _ => bail!("cannot tell the output format of {path}, expected a .gif, .png, .apng, .webp or .avif file, \
+                or use --format"),
+        }
+    }
+
+    /// Appended to the input name when the output name is derived from it.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Gif => "gif",
+            Format::Apng => "png",
+            Format::WebP => "webp",
+            Format::Avif => "avif",
+            Format::Frames => "",
+            Format::Spritesheet => "sprites.png",
+        }
+    }
+}
+
+/// Parses the names listed in `--format`.
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(Format::Gif),
+            "apng" => Ok(Format::Apng),
+            "webp" => Ok(Format::WebP),
+            "avif" => Ok(Format::Avif),
+            "frames" => Ok(Format::Frames),
+            "spritesheet" => Ok(Format::Spritesheet),
+            _ => Err(format!("expected gif, apng, webp, avif, frames or spritesheet, got {s:?}")),
+        }
+    }
+}
+
+/// Output path meaning standard output.
+pub fn is_stdout(path: &Utf8Path) -> bool {
+    path == "-"
+}
+
+/// Receives the processed frames in order on the decoding thread.
+pub trait FrameCollector: Send {
+    fn add_frame(&mut self, index: usize, image: ImgVec<RGBA8>, pts: f64) -> Result<()>;
+}
+
+/// Encodes the collected frames into a file on the calling thread, until the collector is dropped.
+pub trait FrameWriter {
+    fn write(self: Box<Self>, output: &Utf8Path, progress: &mut dyn ProgressReporter) -> Result<()>;
+}
+
+pub fn new(format: Format, settings: &EncodeSettings) -> Result<(Box<dyn FrameCollector>, Box<dyn FrameWriter>)> {
+    let (tx, rx) = mpsc::sync_channel(settings.queue_length);
+    let writer: Box<dyn FrameWriter> = match format {
+        Format::Gif => {
+            let (collector, writer) = settings.new_gifski()?;
+            Box::new(GifskiWriter { rx, collector, writer })
+        },
+        Format::Frames => Box::new(FramesWriter { rx, settings: *settings }),
+        Format::Spritesheet => Box::new(SpritesheetWriter { rx, settings: *settings }),
+        _ => Box::new(FfmpegWriter { rx, format, settings: *settings }),
+    };
+    Ok((Box::new(ChannelCollector(tx)), writer))
+}
+
+/// Opens an encoder for a regular video, picking the container and codec from the extension of `output`.
+///
+/// `quality` maps to the constant quality of the codec; its default is used if `None`. Odd frame sizes are
+/// rounded up, which chroma subsampling needs.
+pub fn open_video(output: &Utf8Path, width: usize, height: usize, quality: Option<u8>) -> Result<VideoEncoder> {
+    let (muxer, codec, pixel_format, max_crf) = match output.extension().map(|ext| ext.to_ascii_lowercase()).as_deref() {
+        Some("webm") => ("webm", "libvpx-vp9", "yuva420p", 63),
+        Some("mkv") => ("matroska", "libvpx-vp9", "yuva420p", 63),
+        Some("mp4") => ("mp4", "libx264", "yuv420p", 51),
+        Some("mov") => ("mov", "libx264", "yuv420p", 51),
+        _ => bail!("cannot tell the video format of {output}, expected a .webm, .mkv, .mp4 or .mov file"),
+    };
+    let mut codec_options = Vec::new();
+    if let Some(quality) = quality {
+        codec_options.push(("crf", (max_crf - quality as u32 * max_crf / 100).to_string()));
+    }
+    if codec == "libvpx-vp9" {
+        // constant quality mode instead of the default bitrate target
+        codec_options.push(("b", "0".to_owned()));
+    }
+    let muxer_options = match muxer {
+        // lets players start before the whole file is downloaded
+        "mp4" | "mov" => vec![("movflags", "+faststart".to_owned())],
+        _ => Vec::new(),
+    };
+    let size = ((width as u32 + 1) & !1, (height as u32 + 1) & !1);
+    VideoEncoder::new(output, muxer, codec, pixel_format, (width as u32, height as u32), size, &muxer_options,
+            &codec_options)
+        .wrap_err_with(|| format!("failed to start encoding {output}"))
+}
+
+/// Size of the output for frames of `width` x `height`; like gifski, frames are never upscaled and keep their
+/// aspect ratio.
+pub fn output_size(settings: &EncodeSettings, width: usize, height: usize) -> (usize, usize) {
+    let scale = [settings.width.map(|w| w as f64 / width as f64), settings.height.map(|h| h as f64 / height as f64)]
+        .into_iter()
+        .flatten()
+        .fold(1.0, f64::min);
+    let scaled = |v: usize| ((v as f64 * scale).round() as usize).max(1);
+    (scaled(width), scaled(height))
+}
+
+impl FrameCollector for gifski::Collector {
+    fn add_frame(&mut self, index: usize, image: ImgVec<RGBA8>, pts: f64) -> Result<()> {
+        self.add_frame_rgba(index, image, pts).map_err(Into::into)
+    }
+}
+
+impl FrameWriter for gifski::Writer {
+    fn write(self: Box<Self>, output: &Utf8Path, progress: &mut dyn ProgressReporter) -> Result<()> {
+        if is_stdout(output) {
+            return (*self).write(BufWriter::new(Throttled(io::stdout().lock())), progress).map_err(Into::into);
+        }
+        let file = File::create(output).wrap_err_with(|| format!("failed to create {output}"))?;
+        (*self).write(BufWriter::new(Throttled(file)), progress).map_err(Into::into)
+    }
+}
+
+type FrameReceiver = Receiver<(ImgVec<RGBA8>, f64)>;
+
+/// Passes the queued frames on to gifski on a thread of their own, which gifski's own queue can block.
+pub(crate) fn feed_gifski(rx: FrameReceiver, mut collector: gifski::Collector) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        for (index, (image, pts)) in rx.into_iter().enumerate() {
+            collector.add_frame_rgba(index, image, pts)?;
+        }
+        Ok(())
+    })
+}
+
+/// gifski behind the same bounded queue as the other formats.
+struct GifskiWriter {
+    rx: FrameReceiver,
+    collector: gifski::Collector,
+    writer: gifski::Writer,
+}
+
+impl FrameWriter for GifskiWriter {
+    fn write(self: Box<Self>, output: &Utf8Path, progress: &mut dyn ProgressReporter) -> Result<()> {
+        let Self { rx, collector, writer } = *self;
+        let feeder = feed_gifski(rx, collector);
+        let result = Box::new(writer).write(output, progress);
+        // the writer's error explains why the feeder stopped, if both failed
+        result.and(feeder.join().unwrap())
+    }
+}
+
+struct ChannelCollector(SyncSender<(ImgVec<RGBA8>, f64)>);
+
+impl FrameCollector for ChannelCollector {
+    fn add_frame(&mut self, _index: usize, image: ImgVec<RGBA8>, pts: f64) -> Result<()> {
+        // the writer only hangs up after failing, its error is reported instead
+        self.0.send((image, pts)).map_err(|_| eyre!("the encoder stopped early"))
+    }
+}
+
+/// Encodes with one of ffmpeg's muxers, which needs the frame size before the first frame can be written.
+struct FfmpegWriter {
+    rx: FrameReceiver,
+    format: Format,
+    settings: EncodeSettings,
+}
+
+impl FrameWriter for FfmpegWriter {
+    fn write(self: Box<Self>, output: &Utf8Path, progress: &mut dyn ProgressReporter) -> Result<()> {
+        let mut encoder = None;
+        for (image, pts) in &self.rx {
+            let encoder = match &mut encoder {
+                Some(encoder) => encoder,
+                None => encoder.insert(self.open(output, image.width(), image.height())?),
+            };
+            encoder.encode(image.as_ref(), pts)?;
+            progress.increase();
+        }
+        encoder.ok_or_else(|| eyre!("no frames to encode"))?.finish()
+    }
+}
+
+impl FfmpegWriter {
+    fn open(&self, output: &Utf8Path, width: usize, height: usize) -> Result<VideoEncoder> {
+        let (mut scaled_width, mut scaled_height) = output_size(&self.settings, width, height);
+        let plays = match self.settings.repeat {
+            Repeat::Infinite => 0,
+            Repeat::Finite(n) => n as u32 + 1,
+        };
+        let (muxer, codec, pixel_format, muxer_options, codec_options) = match self.format {
+            Format::Apng => ("apng", "apng", "rgba", vec![("plays", plays.to_string())], vec![("pred", "mixed".to_owned())]),
+            Format::WebP => ("webp", "libwebp_anim", "yuva420p", vec![("loop", plays.to_string())],
+                vec![("quality", self.settings.quality.to_string())]),
+            Format::Avif => ("avif", "libaom-av1", "yuv420p", vec![("loop", plays.to_string())], vec![
+                // constant quality, 0 is lossless and 63 the worst
+                ("crf", (63 - self.settings.quality as u32 * 63 / 100).to_string()),
+                ("b", "0".to_owned()),
+            ]),
+            Format::Gif | Format::Frames | Format::Spritesheet => unreachable!(),
+        };
+        if pixel_format.starts_with("yuv") {
+            // chroma subsampling needs even sizes
+            scaled_width = (scaled_width + 1) & !1;
+            scaled_height = (scaled_height + 1) & !1;
+        }
+        let path = if is_stdout(output) { Utf8Path::new("pipe:1") } else { output };
+        VideoEncoder::new(path, muxer, codec, pixel_format, (width as u32, height as u32),
+                (scaled_width as u32, scaled_height as u32), &muxer_options, &codec_options)
+            .wrap_err_with(|| format!("failed to start encoding {output}"))
+    }
+}
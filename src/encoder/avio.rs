@@ -0,0 +1,90 @@
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Stdout, Write};
+use std::os::raw::c_int;
+use std::slice;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{ensure, Context};
+use ffmpeg_sys_next as f;
+
+use crate::throttle::Throttled;
+
+/// Size of the buffer ffmpeg collects writes in.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+enum Sink {
+    File(Throttled<File>),
+    Stdout(Throttled<Stdout>),
+}
+
+/// An `AVIOContext` writing through `Throttled`, for `--io-limit`, instead of one opened by `avio_open`.
+pub struct ThrottledIo {
+    pub ctx: *mut f::AVIOContext,
+    /// Boxed so that ffmpeg gets a stable pointer as its opaque value.
+    sink: *mut Sink,
+}
+
+impl ThrottledIo {
+    /// Creates `path`, or writes to stdout for `pipe:1`, which can't seek.
+    pub fn create(path: &Utf8Path) -> Result<Self> {
+        let sink = match path.as_str() {
+            "pipe:1" => Sink::Stdout(Throttled(io::stdout())),
+            _ => Sink::File(Throttled(File::create(path).wrap_err_with(|| format!("failed to create {path}"))?)),
+        };
+        let seekable = matches!(sink, Sink::File(_));
+        unsafe {
+            let buffer = f::av_malloc(BUFFER_SIZE) as *mut u8;
+            ensure!(!buffer.is_null(), "failed to allocate the write buffer");
+            let sink = Box::into_raw(Box::new(sink));
+            let ctx = f::avio_alloc_context(buffer, BUFFER_SIZE as c_int, 1, sink as *mut c_void, None,
+                Some(write_packet), if seekable { Some(seek) } else { None });
+            if ctx.is_null() {
+                f::av_free(buffer as *mut c_void);
+                drop(Box::from_raw(sink));
+            }
+            ensure!(!ctx.is_null(), "failed to allocate the I/O context");
+            Ok(Self { ctx, sink })
+        }
+    }
+}
+
+impl Drop for ThrottledIo {
+    fn drop(&mut self) {
+        unsafe {
+            // ffmpeg may have replaced the buffer it was given
+            f::av_freep(&mut (*self.ctx).buffer as *mut *mut u8 as *mut c_void);
+            f::avio_context_free(&mut self.ctx);
+            drop(Box::from_raw(self.sink));
+        }
+    }
+}
+
+unsafe extern "C" fn write_packet(opaque: *mut c_void, buf: *mut u8, size: c_int) -> c_int {
+    let sink = &mut *(opaque as *mut Sink);
+    let buf = slice::from_raw_parts(buf, size.max(0) as usize);
+    let result = match sink {
+        Sink::File(file) => file.write_all(buf),
+        Sink::Stdout(stdout) => stdout.write_all(buf).and_then(|()| stdout.flush()),
+    };
+    result.map_or(f::AVERROR(f::EIO), |()| size)
+}
+
+unsafe extern "C" fn seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    // only registered for files
+    let Sink::File(file) = &mut *(opaque as *mut Sink) else { return f::AVERROR(f::EINVAL) as i64 };
+    let result = if whence & f::AVSEEK_SIZE as c_int != 0 {
+        // asks for the total size without moving
+        file.0.metadata().map(|metadata| metadata.len())
+    } else {
+        let to = match whence & !(f::AVSEEK_FORCE as c_int) {
+            libc::SEEK_SET => SeekFrom::Start(offset as u64),
+            libc::SEEK_CUR => SeekFrom::Current(offset),
+            libc::SEEK_END => SeekFrom::End(offset),
+            _ => return f::AVERROR(f::EINVAL) as i64,
+        };
+        file.seek(to)
+    };
+    result.map_or(f::AVERROR(f::EIO) as i64, |position| position as i64)
+}
@@ -0,0 +1,214 @@
+use std::ffi::CString;
+use std::ptr;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{ensure, Context};
+use ffmpeg_sys_next as f;
+use imgref::ImgRef;
+use rgb::{ComponentBytes, RGBA8};
+
+use super::avio::ThrottledIo;
+use crate::decoder::AVError;
+use crate::throttle;
+
+/// Timestamps are written in milliseconds, which is all the animated formats can store anyway.
+const TIME_BASE: f::AVRational = f::AVRational { num: 1, den: 1000 };
+
+/// Encodes RGBA frames into a file with one of ffmpeg's muxers.
+pub struct VideoEncoder {
+    fmt_ctx: *mut f::AVFormatContext,
+    enc_ctx: *mut f::AVCodecContext,
+    stream: *mut f::AVStream,
+    sws_ctx: *mut f::SwsContext,
+    frame: *mut f::AVFrame,
+    packet: *mut f::AVPacket,
+    /// Whether `fmt_ctx.pb` was opened by us and has to be closed again.
+    opened_file: bool,
+    source_size: (u32, u32),
+    last_pts: Option<i64>,
+    /// What `fmt_ctx.pb` writes to under `--io-limit`, freed after `fmt_ctx`.
+    io: Option<ThrottledIo>,
+}
+
+/// Turns a negative return value of ffmpeg into an error.
+fn check(ret: i32, what: &str) -> Result<i32> {
+    if ret < 0 {
+        return Err(AVError::from(ret)).wrap_err_with(|| format!("failed to {what}"));
+    }
+    Ok(ret)
+}
+
+/// Builds an `AVDictionary`, which has to be freed by the caller even if this fails.
+unsafe fn set_options(dict: &mut *mut f::AVDictionary, options: &[(&str, String)]) -> Result<()> {
+    for (key, value) in options {
+        check(f::av_dict_set(dict, CString::new(*key)?.as_ptr(), CString::new(value.as_str())?.as_ptr(), 0),
+            &format!("set option {key}"))?;
+    }
+    Ok(())
+}
+
+impl VideoEncoder {
+    /// Opens `output` for frames of `source_size`, which are scaled to `size` and converted to `pixel_format`.
+    ///
+    /// `muxer_options` and `codec_options` are passed to the muxer and the encoder.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(output: &Utf8Path, muxer: &str, codec: &str, pixel_format: &str, source_size: (u32, u32), size: (u32, u32),
+            muxer_options: &[(&str, String)], codec_options: &[(&str, String)]) -> Result<Self> {
+        let path = CString::new(output.as_str())?;
+        let mut encoder = Self {
+            fmt_ctx: ptr::null_mut(),
+            enc_ctx: ptr::null_mut(),
+            stream: ptr::null_mut(),
+            sws_ctx: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+            opened_file: false,
+            source_size,
+            last_pts: None,
+            io: None,
+        };
+        unsafe {
+            let muxer_name = CString::new(muxer)?;
+            check(f::avformat_alloc_output_context2(&mut encoder.fmt_ctx, ptr::null(), muxer_name.as_ptr(), path.as_ptr()),
+                &format!("find the {muxer} muxer"))?;
+            ensure!(!encoder.fmt_ctx.is_null(), "muxer {} is not available in this build of ffmpeg", muxer);
+
+            let codec_name = CString::new(codec)?;
+            let codec_ptr = f::avcodec_find_encoder_by_name(codec_name.as_ptr());
+            ensure!(!codec_ptr.is_null(), "encoder {} is not available in this build of ffmpeg", codec);
+            let format_name = CString::new(pixel_format)?;
+            let format = f::av_get_pix_fmt(format_name.as_ptr());
+            ensure!(format != f::AVPixelFormat::AV_PIX_FMT_NONE, "unknown pixel format {}", pixel_format);
+
+            encoder.stream = f::avformat_new_stream(encoder.fmt_ctx, ptr::null());
+            ensure!(!encoder.stream.is_null(), "failed to create the output stream");
+            encoder.enc_ctx = f::avcodec_alloc_context3(codec_ptr);
+            ensure!(!encoder.enc_ctx.is_null(), "failed to allocate codec context for {}", codec);
+
+            let enc_ctx = &mut *encoder.enc_ctx;
+            enc_ctx.width = size.0 as i32;
+            enc_ctx.height = size.1 as i32;
+            enc_ctx.pix_fmt = format;
+            enc_ctx.time_base = TIME_BASE;
+            (*encoder.stream).time_base = TIME_BASE;
+            if (*(*encoder.fmt_ctx).oformat).flags & f::AVFMT_GLOBALHEADER as i32 != 0 {
+                enc_ctx.flags |= f::AV_CODEC_FLAG_GLOBAL_HEADER as i32;
+            }
+
+            let mut dict = ptr::null_mut();
+            let ret = set_options(&mut dict, codec_options)
+                .and_then(|()| check(f::avcodec_open2(encoder.enc_ctx, codec_ptr, &mut dict), &format!("open encoder {codec}")));
+            f::av_dict_free(&mut dict);
+            ret?;
+            check(f::avcodec_parameters_from_context((*encoder.stream).codecpar, encoder.enc_ctx),
+                "copy codec parameters to the output stream")?;
+
+            if (*(*encoder.fmt_ctx).oformat).flags & f::AVFMT_NOFILE as i32 == 0 {
+                if throttle::is_limited() {
+                    let io = ThrottledIo::create(output)?;
+                    (*encoder.fmt_ctx).pb = io.ctx;
+                    encoder.io = Some(io);
+                } else {
+                    check(f::avio_open(&mut (*encoder.fmt_ctx).pb, path.as_ptr(), f::AVIO_FLAG_WRITE as i32),
+                        "create the output file")?;
+                    encoder.opened_file = true;
+                }
+            }
+            let mut dict = ptr::null_mut();
+            let ret = set_options(&mut dict, muxer_options)
+                .and_then(|()| check(f::avformat_write_header(encoder.fmt_ctx, &mut dict), "write the header"));
+            f::av_dict_free(&mut dict);
+            ret?;
+
+            encoder.frame = f::av_frame_alloc();
+            ensure!(!encoder.frame.is_null(), "failed to allocate frame");
+            let frame = &mut *encoder.frame;
+            frame.width = size.0 as i32;
+            frame.height = size.1 as i32;
+            frame.format = format as i32;
+            check(f::av_frame_get_buffer(encoder.frame, 0), "allocate frame buffer")?;
+            encoder.packet = f::av_packet_alloc();
+            ensure!(!encoder.packet.is_null(), "failed to allocate packet");
+
+            encoder.sws_ctx = f::sws_getContext(
+                source_size.0 as i32, source_size.1 as i32, f::AVPixelFormat::AV_PIX_FMT_RGBA,
+                size.0 as i32, size.1 as i32, format,
+                f::SWS_BICUBIC, ptr::null_mut(), ptr::null_mut(), ptr::null());
+            ensure!(!encoder.sws_ctx.is_null(), "failed to create scale context for the conversion to {}", pixel_format);
+        }
+        Ok(encoder)
+    }
+
+    /// Encodes a frame shown from `pts` seconds on; frames have to be the size given to `new`.
+    pub fn encode(&mut self, image: ImgRef<RGBA8>, pts: f64) -> Result<()> {
+        ensure!((image.width() as u32, image.height() as u32) == self.source_size, "frame size changed while encoding");
+        // muxers reject timestamps that do not increase
+        let mut pts = (pts * TIME_BASE.den as f64).round() as i64;
+        if let Some(last) = self.last_pts.filter(|last| pts <= *last) {
+            pts = last + 1;
+        }
+        self.last_pts = Some(pts);
+
+        unsafe {
+            check(f::av_frame_make_writable(self.frame), "make frame writable")?;
+            let frame = &mut *self.frame;
+            let ret = f::sws_scale(
+                self.sws_ctx,
+                [image.buf().as_bytes().as_ptr()].as_ptr(),
+                [image.stride() as i32 * 4].as_ptr(),
+                0,
+                image.height() as i32,
+                frame.data.as_ptr() as _,
+                frame.linesize.as_ptr(),
+            );
+            ensure!(ret > 0, "failed to convert frame from RGBA");
+            frame.pts = pts;
+            check(f::avcodec_send_frame(self.enc_ctx, self.frame), "submit frame for encoding")?;
+            self.write_packets()
+        }
+    }
+
+    /// Flushes the encoder and completes the file.
+    pub fn finish(self) -> Result<()> {
+        unsafe {
+            check(f::avcodec_send_frame(self.enc_ctx, ptr::null()), "flush the encoder")?;
+            self.write_packets()?;
+            check(f::av_write_trailer(self.fmt_ctx), "write the trailer")?;
+        }
+        Ok(())
+    }
+
+    unsafe fn write_packets(&self) -> Result<()> {
+        loop {
+            let ret = f::avcodec_receive_packet(self.enc_ctx, self.packet);
+            if ret == f::AVERROR(f::EAGAIN) || ret == f::AVERROR_EOF {
+                return Ok(());
+            }
+            check(ret, "encode frame")?;
+            f::av_packet_rescale_ts(self.packet, TIME_BASE, (*self.stream).time_base);
+            (*self.packet).stream_index = (*self.stream).index;
+            // takes over the reference to the packet data
+            check(f::av_interleaved_write_frame(self.fmt_ctx, self.packet), "write frame")?;
+        }
+    }
+}
+
+impl Drop for VideoEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.sws_ctx.is_null() {
+                f::sws_freeContext(self.sws_ctx);
+            }
+            f::av_frame_free(&mut self.frame);
+            f::av_packet_free(&mut self.packet);
+            f::avcodec_free_context(&mut self.enc_ctx);
+            if self.opened_file {
+                f::avio_closep(&mut (*self.fmt_ctx).pb);
+            }
+            if !self.fmt_ctx.is_null() {
+                f::avformat_free_context(self.fmt_ctx);
+            }
+        }
+    }
+}
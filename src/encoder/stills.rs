@@ -0,0 +1,87 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{ensure, eyre, Context};
+use gifski::progress::ProgressReporter;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use imgref::{ImgRef, ImgVec};
+use rgb::{ComponentBytes, RGBA8};
+
+use super::{is_stdout, output_size, FrameReceiver, FrameWriter};
+use crate::filter::resample_bilinear;
+use crate::settings::EncodeSettings;
+use crate::throttle::Throttled;
+
+/// Writes every frame as `000001.png`, `000002.png`, ... into the output directory.
+pub struct FramesWriter {
+    pub rx: FrameReceiver,
+    pub settings: EncodeSettings,
+}
+
+impl FrameWriter for FramesWriter {
+    fn write(self: Box<Self>, output: &Utf8Path, progress: &mut dyn ProgressReporter) -> Result<()> {
+        ensure!(!is_stdout(output), "frames can only be written to a directory");
+        fs::create_dir_all(output).wrap_err_with(|| format!("failed to create {output}"))?;
+        for (index, (image, _)) in self.rx.iter().enumerate() {
+            let path = output.join(format!("{:06}.png", index + 1));
+            let file = File::create(&path).wrap_err_with(|| format!("failed to create {path}"))?;
+            write_png(BufWriter::new(Throttled(file)), scale(&self.settings, image).as_ref())
+                .wrap_err_with(|| format!("failed to write {path}"))?;
+            progress.increase();
+        }
+        Ok(())
+    }
+}
+
+/// Lays all frames out in a grid of about as many columns as rows in a single PNG, which needs every frame in
+/// memory until the last one arrives.
+pub struct SpritesheetWriter {
+    pub rx: FrameReceiver,
+    pub settings: EncodeSettings,
+}
+
+impl FrameWriter for SpritesheetWriter {
+    fn write(self: Box<Self>, output: &Utf8Path, progress: &mut dyn ProgressReporter) -> Result<()> {
+        let mut frames = Vec::new();
+        for (image, _) in &self.rx {
+            frames.push(scale(&self.settings, image));
+            progress.increase();
+        }
+        let first = frames.first().ok_or_else(|| eyre!("no frames to encode"))?;
+        let (width, height) = (first.width(), first.height());
+        let columns = (frames.len() as f64).sqrt().ceil() as usize;
+        let rows = (frames.len() + columns - 1) / columns;
+
+        let mut sheet = ImgVec::new(vec![RGBA8::new(0, 0, 0, 0); width * columns * height * rows], width * columns, height * rows);
+        for (index, frame) in frames.iter().enumerate() {
+            let mut cell = sheet.sub_image_mut(index % columns * width, index / columns * height, width, height);
+            for (dst, src) in cell.rows_mut().zip(frame.rows()) {
+                dst.copy_from_slice(src);
+            }
+        }
+
+        if is_stdout(output) {
+            return write_png(BufWriter::new(Throttled(io::stdout().lock())), sheet.as_ref());
+        }
+        let file = File::create(output).wrap_err_with(|| format!("failed to create {output}"))?;
+        write_png(BufWriter::new(Throttled(file)), sheet.as_ref()).wrap_err_with(|| format!("failed to write {output}"))
+    }
+}
+
+fn scale(settings: &EncodeSettings, image: ImgVec<RGBA8>) -> ImgVec<RGBA8> {
+    let (width, height) = output_size(settings, image.width(), image.height());
+    if (width, height) == (image.width(), image.height()) {
+        return image;
+    }
+    resample_bilinear(image.as_ref(), 0.0, 0.0, image.width() as f32, image.height() as f32, width, height)
+}
+
+fn write_png(mut writer: impl Write, image: ImgRef<RGBA8>) -> Result<()> {
+    let (buf, width, height) = image.to_contiguous_buf();
+    PngEncoder::new(&mut writer).write_image(buf.as_bytes(), width as u32, height as u32, ColorType::Rgba8)?;
+    writer.flush()?;
+    Ok(())
+}
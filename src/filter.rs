@@ -0,0 +1,191 @@
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use imgref::ImgVec;
+use rgb::{FromSlice, RGBA8};
+
+use crate::cli::Args;
+
+mod alpha;
+mod callback;
+mod card;
+mod chroma_key;
+mod crop;
+mod crossfade;
+mod fade;
+mod fps;
+mod hold;
+mod plugin;
+mod resample;
+mod reverse;
+mod rotate;
+mod script;
+mod select;
+mod skip_blank;
+mod speed;
+mod speed_ramp;
+mod stabilize;
+mod zoompan;
+pub use alpha::*;
+pub use callback::*;
+pub use card::*;
+pub use chroma_key::*;
+pub use crop::*;
+pub use crossfade::*;
+pub use fade::*;
+pub use fps::*;
+pub use hold::*;
+pub use plugin::*;
+pub use resample::*;
+pub use reverse::*;
+pub use rotate::*;
+pub use script::*;
+pub use select::*;
+pub use skip_blank::*;
+pub use speed::*;
+pub use speed_ramp::*;
+pub use stabilize::*;
+pub use zoompan::*;
+
+#[derive(Clone)]
+pub struct Frame {
+    pub image: ImgVec<RGBA8>,
+    /// Presentation timestamp in seconds.
+    pub pts: f64,
+}
+
+pub trait Filter: Send {
+    /// Consumes a frame and pushes the frames that are ready to be encoded to `out`.
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()>;
+
+    /// Called after the last frame to release anything the filter is still holding back.
+    fn finish(&mut self, _out: &mut Vec<Frame>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The rate `--fps` caps the decoded frames at, for [`Decoder::with_fps_cap`] or an [`FpsCap`] in front of the
+/// filters. `--speed` changes the frame rate after.
+///
+/// [`Decoder::with_fps_cap`]: crate::decoder::Decoder::with_fps_cap
+pub fn fps_cap(args: &Args) -> Option<f64> {
+    args.fps.map(|fps| fps / args.speed)
+}
+
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl FilterChain {
+    /// `input` is the converted file, if there is a single one, and `duration` its expected length in seconds.
+    /// `crop` is the rectangle found by `--autocrop` in the decoded frames, and `orientation` how to turn them
+    /// upright after that, before `--crop` and everything else. `--fps` is left to the decoder, see [`fps_cap`].
+    pub fn from_args(args: &Args, input: Option<&Utf8Path>, crop: Option<CropRect>, orientation: Orientation,
+            duration: f64) -> Result<Self> {
+        let mut chain = Self::default();
+        if args.alpha == AlphaMode::Premultiplied {
+            chain.push(Unpremultiply);
+        }
+        if let Some(rect) = crop {
+            chain.push(Crop(rect));
+        }
+        if !orientation.is_upright() {
+            chain.push(Rotate(orientation));
+        }
+        if let Some(rect) = args.crop {
+            chain.push(Crop(rect));
+        }
+        if let Some(expr) = &args.select {
+            chain.push(Select::new(expr.clone()));
+        }
+        if let Some(path) = &args.script {
+            chain.push(Script::load(path, input, duration)?);
+        }
+        if args.skip_blank_start {
+            chain.push(SkipBlankStart::new(args.blank_threshold));
+        }
+        if args.stabilize {
+            chain.push(Stabilize::default());
+        }
+        if let Some(spec) = args.zoompan {
+            chain.push(ZoomPan::new(spec, duration));
+        }
+        if let Some(spec) = &args.speed_ramp {
+            chain.push(SpeedRamp::new(spec.clone()));
+        }
+        if args.speed != 1.0 {
+            chain.push(Speed::new(args.speed));
+        }
+        if let Some(key) = args.chroma_key {
+            chain.push(ChromaKey::new(key, args.key_tolerance));
+        }
+        for path in &args.plugin {
+            chain.push(Plugin::load(path)?);
+        }
+        if args.reverse || args.boomerang {
+            chain.push(Reverse::new(args.boomerang));
+        }
+        if !args.loop_crossfade.is_zero() {
+            chain.push(LoopCrossfade::new(args.loop_crossfade.as_secs_f64()));
+        }
+        if !args.fade_in.is_zero() || !args.fade_out.is_zero() {
+            chain.push(Fade::new(args.fade_in.as_secs_f64(), args.fade_out.as_secs_f64()));
+        }
+        if !args.hold_last.is_zero() {
+            chain.push(HoldLast::new(args.hold_last.as_secs_f64()));
+        }
+        if let Some(title) = &args.title {
+            chain.push(TitleCard::new(Card::Text(title.clone()), args.title_duration.as_secs_f64()));
+        }
+        if let Some(path) = &args.outro {
+            let image = image::open(path).wrap_err_with(|| format!("failed to load outro image {path}"))?.to_rgba8();
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            let image = ImgVec::new(image.into_raw().as_rgba().to_vec(), width, height);
+            chain.push(OutroCard::new(Card::Image(image), args.outro_duration.as_secs_f64()));
+        }
+        Ok(chain)
+    }
+
+    pub fn push(&mut self, filter: impl Filter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Adds `filter` in front of the ones pushed so far.
+    pub fn push_front(&mut self, filter: impl Filter + 'static) {
+        self.filters.insert(0, Box::new(filter));
+    }
+
+    /// Calls `callback` with every frame that made it through the filters so far and its timestamp in seconds,
+    /// right before the frame would be encoded.
+    pub fn on_frame(&mut self, callback: impl FnMut(&mut ImgVec<RGBA8>, f64) -> FrameAction + Send + 'static) {
+        self.push(FrameCallback(callback));
+    }
+
+    pub fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let mut frames = vec![frame];
+        for filter in &mut self.filters {
+            let mut next = Vec::with_capacity(frames.len());
+            for frame in frames.drain(..) {
+                filter.process(frame, &mut next)?;
+            }
+            frames = next;
+        }
+        out.append(&mut frames);
+        Ok(())
+    }
+
+    pub fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let mut frames = Vec::new();
+        for filter in &mut self.filters {
+            let mut next = Vec::with_capacity(frames.len());
+            for frame in frames.drain(..) {
+                filter.process(frame, &mut next)?;
+            }
+            filter.finish(&mut next)?;
+            frames = next;
+        }
+        out.append(&mut frames);
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use std::str::FromStr;
+
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// How the color channels of decoded frames relate to their alpha.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Colors are independent of alpha, which is what the filters and the encoder expect.
+    Straight,
+    /// Colors have already been multiplied by alpha, as written by some compositing tools.
+    Premultiplied,
+}
+
+/// Parses `straight` or `premultiplied`.
+impl FromStr for AlphaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "straight" => Ok(AlphaMode::Straight),
+            "premultiplied" => Ok(AlphaMode::Premultiplied),
+            _ => Err(format!("expected straight or premultiplied, got {s:?}")),
+        }
+    }
+}
+
+/// Divides premultiplied colors by alpha; left alone they turn into dark fringes around transparent edges.
+pub struct Unpremultiply;
+
+impl Filter for Unpremultiply {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        for px in frame.image.pixels_mut() {
+            let a = px.a as u32;
+            if a == 0 {
+                px.r = 0;
+                px.g = 0;
+                px.b = 0;
+            } else if a < 255 {
+                let unpremultiply = |c: u8| ((c as u32 * 255 + a / 2) / a).min(255) as u8;
+                px.r = unpremultiply(px.r);
+                px.g = unpremultiply(px.g);
+                px.b = unpremultiply(px.b);
+            }
+        }
+        out.push(frame);
+        Ok(())
+    }
+}
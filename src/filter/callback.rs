@@ -0,0 +1,29 @@
+use color_eyre::Result;
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use super::{Filter, Frame};
+
+/// What a frame callback wants done with the frame it was given.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameAction {
+    /// Encode the frame, including any changes the callback made to it.
+    Keep,
+    /// Leave the frame out; the frame before it stays on screen longer.
+    Drop,
+}
+
+/// Hands every frame to a closure, which may draw on it or drop it.
+pub struct FrameCallback<F>(pub F);
+
+impl<F> Filter for FrameCallback<F>
+where
+    F: FnMut(&mut ImgVec<RGBA8>, f64) -> FrameAction + Send,
+{
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        if (self.0)(&mut frame.image, frame.pts) == FrameAction::Keep {
+            out.push(frame);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use color_eyre::Result;
+use font8x8::{UnicodeFonts, BASIC_FONTS, LATIN_FONTS};
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use super::{letterbox, Filter, Frame};
+
+pub enum Card {
+    Text(String),
+    Image(ImgVec<RGBA8>),
+}
+
+impl Card {
+    fn render(&self, width: usize, height: usize) -> ImgVec<RGBA8> {
+        match self {
+            Card::Text(text) => render_text(text, width, height),
+            Card::Image(image) => letterbox(image.as_ref(), width, height),
+        }
+    }
+}
+
+/// Shows a card before the first frame, delaying the clip by `duration` seconds.
+pub struct TitleCard {
+    card: Card,
+    duration: f64,
+    started: bool,
+}
+
+impl TitleCard {
+    pub fn new(card: Card, duration: f64) -> Self {
+        Self { card, duration, started: false }
+    }
+}
+
+impl Filter for TitleCard {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        if !self.started {
+            self.started = true;
+            out.push(Frame {
+                image: self.card.render(frame.image.width(), frame.image.height()),
+                pts: frame.pts,
+            });
+        }
+        frame.pts += self.duration;
+        out.push(frame);
+        Ok(())
+    }
+}
+
+/// Shows a card for `duration` seconds after the last frame.
+pub struct OutroCard {
+    card: Card,
+    duration: f64,
+    /// Size, timestamp and display time of the last frame seen.
+    last: Option<(usize, usize, f64, f64)>,
+}
+
+impl OutroCard {
+    pub fn new(card: Card, duration: f64) -> Self {
+        Self { card, duration, last: None }
+    }
+}
+
+impl Filter for OutroCard {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let delay = self.last.map_or(0.0, |(_, _, pts, _)| frame.pts - pts);
+        self.last = Some((frame.image.width(), frame.image.height(), frame.pts, delay));
+        out.push(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let Some((width, height, pts, delay)) = self.last.take() else { return Ok(()) };
+        let pts = pts + if delay > 0.0 { delay } else { 0.1 };
+        let image = self.card.render(width, height);
+        // the encoder repeats the previous delay for the last frame, so the card is shown twice for half as long
+        out.push(Frame { image: image.clone(), pts });
+        out.push(Frame { image, pts: pts + self.duration / 2.0 });
+        Ok(())
+    }
+}
+
+/// Renders white text centered on a black background with the built-in 8x8 font, scaled as large as fits.
+pub fn render_text(text: &str, width: usize, height: usize) -> ImgVec<RGBA8> {
+    let text = text.replace("\\n", "\n");
+    let lines = text.lines()
+        .map(|line| line.chars().map(|c| BASIC_FONTS.get(c).or_else(|| LATIN_FONTS.get(c)).or_else(|| BASIC_FONTS.get('?'))).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let columns = lines.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    // glyphs are 8x8 with 2 pixels of line spacing, and the text may cover 80% of the width and half the height
+    let scale = (width * 4 / 5 / (columns * 8)).min(height / 2 / (lines.len().max(1) * 10)).max(1);
+    let line_height = 10 * scale;
+
+    let mut canvas = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); width * height], width, height);
+    let top = height.saturating_sub(lines.len() * line_height) / 2;
+    for (row, line) in lines.iter().enumerate() {
+        let left = width.saturating_sub(line.len() * 8 * scale) / 2;
+        for (column, glyph) in line.iter().enumerate() {
+            let Some(glyph) = glyph else { continue };
+            for (gy, bits) in glyph.iter().enumerate() {
+                for gx in (0..8).filter(|gx| bits & (1 << gx) != 0) {
+                    let x = left + (column * 8 + gx) * scale;
+                    let y = top + row * line_height + gy * scale;
+                    if x + scale <= width && y + scale <= height {
+                        for line in canvas.sub_image_mut(x, y, scale, scale).rows_mut() {
+                            line.fill(RGBA8::new(255, 255, 255, 255));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    canvas
+}
@@ -0,0 +1,51 @@
+use color_eyre::Result;
+use rgb::{RGB8, RGBA8};
+
+use super::{Filter, Frame};
+
+/// Makes pixels close to the key color fully transparent.
+///
+/// Colors are compared in YCbCr with luma at half weight, so shading on a green screen still matches.
+pub struct ChromaKey {
+    key: [f32; 3],
+    tolerance: f32,
+}
+
+impl ChromaKey {
+    /// `tolerance` is the maximum distance to the key, from 0 (exact match) to 1.
+    pub fn new(key: RGB8, tolerance: f32) -> Self {
+        Self {
+            key: ycbcr(key.r, key.g, key.b),
+            tolerance: tolerance * 255.0,
+        }
+    }
+
+    fn matches(&self, px: RGBA8) -> bool {
+        let [y, cb, cr] = ycbcr(px.r, px.g, px.b);
+        let dy = (y - self.key[0]) * 0.5;
+        let dcb = cb - self.key[1];
+        let dcr = cr - self.key[2];
+        dy * dy + dcb * dcb + dcr * dcr <= self.tolerance * self.tolerance
+    }
+}
+
+impl Filter for ChromaKey {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        for px in frame.image.pixels_mut() {
+            if self.matches(*px) {
+                *px = RGBA8::new(0, 0, 0, 0);
+            }
+        }
+        out.push(frame);
+        Ok(())
+    }
+}
+
+fn ycbcr(r: u8, g: u8, b: u8) -> [f32; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.168_736 * r - 0.331_264 * g + 0.5 * b,
+        0.5 * r - 0.418_688 * g - 0.081_312 * b,
+    ]
+}
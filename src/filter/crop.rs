@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+use color_eyre::Result;
+use color_eyre::eyre::ensure;
+use imgref::ImgVec;
+
+use super::{Filter, Frame};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CropRect {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CropRect {
+    pub fn union(self, other: Self) -> Self {
+        let left = self.left.min(other.left);
+        let top = self.top.min(other.top);
+        let right = (self.left + self.width).max(other.left + other.width);
+        let bottom = (self.top + self.height).max(other.top + other.height);
+        Self { left, top, width: right - left, height: bottom - top }
+    }
+}
+
+/// Formats as `WxH+X+Y`.
+impl fmt::Display for CropRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.left, self.top)
+    }
+}
+
+/// Parses `WxH+X+Y`, e.g. `640x360+0+60`.
+impl FromStr for CropRect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("expected WIDTHxHEIGHT+LEFT+TOP, e.g. 640x360+0+60, got {s:?}");
+        let (size, offset) = s.split_once('+').ok_or_else(error)?;
+        let (width, height) = size.split_once('x').ok_or_else(error)?;
+        let (left, top) = offset.split_once('+').ok_or_else(error)?;
+        let parse = |v: &str| v.trim().parse::<usize>().map_err(|_| error());
+        let rect = Self { left: parse(left)?, top: parse(top)?, width: parse(width)?, height: parse(height)? };
+        if rect.width == 0 || rect.height == 0 {
+            return Err(error());
+        }
+        Ok(rect)
+    }
+}
+
+pub struct Crop(pub CropRect);
+
+impl Filter for Crop {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let CropRect { left, top, width, height } = self.0;
+        ensure!(left + width <= frame.image.width() && top + height <= frame.image.height(),
+            "crop rectangle {} exceeds the frame size {}x{}", self.0, frame.image.width(), frame.image.height());
+
+        let (buf, width, height) = frame.image.sub_image(left, top, width, height).to_contiguous_buf();
+        out.push(Frame {
+            image: ImgVec::new(buf.into_owned(), width, height),
+            pts: frame.pts,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rectangles() {
+        let rect = "640x360+0+60".parse::<CropRect>().unwrap();
+        assert!(rect == CropRect { left: 0, top: 60, width: 640, height: 360 });
+        assert_eq!(rect.to_string(), "640x360+0+60");
+        assert!(" 64 x 36 + 1 + 2 ".parse::<CropRect>().unwrap() == CropRect { left: 1, top: 2, width: 64, height: 36 });
+    }
+
+    #[test]
+    fn rejects_invalid_rectangles() {
+        for s in ["", "640x360", "640x360+0", "640+0+60", "0x360+0+0", "640x0+0+0", "ax360+0+0", "640x360+-1+0"] {
+            assert!(s.parse::<CropRect>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn unions() {
+        let a = CropRect { left: 10, top: 0, width: 20, height: 10 };
+        let b = CropRect { left: 0, top: 5, width: 15, height: 20 };
+        assert!(a.union(b) == CropRect { left: 0, top: 0, width: 30, height: 25 });
+    }
+}
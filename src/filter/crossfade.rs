@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// Blends the last `duration` seconds of the clip into the first ones so that the loop point is seamless.
+///
+/// The first `duration` seconds are held back and only shown as part of the blended ending, so the output is
+/// shorter than the input by that amount.
+pub struct LoopCrossfade {
+    duration: f64,
+    first_pts: Option<f64>,
+    head: Vec<Frame>,
+    /// Length of the held back head, subtracted from the timestamps of all frames after it.
+    shift: Option<f64>,
+    tail: VecDeque<Frame>,
+}
+
+impl LoopCrossfade {
+    pub fn new(duration: f64) -> Self {
+        Self { duration, first_pts: None, head: Vec::new(), shift: None, tail: VecDeque::new() }
+    }
+}
+
+impl Filter for LoopCrossfade {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        if self.shift.is_none() && frame.pts - first_pts < self.duration {
+            self.head.push(frame);
+            return Ok(());
+        }
+
+        frame.pts -= *self.shift.get_or_insert(frame.pts - first_pts);
+        while let Some(front) = self.tail.front() {
+            if front.pts >= frame.pts - self.duration {
+                break;
+            }
+            out.extend(self.tail.pop_front());
+        }
+        self.tail.push_back(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = self.first_pts.unwrap_or(0.0);
+        let mut last = self.tail.iter().rev().map(|f| f.pts);
+        let (Some(last_pts), prev_pts) = (last.next(), last.next()) else {
+            // too short to crossfade
+            out.append(&mut self.head);
+            return Ok(());
+        };
+        let start = last_pts + prev_pts.map_or(0.0, |p| last_pts - p) - self.duration;
+
+        for mut frame in self.tail.drain(..) {
+            let offset = frame.pts - start;
+            let head = self.head.iter().rev().find(|h| h.pts - first_pts <= offset);
+            if let Some(head) = head.filter(|_| offset >= 0.0) {
+                blend(&mut frame, head, offset / self.duration);
+            }
+            out.push(frame);
+        }
+        self.head.clear();
+        Ok(())
+    }
+}
+
+/// Mixes `other` into `frame` with the given weight of `other`.
+fn blend(frame: &mut Frame, other: &Frame, weight: f64) {
+    if frame.image.width() != other.image.width() || frame.image.height() != other.image.height() {
+        return;
+    }
+    let weight = (weight.clamp(0.0, 1.0) * 256.0) as u32;
+    let mix = |a: u8, b: u8| ((a as u32 * (256 - weight) + b as u32 * weight) >> 8) as u8;
+    for (px, other) in frame.image.pixels_mut().zip(other.image.pixels()) {
+        *px = rgb::RGBA8::new(mix(px.r, other.r), mix(px.g, other.g), mix(px.b, other.b), mix(px.a, other.a));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    use super::*;
+
+    /// Frames at `pts` whose red channel counts up in steps of 40; returns the red channel and timestamp of each.
+    fn run(duration: f64, pts: &[f64]) -> Vec<(u8, f64)> {
+        let mut crossfade = LoopCrossfade::new(duration);
+        let mut out = Vec::new();
+        for (i, &pts) in pts.iter().enumerate() {
+            let image = ImgVec::new(vec![RGBA8::new(i as u8 * 40, 0, 0, 255)], 1, 1);
+            crossfade.process(Frame { image, pts }, &mut out).unwrap();
+        }
+        crossfade.finish(&mut out).unwrap();
+        out.iter().map(|frame| (frame.image.buf()[0].r, frame.pts)).collect()
+    }
+
+    #[test]
+    fn blends_the_head_into_the_ending() {
+        // the first second is held back and mixed into the last one, shifting everything else forward
+        let out = run(1.0, &[0.0, 0.5, 1.0, 1.5, 2.0, 2.5]);
+        assert_eq!(out, [(80, 0.0), (120, 0.5), (160, 1.0), (120, 1.5)]);
+    }
+
+    #[test]
+    fn keeps_clips_shorter_than_the_crossfade() {
+        assert_eq!(run(2.0, &[0.0, 0.5, 1.0]), [(0, 0.0), (40, 0.5), (80, 1.0)]);
+    }
+}
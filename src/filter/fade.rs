@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// Fades the clip in from and out to black over the given number of seconds.
+///
+/// Frames within `fade_out` of the newest frame are held back until the end of the input is known.
+pub struct Fade {
+    fade_in: f64,
+    fade_out: f64,
+    first_pts: Option<f64>,
+    pending: VecDeque<Frame>,
+}
+
+impl Fade {
+    pub fn new(fade_in: f64, fade_out: f64) -> Self {
+        Self { fade_in, fade_out, first_pts: None, pending: VecDeque::new() }
+    }
+}
+
+impl Filter for Fade {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        let elapsed = frame.pts - first_pts;
+        if elapsed < self.fade_in {
+            darken(&mut frame, elapsed / self.fade_in);
+        }
+
+        if self.fade_out <= 0.0 {
+            out.push(frame);
+            return Ok(());
+        }
+        while let Some(front) = self.pending.front() {
+            if front.pts >= frame.pts - self.fade_out {
+                break;
+            }
+            out.extend(self.pending.pop_front());
+        }
+        self.pending.push_back(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let mut last = self.pending.iter().rev().map(|f| f.pts);
+        let (Some(last_pts), prev_pts) = (last.next(), last.next()) else { return Ok(()) };
+        // the last frame stays on screen for about as long as the one before it
+        let end = last_pts + prev_pts.map_or(0.0, |p| last_pts - p);
+
+        for mut frame in self.pending.drain(..) {
+            let remaining = end - frame.pts;
+            if remaining < self.fade_out {
+                darken(&mut frame, remaining / self.fade_out);
+            }
+            out.push(frame);
+        }
+        Ok(())
+    }
+}
+
+fn darken(frame: &mut Frame, factor: f64) {
+    let factor = (factor.clamp(0.0, 1.0) * 256.0) as u32;
+    for px in frame.image.pixels_mut() {
+        px.r = ((px.r as u32 * factor) >> 8) as u8;
+        px.g = ((px.g as u32 * factor) >> 8) as u8;
+        px.b = ((px.b as u32 * factor) >> 8) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    use super::*;
+
+    /// Gray frames of `value` at `pts`, run through a fade; returns the red channel and timestamp of each.
+    fn run(mut fade: Fade, value: u8, pts: &[f64]) -> Vec<(u8, f64)> {
+        let mut out = Vec::new();
+        for &pts in pts {
+            let image = ImgVec::new(vec![RGBA8::new(value, value, value, 255)], 1, 1);
+            fade.process(Frame { image, pts }, &mut out).unwrap();
+        }
+        fade.finish(&mut out).unwrap();
+        out.iter().map(|frame| (frame.image.buf()[0].r, frame.pts)).collect()
+    }
+
+    #[test]
+    fn fades_in() {
+        assert_eq!(run(Fade::new(1.0, 0.0), 200, &[0.0, 0.5, 1.0]), [(0, 0.0), (100, 0.5), (200, 1.0)]);
+    }
+
+    #[test]
+    fn fades_out_until_the_end_of_the_last_frame() {
+        // the last frame is shown until 2.5, like the one before it for half a second
+        let out = run(Fade::new(0.0, 1.0), 200, &[0.0, 0.5, 1.0, 1.5, 2.0]);
+        assert_eq!(out, [(200, 0.0), (200, 0.5), (200, 1.0), (200, 1.5), (100, 2.0)]);
+    }
+
+    #[test]
+    fn keeps_alpha() {
+        let mut fade = Fade::new(1.0, 0.0);
+        let mut out = Vec::new();
+        for pts in [0.0, 0.5] {
+            let image = ImgVec::new(vec![RGBA8::new(200, 100, 50, 128)], 1, 1);
+            fade.process(Frame { image, pts }, &mut out).unwrap();
+        }
+        assert_eq!(out[1].image.buf()[0], RGBA8::new(100, 50, 25, 128));
+    }
+}
@@ -0,0 +1,89 @@
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// Drops frames so that at most `fps` are shown per second. The frames that remain keep their timestamps, so each
+/// is shown until the next one that was kept.
+pub struct FpsCap {
+    interval: f64,
+    /// Timestamp from which the next frame is kept.
+    next: Option<f64>,
+}
+
+impl FpsCap {
+    pub fn new(fps: f64) -> Self {
+        Self { interval: 1.0 / fps, next: None }
+    }
+
+    /// Whether the frame shown at `pts` is kept, given the ones before it. [`WebmDecoder`] asks before converting
+    /// the frame, see [`Decoder::with_fps_cap`].
+    ///
+    /// [`WebmDecoder`]: crate::decoder::WebmDecoder
+    /// [`Decoder::with_fps_cap`]: crate::decoder::Decoder::with_fps_cap
+    pub fn keep(&mut self, pts: f64) -> bool {
+        let next = *self.next.get_or_insert(pts);
+        // timestamps are rounded to the time base of the stream, which must not drop frames that are just in time
+        if pts < next - self.interval / 100.0 {
+            return false;
+        }
+        // counting from the last threshold rather than the frame keeps the rate exact when frames don't line up,
+        // unless a gap in the input left it behind
+        self.next = Some((next + self.interval).max(pts + self.interval / 2.0));
+        true
+    }
+
+    /// Forgets the frames kept so far, for timestamps that start over after a seek.
+    pub fn restart(&mut self) {
+        self.next = None;
+    }
+}
+
+impl Filter for FpsCap {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        if self.keep(frame.pts) {
+            out.push(frame);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    use super::*;
+
+    fn kept(fps: f64, pts: impl IntoIterator<Item = f64>) -> Vec<f64> {
+        let mut cap = FpsCap::new(fps);
+        let mut out = Vec::new();
+        for pts in pts {
+            cap.process(Frame { image: ImgVec::new(vec![RGBA8::default()], 1, 1), pts }, &mut out).unwrap();
+        }
+        out.iter().map(|frame| frame.pts).collect()
+    }
+
+    #[test]
+    fn keeps_every_nth_frame() {
+        let pts = (0..10).map(|i| i as f64 / 30.0);
+        assert_eq!(kept(10.0, pts), [0.0, 3.0 / 30.0, 6.0 / 30.0, 9.0 / 30.0]);
+    }
+
+    #[test]
+    fn keeps_the_rate_when_frames_do_not_line_up() {
+        // 25 fps to 10 keeps 2 of every 5 frames, not every third one
+        let pts = (0..10).map(|i| i as f64 * 0.04);
+        assert_eq!(kept(10.0, pts).len(), 4);
+    }
+
+    #[test]
+    fn keeps_frames_rounded_just_early() {
+        assert_eq!(kept(10.0, [0.0, 0.0999, 0.2]), [0.0, 0.0999, 0.2]);
+    }
+
+    #[test]
+    fn keeps_slower_inputs_whole() {
+        let pts = (0..5).map(|i| i as f64 / 5.0).collect::<Vec<_>>();
+        assert_eq!(kept(10.0, pts.iter().copied()), pts);
+    }
+}
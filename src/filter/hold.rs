@@ -0,0 +1,68 @@
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// Keeps the last frame on screen for `duration` seconds longer before the gif ends or loops.
+pub struct HoldLast {
+    duration: f64,
+    last: Option<Frame>,
+    /// Display time of the last frame, taken from the gap to the frame before it.
+    delay: f64,
+}
+
+impl HoldLast {
+    pub fn new(duration: f64) -> Self {
+        Self { duration, last: None, delay: 0.0 }
+    }
+}
+
+impl Filter for HoldLast {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        if let Some(last) = self.last.take() {
+            self.delay = frame.pts - last.pts;
+            out.push(last);
+        }
+        self.last = Some(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let Some(last) = self.last.take() else { return Ok(()) };
+        let total = self.duration + if self.delay > 0.0 { self.delay } else { 0.1 };
+        // the encoder repeats the previous delay for the last frame, so the copy splits the time in half
+        let copy = Frame { image: last.image.clone(), pts: last.pts + total / 2.0 };
+        out.push(last);
+        out.push(copy);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    use super::*;
+
+    fn held(duration: f64, pts: &[f64]) -> Vec<f64> {
+        let mut hold = HoldLast::new(duration);
+        let mut out = Vec::new();
+        for &pts in pts {
+            hold.process(Frame { image: ImgVec::new(vec![RGBA8::default()], 1, 1), pts }, &mut out).unwrap();
+        }
+        hold.finish(&mut out).unwrap();
+        out.iter().map(|frame| frame.pts).collect()
+    }
+
+    #[test]
+    fn repeats_the_last_frame() {
+        // the last frame keeps its own 0.25s plus the second it is held for, split over it and the copy
+        assert_eq!(held(1.0, &[0.0, 0.25, 0.5]), [0.0, 0.25, 0.5, 1.125]);
+    }
+
+    #[test]
+    fn holds_a_single_frame() {
+        assert_eq!(held(1.0, &[2.0]), [2.0, 2.55]);
+        assert!(held(1.0, &[]).is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{ensure, eyre, Context};
+use rgb::ComponentBytes;
+use wasmi::core::F64;
+use wasmi::{Engine, Linker, Memory, Module, Store, TypedFunc};
+
+use super::{Filter, Frame};
+
+/// Runs every frame through a WebAssembly module, which has no access to anything but the frame.
+///
+/// The module has to export:
+/// - `memory`, its linear memory
+/// - `alloc(len: i32) -> i32`, returning the offset of `len` bytes the frames can be copied to; called again
+///   whenever the frame size changes
+/// - `process_frame(rgba: i32, width: i32, height: i32, pts: f64)`, which edits the RGBA pixels at `rgba`
+///   in place; `pts` is the timestamp of the frame in seconds
+pub struct Plugin {
+    path: String,
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    process_frame: TypedFunc<(i32, i32, i32, F64), ()>,
+    /// Offset and length of the buffer returned by `alloc`.
+    buffer: Option<(usize, usize)>,
+}
+
+impl Plugin {
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        let wasm = fs::read(path).wrap_err_with(|| format!("failed to read plugin {path}"))?;
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm.as_slice()).map_err(|e| eyre!("invalid plugin {path}: {e}"))?;
+        let mut store = Store::new(&engine, ());
+        // plugins get no imports, so they cannot touch files or the network
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| eyre!("failed to instantiate plugin {path}: {e}"))?;
+
+        let memory = instance.get_memory(&store, "memory").ok_or_else(|| eyre!("plugin {path} does not export memory"))?;
+        let alloc = instance.get_typed_func(&store, "alloc")
+            .map_err(|e| eyre!("plugin {path} does not export alloc(i32) -> i32: {e}"))?;
+        let process_frame = instance.get_typed_func(&store, "process_frame")
+            .map_err(|e| eyre!("plugin {path} does not export process_frame(i32, i32, i32, f64): {e}"))?;
+        Ok(Self { path: path.to_string(), store, memory, alloc, process_frame, buffer: None })
+    }
+
+    fn buffer(&mut self, len: usize) -> Result<usize> {
+        match self.buffer {
+            Some((offset, allocated)) if allocated == len => Ok(offset),
+            _ => {
+                let offset = self.alloc.call(&mut self.store, i32::try_from(len)?)
+                    .map_err(|e| eyre!("alloc in plugin {} failed: {e}", self.path))?;
+                let offset = usize::try_from(offset)?;
+                ensure!(matches!(offset.checked_add(len), Some(end) if end <= self.memory.data(&self.store).len()),
+                    "alloc in plugin {} returned a buffer outside of its memory", self.path);
+                self.buffer = Some((offset, len));
+                Ok(offset)
+            },
+        }
+    }
+}
+
+impl Filter for Plugin {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let (width, height) = (frame.image.width(), frame.image.height());
+        let row_len = width * 4;
+        let offset = self.buffer(row_len * height)?;
+
+        let data = self.memory.data_mut(&mut self.store);
+        for (y, row) in frame.image.rows().enumerate() {
+            let start = offset + y * row_len;
+            data[start..start + row_len].copy_from_slice(row.as_bytes());
+        }
+        self.process_frame.call(&mut self.store, (offset as i32, width as i32, height as i32, F64::from(frame.pts)))
+            .map_err(|e| eyre!("plugin {} failed on the frame at {:.3}s: {e}", self.path, frame.pts))?;
+
+        // the plugin may have grown its memory, which can move it
+        let data = self.memory.data(&self.store);
+        for (y, row) in frame.image.rows_mut().enumerate() {
+            let start = offset + y * row_len;
+            row.as_bytes_mut().copy_from_slice(&data[start..start + row_len]);
+        }
+        out.push(frame);
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use imgref::{ImgRef, ImgVec};
+use rgb::RGBA8;
+
+/// Bilinearly resamples the (possibly fractional) source rectangle at `left`, `top` into a `width` x `height` image.
+pub fn resample_bilinear(src: ImgRef<RGBA8>, left: f32, top: f32, src_width: f32, src_height: f32, width: usize, height: usize) -> ImgVec<RGBA8> {
+    let max_x = src.width() as f32 - 1.0;
+    let max_y = src.height() as f32 - 1.0;
+    let step_x = src_width / width as f32;
+    let step_y = src_height / height as f32;
+    let buf = src.buf();
+    let stride = src.stride();
+
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let sy = (top + (y as f32 + 0.5) * step_y - 0.5).clamp(0.0, max_y);
+        let y0 = sy as usize;
+        let y1 = (y0 + 1).min(max_y as usize);
+        let fy = sy - y0 as f32;
+        for x in 0..width {
+            let sx = (left + (x as f32 + 0.5) * step_x - 0.5).clamp(0.0, max_x);
+            let x0 = sx as usize;
+            let x1 = (x0 + 1).min(max_x as usize);
+            let fx = sx - x0 as f32;
+
+            let lerp = |a: RGBA8, b: RGBA8, t: f32| [
+                a.r as f32 + (b.r as f32 - a.r as f32) * t,
+                a.g as f32 + (b.g as f32 - a.g as f32) * t,
+                a.b as f32 + (b.b as f32 - a.b as f32) * t,
+                a.a as f32 + (b.a as f32 - a.a as f32) * t,
+            ];
+            let upper = lerp(buf[y0 * stride + x0], buf[y0 * stride + x1], fx);
+            let lower = lerp(buf[y1 * stride + x0], buf[y1 * stride + x1], fx);
+            let channel = |i: usize| (upper[i] + (lower[i] - upper[i]) * fy + 0.5) as u8;
+            out.push(RGBA8::new(channel(0), channel(1), channel(2), channel(3)));
+        }
+    }
+    ImgVec::new(out, width, height)
+}
+
+/// Scales `image` to fit into `width` x `height` keeping its aspect ratio.
+pub fn resize_to_fit(image: ImgRef<RGBA8>, width: usize, height: usize) -> ImgVec<RGBA8> {
+    let scale = (width as f32 / image.width() as f32).min(height as f32 / image.height() as f32);
+    let fit_width = ((image.width() as f32 * scale).round() as usize).clamp(1, width);
+    let fit_height = ((image.height() as f32 * scale).round() as usize).clamp(1, height);
+    resample_bilinear(image, 0.0, 0.0, image.width() as f32, image.height() as f32, fit_width, fit_height)
+}
+
+/// Scales `image` to fit into `width` x `height` and centers it on a black background.
+pub fn letterbox(image: ImgRef<RGBA8>, width: usize, height: usize) -> ImgVec<RGBA8> {
+    let image = resize_to_fit(image, width, height);
+    let mut canvas = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); width * height], width, height);
+    let left = (width - image.width()) / 2;
+    let top = (height - image.height()) / 2;
+    for (dst, src) in canvas.sub_image_mut(left, top, image.width(), image.height()).rows_mut().zip(image.rows()) {
+        dst.copy_from_slice(src);
+    }
+    canvas
+}
@@ -0,0 +1,96 @@
+use std::mem;
+
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// Plays the clip backwards, or forwards and then backwards for a boomerang. Keeps all frames in memory until the
+/// end of the input.
+pub struct Reverse {
+    boomerang: bool,
+    frames: Vec<Frame>,
+}
+
+impl Reverse {
+    pub fn new(boomerang: bool) -> Self {
+        Self { boomerang, frames: Vec::new() }
+    }
+}
+
+impl Filter for Reverse {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        if self.boomerang {
+            out.push(frame.clone());
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let frames = mem::take(&mut self.frames);
+        let Some(first) = frames.first() else { return Ok(()) };
+        // how long each frame is shown, the last one as long as the one before it like the encoder does
+        let mut durations = frames.windows(2).map(|pair| pair[1].pts - pair[0].pts).collect::<Vec<_>>();
+        durations.push(durations.last().copied().unwrap_or(0.1));
+
+        let (mut pts, backwards) = if self.boomerang {
+            // the turning points are shown once, and the first frame again when the gif loops
+            let last = frames.len() - 1;
+            (frames[last].pts + durations[last], 1..last.max(1))
+        } else {
+            (first.pts, 0..frames.len())
+        };
+        for (mut frame, duration) in frames.into_iter().zip(durations).skip(backwards.start).take(backwards.len()).rev() {
+            frame.pts = pts;
+            pts += duration;
+            out.push(frame);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    use super::*;
+
+    /// Frames at `pts`, told apart by the red channel of their single pixel, which is their index.
+    fn run(reverse: &mut Reverse, pts: &[f64]) -> (Vec<Frame>, Vec<Frame>) {
+        let mut forward = Vec::new();
+        for (i, &pts) in pts.iter().enumerate() {
+            let image = ImgVec::new(vec![RGBA8::new(i as u8, 0, 0, 255)], 1, 1);
+            reverse.process(Frame { image, pts }, &mut forward).unwrap();
+        }
+        let mut finished = Vec::new();
+        reverse.finish(&mut finished).unwrap();
+        (forward, finished)
+    }
+
+    fn indices_and_pts(frames: &[Frame]) -> Vec<(u8, f64)> {
+        frames.iter().map(|frame| (frame.image.buf()[0].r, frame.pts)).collect()
+    }
+
+    #[test]
+    fn reverses_keeping_durations() {
+        let (forward, finished) = run(&mut Reverse::new(false), &[1.0, 1.25, 1.75]);
+        assert!(forward.is_empty());
+        // each frame keeps how long it was shown, the last one as long as the one before it
+        assert_eq!(indices_and_pts(&finished), [(2, 1.0), (1, 1.5), (0, 2.0)]);
+    }
+
+    #[test]
+    fn boomerang_returns_without_repeating_the_ends() {
+        let (forward, finished) = run(&mut Reverse::new(true), &[0.0, 0.25, 0.5, 1.0]);
+        assert_eq!(indices_and_pts(&forward), [(0, 0.0), (1, 0.25), (2, 0.5), (3, 1.0)]);
+        assert_eq!(indices_and_pts(&finished), [(2, 1.5), (1, 2.0)]);
+    }
+
+    #[test]
+    fn handles_short_inputs() {
+        assert!(run(&mut Reverse::new(false), &[]).1.is_empty());
+        assert_eq!(indices_and_pts(&run(&mut Reverse::new(false), &[3.0]).1), [(0, 3.0)]);
+        assert!(run(&mut Reverse::new(true), &[3.0]).1.is_empty());
+    }
+}
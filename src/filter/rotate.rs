@@ -0,0 +1,53 @@
+use color_eyre::Result;
+use imgref::ImgVec;
+
+use super::{Filter, Frame};
+use crate::decoder::StreamInfo;
+
+/// How decoded frames have to be turned to be shown the way the display matrix of the stream says.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Orientation {
+    /// Clockwise, from 0 to 3.
+    pub quarter_turns: u8,
+    /// Flip horizontally before turning.
+    pub mirrored: bool,
+}
+
+impl Orientation {
+    /// Rotations that are not a multiple of 90 degrees are rounded to the nearest one.
+    pub fn from_info(info: &StreamInfo) -> Self {
+        Self { quarter_turns: ((info.rotation / 90.0).round() as u8) % 4, mirrored: info.mirrored }
+    }
+
+    pub fn is_upright(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub struct Rotate(pub Orientation);
+
+impl Filter for Rotate {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let Orientation { quarter_turns, mirrored } = self.0;
+        let image = frame.image.as_ref();
+        let (width, height) = (image.width(), image.height());
+        let (new_width, new_height) = if quarter_turns % 2 == 1 { (height, width) } else { (width, height) };
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..new_height {
+            for x in 0..new_width {
+                // the pixel of the decoded frame that ends up at (x, y)
+                let (sx, sy) = match quarter_turns {
+                    0 => (x, y),
+                    1 => (y, height - 1 - x),
+                    2 => (width - 1 - x, height - 1 - y),
+                    _ => (width - 1 - y, x),
+                };
+                let sx = if mirrored { width - 1 - sx } else { sx };
+                pixels.push(image[(sx, sy)]);
+            }
+        }
+        out.push(Frame { image: ImgVec::new(pixels, new_width, new_height), pts: frame.pts });
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{bail, eyre};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use super::skip_blank::luma_stats;
+use super::{resample_bilinear, Filter, Frame};
+
+/// Lets a rhai script decide per frame whether to keep it, how long to show it and what part of it to show.
+///
+/// The script defines `fn frame(info)`, where `info` is a map with `index`, `pts` (seconds), `width`, `height`,
+/// `brightness` and `deviation` (mean and standard deviation of the luma, 0-255) and `file`, a map with `path`
+/// (empty for grids and recordings) and `duration` (seconds). It returns:
+/// - nothing or `true` to keep the frame, `false` to drop it
+/// - or a map with any of `skip` (bool), `delay` (seconds the frame stays on screen) and `crop` (`[x, y, width,
+///   height]` in pixels, scaled back to the frame size)
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    file: Map,
+    index: i64,
+    /// The last kept frame with its original timestamp and requested delay, held back until the next frame shows
+    /// how long it was meant to last.
+    pending: Option<(Frame, f64, Option<f64>)>,
+    /// Added to the timestamps because of the delays changed so far.
+    shift: f64,
+}
+
+/// What the script decided for a frame.
+struct Decision {
+    skip: bool,
+    delay: Option<f64>,
+    crop: Option<[f32; 4]>,
+}
+
+impl Script {
+    /// `input` is the converted file and `duration` the expected length of the clip in seconds.
+    pub fn load(path: &Utf8Path, input: Option<&Utf8Path>, duration: f64) -> Result<Self> {
+        let engine = Engine::new();
+        let ast = engine.compile_file(path.into()).map_err(|e| eyre!("failed to load script {path}: {e}"))?;
+        if !ast.iter_functions().any(|f| f.name == "frame" && f.params.len() == 1) {
+            bail!("script {path} does not define fn frame(info)");
+        }
+        let mut file = Map::new();
+        file.insert("path".into(), input.map_or("", Utf8Path::as_str).into());
+        file.insert("duration".into(), duration.into());
+        Ok(Self { engine, ast, file, index: 0, pending: None, shift: 0.0 })
+    }
+
+    fn decide(&mut self, frame: &Frame) -> Result<Decision> {
+        let (brightness, deviation) = luma_stats(frame.image.as_ref());
+        let mut info = Map::new();
+        info.insert("index".into(), self.index.into());
+        info.insert("pts".into(), frame.pts.into());
+        info.insert("width".into(), (frame.image.width() as i64).into());
+        info.insert("height".into(), (frame.image.height() as i64).into());
+        info.insert("brightness".into(), brightness.into());
+        info.insert("deviation".into(), deviation.into());
+        info.insert("file".into(), self.file.clone().into());
+        self.index += 1;
+
+        let result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, "frame", (info,))
+            .map_err(|e| eyre!("script failed on the frame at {:.3}s: {e}", frame.pts))?;
+        let mut decision = Decision { skip: false, delay: None, crop: None };
+        if result.is_unit() {
+            return Ok(decision);
+        }
+        if let Ok(keep) = result.as_bool() {
+            decision.skip = !keep;
+            return Ok(decision);
+        }
+        let Some(map) = result.try_cast::<Map>() else {
+            bail!("fn frame must return nothing, a bool or a map");
+        };
+        for (key, value) in map {
+            match key.as_str() {
+                "skip" => decision.skip = value.as_bool().map_err(|_| eyre!("skip must be a bool"))?,
+                "delay" => decision.delay = Some(number(&value).filter(|d| *d >= 0.0)
+                    .ok_or_else(|| eyre!("delay must be a number of seconds"))?),
+                "crop" => {
+                    let crop = value.try_cast::<rhai::Array>()
+                        .and_then(|values| values.iter().map(number).collect::<Option<Vec<_>>>())
+                        .filter(|values| values.len() == 4 && values[2] > 0.0 && values[3] > 0.0)
+                        .ok_or_else(|| eyre!("crop must be [x, y, width, height]"))?;
+                    decision.crop = Some([crop[0] as f32, crop[1] as f32, crop[2] as f32, crop[3] as f32]);
+                },
+                _ => bail!("unknown key {key} in the map returned by fn frame, expected skip, delay or crop"),
+            }
+        }
+        Ok(decision)
+    }
+}
+
+fn number(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|i| i as f64))
+}
+
+impl Filter for Script {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let decision = self.decide(&frame)?;
+        if decision.skip {
+            return Ok(());
+        }
+        let pts = frame.pts;
+        if let Some((mut previous, previous_pts, delay)) = self.pending.take() {
+            previous.pts = previous_pts + self.shift;
+            if let Some(delay) = delay {
+                self.shift += delay - (pts - previous_pts);
+            }
+            out.push(previous);
+        }
+        if let Some([x, y, width, height]) = decision.crop {
+            let (frame_width, frame_height) = (frame.image.width(), frame.image.height());
+            let width = width.min(frame_width as f32);
+            let height = height.min(frame_height as f32);
+            let x = x.clamp(0.0, frame_width as f32 - width);
+            let y = y.clamp(0.0, frame_height as f32 - height);
+            frame.image = resample_bilinear(frame.image.as_ref(), x, y, width, height, frame_width, frame_height);
+        }
+        self.pending = Some((frame, pts, decision.delay));
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        if let Some((mut last, pts, _)) = self.pending.take() {
+            last.pts = pts + self.shift;
+            out.push(last);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+use std::fmt;
+use std::str::FromStr;
+
+use color_eyre::Result;
+use imgref::ImgRef;
+use rgb::RGBA8;
+
+use super::{Filter, Frame};
+
+/// Variables an expression can refer to, in the order their values are passed to `Expr::eval`.
+const VARIABLES: &[&str] = &["n", "t", "scene", "selected_n"];
+const SCENE: usize = 2;
+
+#[derive(Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone)]
+enum Expr {
+    Number(f64),
+    Variable(usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(&'static str, Vec<Expr>),
+}
+
+/// Functions with their number of arguments, named like in ffmpeg expressions.
+const FUNCTIONS: &[(&str, usize)] = &[
+    ("not", 1),
+    ("mod", 2),
+    ("eq", 2),
+    ("gt", 2),
+    ("gte", 2),
+    ("lt", 2),
+    ("lte", 2),
+    ("between", 3),
+    ("if", 3),
+    ("abs", 1),
+    ("min", 2),
+    ("max", 2),
+    ("floor", 1),
+    ("ceil", 1),
+    ("round", 1),
+];
+
+/// A per-frame condition like `not(mod(n,2)) and gte(t,1.5)`.
+///
+/// Supports numbers, the variables `n` (index of the decoded frame), `t` (seconds since the first frame), `scene`
+/// (0-1, how much the frame differs from the previous one) and `selected_n` (frames selected so far), the operators
+/// `+ - * / % < <= > >= == != and or not` and the functions of ffmpeg's select filter listed in `FUNCTIONS`.
+/// Non-zero values count as true.
+#[derive(Clone)]
+pub struct SelectExpr {
+    expr: Expr,
+    uses_scene: bool,
+}
+
+impl FromStr for SelectExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected {token} in {s:?}"));
+        }
+        let uses_scene = expr.uses(SCENE);
+        Ok(Self { expr, uses_scene })
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Ident(name) => write!(f, "{name:?}"),
+            Token::Symbol(symbol) => write!(f, "{symbol:?}"),
+        }
+    }
+}
+
+const SYMBOLS: &[&str] = &["<=", ">=", "==", "!=", "(", ")", ",", "+", "-", "*", "/", "%", "<", ">"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() || c == '.' {
+            let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+            let number = rest[..end].parse().map_err(|_| format!("invalid number {:?}", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(format!("unexpected character {c:?}"));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_is(&self, token: &Token) -> bool {
+        self.tokens.get(self.pos) == Some(token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matched = self.peek_is(token);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        if self.eat(&Token::Symbol(symbol)) {
+            return Ok(());
+        }
+        match self.tokens.get(self.pos) {
+            Some(token) => Err(format!("expected {symbol:?}, got {token}")),
+            None => Err(format!("expected {symbol:?} at the end")),
+        }
+    }
+
+    /// Parses operands of `next` joined by any of `ops`, left to right.
+    fn binary(&mut self, ops: &[(Token, Op)], next: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        let mut left = next(self)?;
+        'outer: loop {
+            for (token, op) in ops {
+                if self.eat(token) {
+                    left = Expr::Binary(*op, Box::new(left), Box::new(next(self)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[(Token::Ident("or".into()), Op::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[(Token::Ident("and".into()), Op::And)], Self::not)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        // `not(x)` is the function, which binds tighter
+        let keyword = self.peek_is(&Token::Ident("not".into()))
+            && self.tokens.get(self.pos + 1) != Some(&Token::Symbol("("));
+        if keyword {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(&[
+            (Token::Symbol("<="), Op::Le),
+            (Token::Symbol(">="), Op::Ge),
+            (Token::Symbol("=="), Op::Eq),
+            (Token::Symbol("!="), Op::Ne),
+            (Token::Symbol("<"), Op::Lt),
+            (Token::Symbol(">"), Op::Gt),
+        ], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[(Token::Symbol("+"), Op::Add), (Token::Symbol("-"), Op::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(&[(Token::Symbol("*"), Op::Mul), (Token::Symbol("/"), Op::Div), (Token::Symbol("%"), Op::Mod)],
+            Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat(&Token::Symbol("-")) {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("unexpected end of the expression")?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            Token::Ident(name) if self.eat(&Token::Symbol("(")) => {
+                let &(name, arity) = FUNCTIONS.iter().find(|(f, _)| *f == name)
+                    .ok_or_else(|| format!("unknown function {name:?}"))?;
+                let mut args = Vec::new();
+                if !self.eat(&Token::Symbol(")")) {
+                    loop {
+                        args.push(self.or()?);
+                        if self.eat(&Token::Symbol(")")) {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                if args.len() != arity {
+                    return Err(format!("{name} takes {arity} arguments, got {}", args.len()));
+                }
+                Ok(Expr::Call(name, args))
+            },
+            Token::Ident(name) => VARIABLES.iter().position(|v| *v == name).map(Expr::Variable)
+                .ok_or_else(|| format!("unknown variable {name:?}, expected one of {}", VARIABLES.join(", "))),
+            token => Err(format!("unexpected {token}")),
+        }
+    }
+}
+
+fn truth(b: bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+}
+
+impl Expr {
+    /// Whether the variable at `index` in `VARIABLES` appears anywhere in the expression.
+    fn uses(&self, index: usize) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Variable(i) => *i == index,
+            Expr::Neg(e) | Expr::Not(e) => e.uses(index),
+            Expr::Binary(_, a, b) => a.uses(index) || b.uses(index),
+            Expr::Call(_, args) => args.iter().any(|e| e.uses(index)),
+        }
+    }
+
+    fn eval(&self, vars: &[f64; 4]) -> f64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(i) => vars[*i],
+            Expr::Neg(e) => -e.eval(vars),
+            Expr::Not(e) => truth(e.eval(vars) == 0.0),
+            Expr::Binary(Op::And, a, b) => truth(a.eval(vars) != 0.0 && b.eval(vars) != 0.0),
+            Expr::Binary(Op::Or, a, b) => truth(a.eval(vars) != 0.0 || b.eval(vars) != 0.0),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(vars), b.eval(vars));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Mod => a % b,
+                    Op::Lt => truth(a < b),
+                    Op::Le => truth(a <= b),
+                    Op::Gt => truth(a > b),
+                    Op::Ge => truth(a >= b),
+                    Op::Eq => truth(a == b),
+                    Op::Ne => truth(a != b),
+                    Op::And | Op::Or => unreachable!(),
+                }
+            },
+            Expr::Call(name, args) => {
+                let arg = |i: usize| args[i].eval(vars);
+                match *name {
+                    "not" => truth(arg(0) == 0.0),
+                    "mod" => arg(0) % arg(1),
+                    "eq" => truth(arg(0) == arg(1)),
+                    "gt" => truth(arg(0) > arg(1)),
+                    "gte" => truth(arg(0) >= arg(1)),
+                    "lt" => truth(arg(0) < arg(1)),
+                    "lte" => truth(arg(0) <= arg(1)),
+                    "between" => truth(arg(0) >= arg(1) && arg(0) <= arg(2)),
+                    "if" => if arg(0) != 0.0 { arg(1) } else { arg(2) },
+                    "abs" => arg(0).abs(),
+                    "min" => arg(0).min(arg(1)),
+                    "max" => arg(0).max(arg(1)),
+                    "floor" => arg(0).floor(),
+                    "ceil" => arg(0).ceil(),
+                    "round" => arg(0).round(),
+                    _ => unreachable!(),
+                }
+            },
+        }
+    }
+}
+
+/// Keeps only the frames for which an expression is true. The kept frames keep their timestamps, so each is shown
+/// until the next kept one.
+pub struct Select {
+    expr: SelectExpr,
+    index: u64,
+    selected: u64,
+    first_pts: Option<f64>,
+    /// Luma of the previous frame, only computed if the expression uses `scene`.
+    previous_luma: Option<Vec<u8>>,
+}
+
+impl Select {
+    pub fn new(expr: SelectExpr) -> Self {
+        Self { expr, index: 0, selected: 0, first_pts: None, previous_luma: None }
+    }
+
+    fn scene_score(&mut self, image: ImgRef<RGBA8>) -> f64 {
+        let luma = image.pixels()
+            .map(|px| ((px.r as u32 * 299 + px.g as u32 * 587 + px.b as u32 * 114) / 1000) as u8)
+            .collect::<Vec<_>>();
+        let score = match &self.previous_luma {
+            Some(previous) if previous.len() == luma.len() && !luma.is_empty() => {
+                let diff = previous.iter().zip(&luma).map(|(a, b)| a.abs_diff(*b) as u64).sum::<u64>();
+                diff as f64 / luma.len() as f64 / 255.0
+            },
+            // the first frame, or one of a different size, starts a new scene
+            _ => 1.0,
+        };
+        self.previous_luma = Some(luma);
+        score
+    }
+}
+
+impl Filter for Select {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        let scene = if self.expr.uses_scene { self.scene_score(frame.image.as_ref()) } else { 0.0 };
+        let vars = [self.index as f64, frame.pts - first_pts, scene, self.selected as f64];
+        self.index += 1;
+        if self.expr.expr.eval(&vars) != 0.0 {
+            self.selected += 1;
+            out.push(frame);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+
+    use super::*;
+
+    /// Evaluates `s` with `n`, `t`, `scene` and `selected_n` set to `vars`.
+    fn eval(s: &str, vars: [f64; 4]) -> f64 {
+        s.parse::<SelectExpr>().unwrap().expr.eval(&vars)
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<SelectExpr>().err().unwrap()
+    }
+
+    #[test]
+    fn tokenizes() {
+        let tokens = tokenize("gte(t,1.5)<=n_2").unwrap();
+        assert!(tokens == [
+            Token::Ident("gte".into()),
+            Token::Symbol("("),
+            Token::Ident("t".into()),
+            Token::Symbol(","),
+            Token::Number(1.5),
+            Token::Symbol(")"),
+            Token::Symbol("<="),
+            Token::Ident("n_2".into()),
+        ]);
+        assert!(tokenize("  ").unwrap().is_empty());
+        assert_eq!(tokenize("1.2.3").err().unwrap(), "invalid number \"1.2.3\"");
+        assert_eq!(tokenize("n & 1").err().unwrap(), "unexpected character '&'");
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        assert_eq!(eval("1 + 2 * 3", [0.0; 4]), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", [0.0; 4]), 9.0);
+        assert_eq!(eval("10 - 4 - 3", [0.0; 4]), 3.0);
+        assert_eq!(eval("-2 * 3 + 10 % 4", [0.0; 4]), -4.0);
+        assert_eq!(eval("1 + 2 < 4 and 2 * 2 == 4", [0.0; 4]), 1.0);
+    }
+
+    #[test]
+    fn logic() {
+        let expr = "not(mod(n,2)) and gte(t,1.5)";
+        assert_eq!(eval(expr, [4.0, 2.0, 0.0, 0.0]), 1.0);
+        assert_eq!(eval(expr, [3.0, 2.0, 0.0, 0.0]), 0.0);
+        assert_eq!(eval(expr, [4.0, 1.0, 0.0, 0.0]), 0.0);
+        assert_eq!(eval("n < 1 or n > 5", [6.0, 0.0, 0.0, 0.0]), 1.0);
+        assert_eq!(eval("n < 1 or n > 5", [3.0, 0.0, 0.0, 0.0]), 0.0);
+        // the keyword covers the comparison, the function only its argument
+        assert_eq!(eval("not n == 1", [1.0, 0.0, 0.0, 0.0]), 0.0);
+        assert_eq!(eval("not(n) + 1", [0.0, 0.0, 0.0, 0.0]), 2.0);
+        assert_eq!(eval("not not n", [5.0, 0.0, 0.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn functions() {
+        let vars = [3.0, 0.25, 0.5, 2.0];
+        assert_eq!(eval("between(n, 1, 3)", vars), 1.0);
+        assert_eq!(eval("if(gt(scene, 0.4), 10, 20)", vars), 10.0);
+        assert_eq!(eval("min(n, selected_n) + max(n, selected_n)", vars), 5.0);
+        assert_eq!(eval("abs(-t) + floor(2.7) + ceil(2.2) + round(2.5)", vars), 8.25);
+        assert_eq!(eval("eq(n, 3) + lt(t, 1) + lte(n, 3) + gte(n, 4)", vars), 3.0);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert_eq!(error("x > 1"), "unknown variable \"x\", expected one of n, t, scene, selected_n");
+        assert_eq!(error("foo(1)"), "unknown function \"foo\"");
+        assert_eq!(error("mod(n)"), "mod takes 2 arguments, got 1");
+        assert_eq!(error("(n"), "expected \")\" at the end");
+        assert_eq!(error("n )"), "unexpected \")\" in \"n )\"");
+        assert_eq!(error("n +"), "unexpected end of the expression");
+    }
+
+    #[test]
+    fn notes_scene_use() {
+        assert!("scene > 0.3 or n == 0".parse::<SelectExpr>().unwrap().uses_scene);
+        assert!(!"n > 0.3".parse::<SelectExpr>().unwrap().uses_scene);
+    }
+
+    #[test]
+    fn counts_frames() {
+        let mut select = Select::new("selected_n < 2 and t >= 0.1".parse().unwrap());
+        let mut out = Vec::new();
+        for i in 0..5 {
+            let frame = Frame { image: ImgVec::new(vec![RGBA8::default()], 1, 1), pts: 1.0 + i as f64 / 10.0 };
+            select.process(frame, &mut out).unwrap();
+        }
+        // t counts from the first frame
+        assert_eq!(out.iter().map(|frame| frame.pts).collect::<Vec<_>>(), [1.1, 1.2]);
+    }
+}
@@ -0,0 +1,51 @@
+use color_eyre::Result;
+use imgref::ImgRef;
+use rgb::RGBA8;
+
+use super::{Filter, Frame};
+
+/// Drops near-uniform frames at the start of the clip, such as the black frames many screen recorders begin with,
+/// and moves the remaining frames forward so that the clip starts on the first frame with content.
+pub struct SkipBlankStart {
+    /// Maximum standard deviation of the luma for a frame to count as blank.
+    threshold: f64,
+    first_pts: Option<f64>,
+    /// Amount subtracted from the timestamps, known once the first frame with content arrives.
+    shift: Option<f64>,
+}
+
+impl SkipBlankStart {
+    pub fn new(threshold: f64) -> Self {
+        Self { threshold, first_pts: None, shift: None }
+    }
+}
+
+impl Filter for SkipBlankStart {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        let shift = match self.shift {
+            Some(shift) => shift,
+            None if luma_stats(frame.image.as_ref()).1 <= self.threshold => return Ok(()),
+            None => *self.shift.insert(frame.pts - first_pts),
+        };
+        frame.pts -= shift;
+        out.push(frame);
+        Ok(())
+    }
+}
+
+/// Mean and standard deviation of the luma, from 0 to 255.
+pub(super) fn luma_stats(image: ImgRef<RGBA8>) -> (f64, f64) {
+    let (mut sum, mut sum_sq, mut count) = (0u64, 0u64, 0u64);
+    for px in image.pixels() {
+        let luma = (px.r as u64 * 299 + px.g as u64 * 587 + px.b as u64 * 114) / 1000;
+        sum += luma;
+        sum_sq += luma * luma;
+        count += 1;
+    }
+    if count == 0 {
+        return (0.0, 0.0);
+    }
+    let mean = sum as f64 / count as f64;
+    (mean, (sum_sq as f64 / count as f64 - mean * mean).max(0.0).sqrt())
+}
@@ -0,0 +1,24 @@
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+
+/// Plays the whole clip `speed` times as fast, counting from the first frame.
+pub struct Speed {
+    speed: f64,
+    first_pts: Option<f64>,
+}
+
+impl Speed {
+    pub fn new(speed: f64) -> Self {
+        Self { speed, first_pts: None }
+    }
+}
+
+impl Filter for Speed {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        frame.pts = first_pts + (frame.pts - first_pts) / self.speed;
+        out.push(frame);
+        Ok(())
+    }
+}
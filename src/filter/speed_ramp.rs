@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use color_eyre::Result;
+
+use super::{Filter, Frame};
+use crate::cli::parse_duration;
+
+#[derive(Clone, Copy)]
+struct Segment {
+    start: f64,
+    end: Option<f64>,
+    speed: f64,
+}
+
+#[derive(Clone)]
+pub struct SpeedRampSpec {
+    segments: Vec<Segment>,
+}
+
+/// Parses comma separated `START-END:SPEED` segments, e.g. `0-2s:1.0,2-4s:0.25,4s-:1.0`.
+/// The end of the last segment may be left open; time outside of any segment plays at normal speed.
+impl FromStr for SpeedRampSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let (range, speed) = part.rsplit_once(':').ok_or_else(|| format!("expected START-END:SPEED, got {part:?}"))?;
+            let (start, end) = range.split_once('-').ok_or_else(|| format!("expected a time range like 2-4s, got {range:?}"))?;
+            let start = parse_duration(start.trim())?.as_secs_f64();
+            let end = match end.trim() {
+                "" => None,
+                end => Some(parse_duration(end)?.as_secs_f64()),
+            };
+            let speed = speed.trim().parse::<f64>().ok().filter(|s| s.is_finite() && *s > 0.0)
+                .ok_or_else(|| format!("invalid speed {speed:?} in {part:?}, expected a positive number"))?;
+            if end.map_or(false, |end| end <= start) {
+                return Err(format!("empty time range in {part:?}"));
+            }
+            segments.push(Segment { start, end, speed });
+        }
+
+        segments.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap_or_else(|| unreachable!()));
+        for pair in segments.windows(2) {
+            match pair[0].end {
+                Some(end) if end <= pair[1].start => {},
+                _ => return Err(format!("speed ramp segments starting at {}s and {}s overlap", pair[0].start, pair[1].start)),
+            }
+        }
+        Ok(Self { segments })
+    }
+}
+
+impl SpeedRampSpec {
+    /// Maps a time in the input to the time in the output.
+    fn remap(&self, t: f64) -> f64 {
+        let mut pos = 0.0;
+        let mut out = 0.0;
+        for segment in &self.segments {
+            if t <= segment.start {
+                break;
+            }
+            out += segment.start - pos;
+            let end = segment.end.map_or(t, |end| end.min(t));
+            out += (end - segment.start) / segment.speed;
+            pos = end;
+        }
+        out + (t - pos).max(0.0)
+    }
+}
+
+/// Changes the playback speed of parts of the clip by remapping the timestamps.
+pub struct SpeedRamp {
+    spec: SpeedRampSpec,
+    first_pts: Option<f64>,
+}
+
+impl SpeedRamp {
+    pub fn new(spec: SpeedRampSpec) -> Self {
+        Self { spec, first_pts: None }
+    }
+}
+
+impl Filter for SpeedRamp {
+    fn process(&mut self, mut frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        frame.pts = first_pts + self.spec.remap(frame.pts - first_pts);
+        out.push(frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remap(spec: &str, t: f64) -> f64 {
+        spec.parse::<SpeedRampSpec>().unwrap().remap(t)
+    }
+
+    #[test]
+    fn remaps_segments() {
+        let spec = "0-2s:1.0,2-4s:0.5,4s-:2";
+        assert_eq!(remap(spec, 1.0), 1.0);
+        assert_eq!(remap(spec, 3.0), 4.0);
+        assert_eq!(remap(spec, 4.0), 6.0);
+        assert_eq!(remap(spec, 6.0), 7.0);
+    }
+
+    #[test]
+    fn plays_gaps_at_normal_speed() {
+        assert_eq!(remap("1-2s:0.5", 0.5), 0.5);
+        assert_eq!(remap("1-2s:0.5", 1.5), 2.0);
+        assert_eq!(remap("1-2s:0.5", 3.0), 4.0);
+        assert_eq!(remap("2-3s:2, 0-1s:0.5", 4.0), 4.5);
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        for spec in ["0-2s", "2s:1", "2-1s:1", "0-2s:0", "0-2s:fast", "0-3s:1,2-4s:1", "0-:1,2-3s:1"] {
+            assert!(spec.parse::<SpeedRampSpec>().is_err(), "{spec}");
+        }
+    }
+}
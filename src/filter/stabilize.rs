@@ -0,0 +1,145 @@
+use color_eyre::Result;
+use imgref::{ImgRef, ImgVec};
+use rgb::RGBA8;
+
+use super::{Filter, Frame};
+
+/// Width of the downscaled luma thumbnails used for motion estimation.
+const THUMB_SIZE: usize = 160;
+/// Maximum motion between two consecutive frames, in thumbnail pixels.
+const SEARCH_RANGE: isize = 8;
+/// Number of frames on each side averaged when smoothing the camera path.
+const SMOOTHING_RADIUS: usize = 15;
+/// Upper bound of the border cropped away to hide the shifted edges, as a fraction of the frame size.
+const MAX_MARGIN: f32 = 0.1;
+
+/// Removes camera shake by estimating the global translation between frames, smoothing the resulting
+/// camera path and shifting every frame onto it.
+///
+/// This is a two-pass filter: all frames are kept in memory until the input ends.
+#[derive(Default)]
+pub struct Stabilize {
+    frames: Vec<Frame>,
+    prev: Option<Thumb>,
+    /// Accumulated content position of each frame relative to the first one, in full-size pixels.
+    path: Vec<(f32, f32)>,
+}
+
+impl Filter for Stabilize {
+    fn process(&mut self, frame: Frame, _out: &mut Vec<Frame>) -> Result<()> {
+        let thumb = Thumb::new(frame.image.as_ref());
+        let pos = match (&self.prev, self.path.last()) {
+            (Some(prev), Some(&(x, y))) => {
+                let (dx, dy) = thumb.offset_from(prev);
+                (x + dx * thumb.scale as f32, y + dy * thumb.scale as f32)
+            },
+            _ => (0.0, 0.0),
+        };
+        self.path.push(pos);
+        self.prev = Some(thumb);
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<Frame>) -> Result<()> {
+        let Some(first) = self.frames.first() else { return Ok(()) };
+        let (width, height) = (first.image.width(), first.image.height());
+
+        let corrections = self.path.iter().enumerate().map(|(i, &(x, y))| {
+            let window = &self.path[i.saturating_sub(SMOOTHING_RADIUS)..(i + SMOOTHING_RADIUS + 1).min(self.path.len())];
+            let (sx, sy) = window.iter().fold((0.0, 0.0), |(ax, ay), &(x, y)| (ax + x, ay + y));
+            let n = window.len() as f32;
+            ((sx / n - x).round() as isize, (sy / n - y).round() as isize)
+        }).collect::<Vec<_>>();
+
+        let max_x = (width as f32 * MAX_MARGIN) as isize;
+        let max_y = (height as f32 * MAX_MARGIN) as isize;
+        let margin_x = corrections.iter().map(|c| c.0.abs()).max().unwrap_or(0).min(max_x);
+        let margin_y = corrections.iter().map(|c| c.1.abs()).max().unwrap_or(0).min(max_y);
+        let out_width = width - 2 * margin_x as usize;
+        let out_height = height - 2 * margin_y as usize;
+
+        for (frame, (cx, cy)) in self.frames.drain(..).zip(corrections) {
+            let left = (margin_x - cx.clamp(-margin_x, margin_x)) as usize;
+            let top = (margin_y - cy.clamp(-margin_y, margin_y)) as usize;
+            let (buf, w, h) = frame.image.sub_image(left, top, out_width, out_height).to_contiguous_buf();
+            out.push(Frame {
+                image: ImgVec::new(buf.into_owned(), w, h),
+                pts: frame.pts,
+            });
+        }
+        self.path.clear();
+        self.prev = None;
+        Ok(())
+    }
+}
+
+struct Thumb {
+    luma: Vec<i32>,
+    width: usize,
+    height: usize,
+    scale: usize,
+}
+
+impl Thumb {
+    fn new(image: ImgRef<RGBA8>) -> Self {
+        let scale = (image.width().max(image.height()) / THUMB_SIZE).max(1);
+        let width = image.width() / scale;
+        let height = image.height() / scale;
+
+        let mut luma = vec![0; width * height];
+        for (y, row) in image.rows().take(height * scale).enumerate() {
+            let thumb_row = &mut luma[(y / scale) * width..][..width];
+            for (x, px) in row.iter().take(width * scale).enumerate() {
+                thumb_row[x / scale] += (px.r as i32 * 299 + px.g as i32 * 587 + px.b as i32 * 114) / 1000;
+            }
+        }
+        let area = (scale * scale) as i32;
+        luma.iter_mut().for_each(|v| *v /= area);
+
+        Self { luma, width, height, scale }
+    }
+
+    /// Finds the translation of the content from `prev` to `self` with the smallest mean absolute difference,
+    /// refined to sub-pixel precision with a parabola fitted through the neighbouring costs.
+    fn offset_from(&self, prev: &Thumb) -> (f32, f32) {
+        let r = SEARCH_RANGE;
+        if self.width as isize <= 2 * r || self.height as isize <= 2 * r {
+            return (0.0, 0.0);
+        }
+
+        let side = (2 * r + 1) as usize;
+        let mut costs = vec![0i64; side * side];
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let mut diff = 0i64;
+                for y in r..self.height as isize - r {
+                    let prev_row = &prev.luma[y as usize * prev.width..];
+                    let cur_row = &self.luma[(y + dy) as usize * self.width..];
+                    for x in r..self.width as isize - r {
+                        diff += (cur_row[(x + dx) as usize] - prev_row[x as usize]).abs() as i64;
+                    }
+                }
+                costs[(dy + r) as usize * side + (dx + r) as usize] = diff;
+            }
+        }
+
+        // prefer the smallest motion among equally good candidates
+        let (best, _) = costs.iter().enumerate()
+            .min_by_key(|&(i, &cost)| {
+                let (dx, dy) = ((i % side) as isize - r, (i / side) as isize - r);
+                (cost, dx.abs() + dy.abs())
+            })
+            .unwrap_or_else(|| unreachable!());
+        let (bx, by) = (best % side, best / side);
+
+        let refine = |before: Option<i64>, at: i64, after: Option<i64>| match (before, after) {
+            (Some(b), Some(a)) if b + a - 2 * at > 0 => (b - a) as f32 / (2 * (b + a - 2 * at)) as f32,
+            _ => 0.0,
+        };
+        let cost = |x: usize, y: usize| costs[y * side + x];
+        let fx = refine(bx.checked_sub(1).map(|x| cost(x, by)), cost(bx, by), (bx + 1 < side).then(|| cost(bx + 1, by)));
+        let fy = refine(by.checked_sub(1).map(|y| cost(bx, y)), cost(bx, by), (by + 1 < side).then(|| cost(bx, by + 1)));
+        ((bx as isize - r) as f32 + fx, (by as isize - r) as f32 + fy)
+    }
+}
@@ -0,0 +1,147 @@
+use std::str::FromStr;
+
+use color_eyre::Result;
+
+use super::{resample_bilinear, Filter, Frame};
+
+/// Crop window with its top-left corner in source pixels and its size as a fraction of the frame.
+#[derive(Clone, Copy)]
+pub struct ZoomWindow {
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+}
+
+#[derive(Clone, Copy)]
+pub struct ZoomPanSpec {
+    pub start: ZoomWindow,
+    pub end: ZoomWindow,
+}
+
+/// Parses `start=X,Y,Z%;end=X,Y,Z%`; an omitted side defaults to the full frame.
+impl FromStr for ZoomPanSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let full = ZoomWindow { x: 0.0, y: 0.0, size: 1.0 };
+        let mut spec = ZoomPanSpec { start: full, end: full };
+        for part in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("expected start=X,Y,Z% or end=X,Y,Z%, got {part:?}"))?;
+            let window = parse_window(value).ok_or_else(|| format!("invalid zoom window {value:?}, expected X,Y,Z%"))?;
+            match key.trim() {
+                "start" => spec.start = window,
+                "end" => spec.end = window,
+                _ => return Err(format!("unknown zoompan key {key:?}, expected start or end")),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+fn parse_window(s: &str) -> Option<ZoomWindow> {
+    let mut parts = s.split(',').map(str::trim);
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    let size = parts.next()?.strip_suffix('%')?.parse::<f32>().ok()? / 100.0;
+    if parts.next().is_some() || x < 0.0 || y < 0.0 || !(size > 0.0 && size <= 1.0) {
+        return None;
+    }
+    Some(ZoomWindow { x, y, size })
+}
+
+/// Animates a crop window from `start` to `end` over the clip and scales it back to the frame size.
+pub struct ZoomPan {
+    spec: ZoomPanSpec,
+    duration: f64,
+    first_pts: Option<f64>,
+}
+
+impl ZoomPan {
+    /// `duration` is the length of the clip in seconds, over which the window is interpolated.
+    pub fn new(spec: ZoomPanSpec, duration: f64) -> Self {
+        Self { spec, duration, first_pts: None }
+    }
+}
+
+impl Filter for ZoomPan {
+    fn process(&mut self, frame: Frame, out: &mut Vec<Frame>) -> Result<()> {
+        let first_pts = *self.first_pts.get_or_insert(frame.pts);
+        let t = if self.duration > 0.0 { ((frame.pts - first_pts) / self.duration).clamp(0.0, 1.0) as f32 } else { 0.0 };
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let (start, end) = (self.spec.start, self.spec.end);
+
+        let (width, height) = (frame.image.width(), frame.image.height());
+        let size = lerp(start.size, end.size);
+        let (window_width, window_height) = (width as f32 * size, height as f32 * size);
+        let x = lerp(start.x, end.x).min(width as f32 - window_width);
+        let y = lerp(start.y, end.y).min(height as f32 - window_height);
+
+        out.push(Frame {
+            image: resample_bilinear(frame.image.as_ref(), x, y, window_width, window_height, width, height),
+            pts: frame.pts,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    use super::*;
+
+    /// A 4x4 frame whose red channel grows by 40 per column and green channel by 40 per row, which bilinear
+    /// sampling reproduces exactly between pixels.
+    fn gradient(pts: f64) -> Frame {
+        let pixels = (0..4).flat_map(|y| (0..4).map(move |x| RGBA8::new(x * 40, y * 40, 0, 255))).collect();
+        Frame { image: ImgVec::new(pixels, 4, 4), pts }
+    }
+
+    fn zoom(spec: &str, pts: &[f64]) -> Vec<Frame> {
+        let mut zoompan = ZoomPan::new(spec.parse().unwrap(), 1.0);
+        let mut out = Vec::new();
+        for &pts in pts {
+            zoompan.process(gradient(pts), &mut out).unwrap();
+        }
+        out
+    }
+
+    fn first_row(frame: &Frame) -> Vec<(u8, u8)> {
+        frame.image.rows().next().unwrap().iter().map(|px| (px.r, px.g)).collect()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let spec = "start=0,0,100%; end=10,20,50%".parse::<ZoomPanSpec>().unwrap();
+        assert_eq!((spec.start.x, spec.start.y, spec.start.size), (0.0, 0.0, 1.0));
+        assert_eq!((spec.end.x, spec.end.y, spec.end.size), (10.0, 20.0, 0.5));
+        // an omitted side is the full frame
+        let spec = "end=5,5,25%".parse::<ZoomPanSpec>().unwrap();
+        assert_eq!((spec.start.x, spec.start.y, spec.start.size), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        for s in ["start", "start=0,0", "start=0,0,50", "start=0,0,0%", "start=0,0,150%", "start=-1,0,50%",
+                "start=0,0,50%,1", "middle=0,0,50%"] {
+            assert!(s.parse::<ZoomPanSpec>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn moves_the_window_over_the_clip() {
+        let out = zoom("end=2,2,50%", &[0.0, 1.0]);
+        // the full frame at the start, unchanged
+        assert!(out[0].image.pixels().eq(gradient(0.0).image.pixels()));
+        // the bottom right quarter scaled up at the end
+        assert_eq!(out[1].image.width(), 4);
+        assert_eq!(first_row(&out[1]), [(70, 70), (90, 70), (110, 70), (120, 70)]);
+    }
+
+    #[test]
+    fn keeps_the_window_inside_the_frame() {
+        let out = zoom("end=10,10,50%", &[0.0, 1.0]);
+        assert_eq!(first_row(&out[1]), [(70, 70), (90, 70), (110, 70), (120, 70)]);
+    }
+}
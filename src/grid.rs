@@ -0,0 +1,120 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use color_eyre::Result;
+use color_eyre::eyre::ensure;
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::decoder::{Decoder, WebmDecoder, WebmStream};
+use crate::filter::{resize_to_fit, Frame};
+
+#[derive(Clone, Copy)]
+pub struct GridLayout {
+    pub columns: usize,
+    pub rows: usize,
+}
+
+/// Parses `COLUMNSxROWS`, e.g. `2x2`.
+impl FromStr for GridLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (columns, rows) = s.split_once('x')?;
+            Some(GridLayout { columns: columns.parse().ok()?, rows: rows.parse().ok()? })
+        };
+        match parse() {
+            Some(layout) if layout.columns > 0 && layout.rows > 0 => Ok(layout),
+            _ => Err(format!("expected a grid layout like 2x2, got {s:?}")),
+        }
+    }
+}
+
+struct Cell<'a> {
+    decoder: WebmDecoder<'a>,
+    /// Frame shown at the current output time, already scaled to the cell.
+    current: Option<ImgVec<RGBA8>>,
+    next: Option<(ImgVec<RGBA8>, f64)>,
+    /// Added to the input timestamps, grows by the input length every time it loops.
+    offset: f64,
+    last_pts: f64,
+}
+
+/// Plays every stream in its own cell of a `layout` grid, sampled at `fps` for `duration` seconds, decoding each
+/// with the matching entry of `decoders`.
+///
+/// Cells take the size of the first input; other inputs are scaled to fit and centered. Inputs that end early
+/// freeze on their last frame or start over if `looping` is set.
+pub fn compose(streams: &mut [WebmStream], decoders: &[Decoder], layout: GridLayout, fps: (u32, u32), duration: f64, looping: bool,
+        emit: &mut dyn FnMut(Frame) -> Result<bool>) -> Result<()> {
+    ensure!(streams.len() <= layout.columns * layout.rows, "{} inputs do not fit in a {}x{} grid",
+        streams.len(), layout.columns, layout.rows);
+
+    let mut cells = Vec::with_capacity(streams.len());
+    for (stream, &decoder) in streams.iter_mut().zip(decoders) {
+        let mut decoder = stream.decode(decoder)?;
+        let next = decoder.next_frame()?;
+        cells.push(Cell { decoder, current: None, next, offset: 0.0, last_pts: 0.0 });
+    }
+    let Some((cell_width, cell_height)) = cells.first().and_then(|c| c.next.as_ref()).map(|(f, _)| (f.width(), f.height())) else {
+        return Ok(());
+    };
+
+    let width = cell_width * layout.columns;
+    let height = cell_height * layout.rows;
+    let frame_duration = fps.1 as f64 / fps.0 as f64;
+    let count = (duration / frame_duration).ceil() as u64;
+
+    for i in 0..count {
+        let pts = i as f64 * frame_duration;
+        let mut canvas = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); width * height], width, height);
+        for (index, cell) in cells.iter_mut().enumerate() {
+            cell.advance(pts, frame_duration, looping, cell_width, cell_height)?;
+            if let Some(image) = &cell.current {
+                let left = (index % layout.columns) * cell_width + (cell_width - image.width()) / 2;
+                let top = (index / layout.columns) * cell_height + (cell_height - image.height()) / 2;
+                let mut target = canvas.sub_image_mut(left, top, image.width(), image.height());
+                for (dst, src) in target.rows_mut().zip(image.rows()) {
+                    dst.copy_from_slice(src);
+                }
+            }
+        }
+        if !emit(Frame { image: canvas, pts })? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Cell<'_> {
+    fn advance(&mut self, pts: f64, frame_duration: f64, looping: bool, width: usize, height: usize) -> Result<()> {
+        loop {
+            match self.next.take() {
+                Some((image, frame_pts)) if frame_pts + self.offset <= pts || self.current.is_none() => {
+                    self.last_pts = frame_pts;
+                    self.current = Some(if image.width() == width && image.height() == height {
+                        image
+                    } else {
+                        resize_to_fit(image.as_ref(), width, height)
+                    });
+                    self.next = self.decoder.next_frame()?;
+                },
+                Some(next) => {
+                    self.next = Some(next);
+                    return Ok(());
+                },
+                None if looping && self.last_pts > 0.0 => {
+                    self.decoder.seek(Duration::ZERO)?;
+                    self.next = self.decoder.next_frame()?;
+                    let Some((_, first_pts)) = self.next else { return Ok(()) };
+                    self.offset += self.last_pts + frame_duration - first_pts;
+                    if first_pts + self.offset > pts {
+                        return Ok(());
+                    }
+                },
+                None => return Ok(()),
+            }
+        }
+    }
+}
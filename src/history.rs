@@ -0,0 +1,124 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::Result;
+use tracing::warn;
+
+use crate::probe;
+
+/// Only the latest conversions are used, so that the estimates follow hardware and setting changes.
+const MAX_SAMPLES: usize = 500;
+
+/// How long a past conversion took.
+pub struct Sample {
+    pub width: u32,
+    pub height: u32,
+    pub frames: u64,
+    pub quality: u8,
+    pub seconds: f64,
+}
+
+impl Sample {
+    /// Megapixels of all frames together, which the time is roughly proportional to.
+    fn work(&self) -> f64 {
+        self.width as f64 * self.height as f64 * self.frames as f64 / 1e6
+    }
+}
+
+/// Past conversions, kept in `webm2gif/history.tsv` in the platform's data directory, to estimate how long the
+/// next ones will take before decoding them.
+pub struct History {
+    path: Option<Utf8PathBuf>,
+    samples: Vec<Sample>,
+}
+
+impl History {
+    /// Reads the history; a missing or unreadable file only means there is nothing to estimate from.
+    pub fn load() -> Self {
+        let path = dirs::data_dir()
+            .and_then(|dir| Utf8PathBuf::from_path_buf(dir).ok())
+            .map(|dir| dir.join("webm2gif").join("history.tsv"));
+        let mut samples = path.as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().filter_map(parse_line).collect::<Vec<_>>())
+            .unwrap_or_default();
+        samples.drain(..samples.len().saturating_sub(MAX_SAMPLES));
+        Self { path, samples }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn record(&mut self, sample: Sample) {
+        if let Some(path) = &self.path {
+            if let Err(e) = append(path, &sample) {
+                warn!("failed to update {path}: {e:#}");
+                // don't keep warning about it
+                self.path = None;
+            }
+        }
+        self.samples.push(sample);
+    }
+
+    /// Seconds a conversion of `frames` frames of `width` x `height` at `quality` is expected to take.
+    pub fn estimate(&self, width: u32, height: u32, frames: u64, quality: u8) -> Option<f64> {
+        // the same quality if there is any, as it changes the speed of gifski a lot
+        let same_quality = self.samples.iter().filter(|s| s.quality == quality).collect::<Vec<_>>();
+        let samples = if same_quality.is_empty() { self.samples.iter().collect() } else { same_quality };
+        let work = samples.iter().map(|s| s.work()).sum::<f64>();
+        if work <= 0.0 {
+            return None;
+        }
+        let seconds_per_work = samples.iter().map(|s| s.seconds).sum::<f64>() / work;
+        Some(seconds_per_work * Sample { width, height, frames, quality, seconds: 0.0 }.work())
+    }
+
+    /// Estimates an input from its container header, without decoding it.
+    pub fn estimate_file(&self, path: &Utf8Path, quality: u8) -> Result<Option<f64>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let info = probe::stream_info(path)?;
+        let Some(duration) = info.duration else { return Ok(None) };
+        let (num, den) = info.frame_rate;
+        let frames = (duration.as_secs_f64() * num as f64 / den.max(1) as f64).ceil() as u64;
+        Ok(self.estimate(info.width, info.height, frames, quality))
+    }
+}
+
+fn parse_line(line: &str) -> Option<Sample> {
+    let mut fields = line.split('\t');
+    let mut next = || fields.next();
+    Some(Sample {
+        width: next()?.parse().ok()?,
+        height: next()?.parse().ok()?,
+        frames: next()?.parse().ok()?,
+        quality: next()?.parse().ok()?,
+        seconds: next()?.parse().ok().filter(|s: &f64| s.is_finite() && *s >= 0.0)?,
+    })
+}
+
+fn append(path: &Utf8Path, sample: &Sample) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}\t{}\t{}\t{}\t{:.3}", sample.width, sample.height, sample.frames, sample.quality, sample.seconds)?;
+    Ok(())
+}
+
+/// Formats a number of seconds like `45s`, `3m 20s` or `1h 05m`.
+pub fn format_eta(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
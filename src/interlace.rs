@@ -0,0 +1,55 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use gif::{ColorOutput, DecodeOptions, Decoder, Encoder, Repeat};
+
+/// Rewrites every frame of a gif with interlaced rows so that it renders progressively.
+///
+/// The frames are re-encoded with a plain LZW encoder, so gains from `--lossy-quality` are partially lost.
+pub fn rewrite_interlaced(path: &Utf8Path, repeat: Repeat) -> Result<()> {
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let decoder = options.read_info(BufReader::new(File::open(path)?))
+        .wrap_err_with(|| format!("failed to read {path}"))?;
+
+    let tmp = path.with_extension("gif.tmp");
+    match write_interlaced(decoder, &tmp, repeat) {
+        Ok(()) => fs::rename(&tmp, path).wrap_err_with(|| format!("failed to replace {path}")),
+        Err(e) => {
+            fs::remove_file(&tmp).ok();
+            Err(e)
+        },
+    }
+}
+
+fn write_interlaced<R: Read>(mut decoder: Decoder<R>, output: &Utf8Path, repeat: Repeat) -> Result<()> {
+    let global_palette = decoder.global_palette().unwrap_or(&[]).to_vec();
+    let mut encoder = Encoder::new(BufWriter::new(File::create(output)?), decoder.width(), decoder.height(), &global_palette)?;
+    encoder.set_repeat(repeat)?;
+
+    while let Some(frame) = decoder.read_next_frame()? {
+        let mut frame = frame.clone();
+        frame.buffer = interlace_rows(&frame.buffer, frame.width as usize).into();
+        frame.interlaced = true;
+        encoder.write_frame(&frame)?;
+    }
+
+    encoder.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Reorders rows into the four gif interlace passes: every 8th row from 0, every 8th from 4,
+/// every 4th from 2 and every 2nd from 1.
+fn interlace_rows(buffer: &[u8], width: usize) -> Vec<u8> {
+    let height = if width == 0 { 0 } else { buffer.len() / width };
+    let mut out = Vec::with_capacity(buffer.len());
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for row in (start..height).step_by(step) {
+            out.extend_from_slice(&buffer[row * width..][..width]);
+        }
+    }
+    out
+}
@@ -0,0 +1,36 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use color_eyre::Result;
+use color_eyre::eyre::bail;
+use tracing::info;
+
+/// Exit code after stopping for Ctrl-C, the one shells use for processes killed by SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C stop the conversions in progress instead of killing the process, so that their partial outputs
+/// are removed and the batch can be summed up. A second Ctrl-C exits right away.
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            process::exit(EXIT_CODE);
+        }
+        info!("Stopping, press Ctrl-C again to exit right away");
+    })?;
+    Ok(())
+}
+
+/// Whether Ctrl-C was pressed.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Fails once Ctrl-C was pressed, to stop the conversion calling it between two frames.
+pub fn check() -> Result<()> {
+    if requested() {
+        bail!("interrupted");
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use color_eyre::Result;
+
+/// Runs jobs on a fixed number of threads, handing each one to the first thread that is free.
+pub struct JobPool<J> {
+    tx: Option<mpsc::SyncSender<J>>,
+    done: mpsc::Receiver<(J, Result<()>)>,
+    threads: Vec<JoinHandle<()>>,
+    /// Jobs submitted whose results were not returned yet.
+    pending: usize,
+}
+
+/// The jobs one thread of a `JobPool` takes, and where it reports them done.
+pub struct JobQueue<J> {
+    rx: Arc<Mutex<mpsc::Receiver<J>>>,
+    done: mpsc::Sender<(J, Result<()>)>,
+}
+
+impl<J> JobQueue<J> {
+    /// Waits for the next job; `None` once the pool is finished.
+    pub fn next(&self) -> Option<J> {
+        self.rx.lock().unwrap_or_else(|e| e.into_inner()).recv().ok()
+    }
+
+    pub fn done(&self, job: J, result: Result<()>) {
+        self.done.send((job, result)).ok();
+    }
+}
+
+impl<J: Send + 'static> JobPool<J> {
+    /// Starts `threads` threads that each call `work` once, which keeps taking jobs from the queue it is given.
+    pub fn start<F>(threads: usize, work: F) -> Self
+    where
+        F: Fn(JobQueue<J>) + Clone + Send + 'static,
+    {
+        // no jobs wait in the channel, so that a failure stops the batch before the rest is handed out
+        let (tx, rx) = mpsc::sync_channel(0);
+        let rx = Arc::new(Mutex::new(rx));
+        let (done_tx, done) = mpsc::channel();
+        let threads = (0..threads)
+            .map(|_| {
+                let queue = JobQueue { rx: Arc::clone(&rx), done: done_tx.clone() };
+                let work = work.clone();
+                thread::spawn(move || work(queue))
+            })
+            .collect();
+        Self { tx: Some(tx), done, threads, pending: 0 }
+    }
+
+    /// Waits until a thread is free to take `job`.
+    pub fn submit(&mut self, job: J) {
+        if let Some(tx) = &self.tx {
+            if tx.send(job).is_ok() {
+                self.pending += 1;
+            }
+        }
+    }
+
+    /// Jobs that finished since the last call, without waiting for the others.
+    pub fn finished(&mut self) -> Vec<(J, Result<()>)> {
+        let results = self.done.try_iter().collect::<Vec<_>>();
+        self.pending -= results.len();
+        results
+    }
+
+    /// Waits for every submitted job and stops the threads.
+    pub fn finish(mut self) -> Vec<(J, Result<()>)> {
+        drop(self.tx.take());
+        // ends early if the threads are gone, so a panic in one of them can't block forever
+        let results = self.done.iter().take(self.pending).collect();
+        for thread in self.threads {
+            thread.join().ok();
+        }
+        results
+    }
+}
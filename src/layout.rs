@@ -0,0 +1,72 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Columns the default progress bar template needs besides the file name.
+const BAR_COLUMNS: usize = 70;
+/// File names are never squeezed below this, even if the bar wraps on very narrow terminals.
+const MIN_NAME_COLUMNS: usize = 12;
+
+/// File name shown in front of a progress bar, right-aligned to the widest name seen so far.
+pub struct Label {
+    text: String,
+    widest: usize,
+    title: Option<String>,
+}
+
+impl Label {
+    pub fn new(text: &str, widest: usize) -> Self {
+        Label { text: text.to_owned(), widest: widest.max(display_width(text)), title: None }
+    }
+
+    /// Also shows `title` followed by the percentage done in the title of the terminal.
+    pub fn with_title(mut self, title: String) -> Self {
+        self.title = Some(title);
+        self
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Lays the name out for a terminal `columns` wide, or without a width limit if that is unknown.
+    pub fn render(&self, columns: Option<usize>) -> String {
+        let width = match columns {
+            Some(columns) => self.widest.min(columns.saturating_sub(BAR_COLUMNS).max(MIN_NAME_COLUMNS)),
+            None => self.widest,
+        };
+        fit(&self.text, width)
+    }
+}
+
+pub fn terminal_width() -> Option<usize> {
+    console::Term::stdout().size_checked().map(|(_, columns)| columns as usize)
+}
+
+/// Width of `s` in terminal columns, counting ambiguous characters as wide like most CJK terminals do.
+pub fn display_width(s: &str) -> usize {
+    UnicodeWidthStr::width_cjk(s)
+}
+
+/// Pads `s` on the left to exactly `width` columns, cutting it short with an ellipsis if it does not fit.
+fn fit(s: &str, width: usize) -> String {
+    let mut text_width = display_width(s);
+    let mut text = s.to_owned();
+    if text_width > width {
+        text.clear();
+        text_width = 0;
+        // '…' is ambiguous and takes two columns by this measure
+        let ellipsis_width = display_width("…");
+        for c in s.chars() {
+            let char_width = c.width_cjk().unwrap_or(0);
+            if text_width + char_width + ellipsis_width > width {
+                break;
+            }
+            text.push(c);
+            text_width += char_width;
+        }
+        text.push('…');
+        text_width += ellipsis_width;
+    }
+    let mut padded = " ".repeat(width.saturating_sub(text_width));
+    padded.push_str(&text);
+    padded
+}
@@ -0,0 +1,21 @@
+//! Converts webm and other videos to gifs with ffmpeg and gifski.
+//!
+//! `Pipeline` converts one input to any `Write` sink. The modules below it are what the `webm2gif` binary is
+//! built from, for programs that need more control.
+#![feature(let_else)]
+#![feature(once_cell)]
+#![feature(scoped_threads)]
+
+pub mod cleanup;
+pub mod cli;
+pub mod decoder;
+pub mod encoder;
+pub mod filter;
+pub mod grid;
+mod pipeline;
+pub mod progress;
+pub mod scan;
+pub mod settings;
+pub mod throttle;
+pub use pipeline::{decode_frames, Pipeline, Trim};
+pub use settings::EncodeSettings;
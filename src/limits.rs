@@ -0,0 +1,218 @@
+use color_eyre::Result;
+
+/// Caps the CPU time and memory of this process and of every process it starts, such as gifsicle.
+///
+/// `cpu` is a percentage of all cores together, `memory` a number of bytes.
+pub fn apply(cpu: Option<f64>, memory: Option<u64>) -> Result<()> {
+    if cpu.is_none() && memory.is_none() {
+        return Ok(());
+    }
+    platform::apply(cpu, memory)
+}
+
+/// cgroups v2: moves the process into a new child of its current cgroup and sets the limits there. The current
+/// cgroup is left without processes of its own, which the kernel requires to enable controllers for its children,
+/// so it has to be one this process has to itself, e.g. a systemd scope.
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+    use std::thread;
+
+    use color_eyre::Result;
+    use color_eyre::eyre::{bail, ensure, eyre, Context};
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+    const CPU_PERIOD: u64 = 100_000;
+
+    pub fn apply(cpu: Option<f64>, memory: Option<u64>) -> Result<()> {
+        limit(cpu, memory).wrap_err("failed to set up a cgroup for --cpu-limit/--memory-limit; the cgroup of this \
+            process must be delegated to the user and hold no other processes, e.g. run inside \
+            'systemd-run --user --scope -p Delegate=yes'")
+    }
+
+    fn limit(cpu: Option<f64>, memory: Option<u64>) -> Result<()> {
+        let cgroups = fs::read_to_string("/proc/self/cgroup").wrap_err("failed to read /proc/self/cgroup")?;
+        let current = cgroups.lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| eyre!("cgroups v2 is not mounted"))?;
+        let parent = Path::new(CGROUP_ROOT).join(current.trim_start_matches('/'));
+        // a system with the legacy hierarchies can have a tmpfs there, where the files below would be created as
+        // plain ones
+        ensure!(parent.join("cgroup.controllers").exists(), "cgroups v2 is not mounted at {CGROUP_ROOT}");
+
+        // groups left behind by earlier runs; removing fails while a process is still inside
+        for entry in fs::read_dir(&parent)?.flatten() {
+            if entry.file_name().to_string_lossy().starts_with("webm2gif-") {
+                fs::remove_dir(entry.path()).ok();
+            }
+        }
+
+        let group = parent.join(format!("webm2gif-{}", process::id()));
+        fs::create_dir(&group).wrap_err_with(|| format!("failed to create {}", group.display()))?;
+        // controllers can only be enabled for the children of a group without processes of its own
+        write(&group.join("cgroup.procs"), &process::id().to_string())?;
+        let result = set_limits(&parent, &group, cpu, memory);
+        if result.is_err() {
+            // back where it was, the group is of no use
+            fs::write(parent.join("cgroup.procs"), process::id().to_string()).ok();
+            fs::remove_dir(&group).ok();
+        }
+        result
+    }
+
+    fn set_limits(parent: &Path, group: &Path, cpu: Option<f64>, memory: Option<u64>) -> Result<()> {
+        let mut controllers = Vec::new();
+        if cpu.is_some() {
+            controllers.push("cpu");
+        }
+        if memory.is_some() {
+            controllers.push("memory");
+        }
+        enable(parent, &controllers)?;
+
+        if let Some(percent) = cpu {
+            let cores = thread::available_parallelism().map_or(1, |n| n.get());
+            let quota = (percent / 100.0 * cores as f64 * CPU_PERIOD as f64).max(1000.0) as u64;
+            write(&group.join("cpu.max"), &format!("{quota} {CPU_PERIOD}"))?;
+        }
+        if let Some(bytes) = memory {
+            write(&group.join("memory.max"), &bytes.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Enables `controllers` for the children of `parent`, unless they already are, as in a delegated systemd
+    /// scope or service.
+    fn enable(parent: &Path, controllers: &[&str]) -> Result<()> {
+        let available = read(&parent.join("cgroup.controllers"))?;
+        if let Some(controller) = controllers.iter().find(|c| !available.split_whitespace().any(|a| a == **c)) {
+            bail!("the {controller} controller is not delegated to {}", parent.display());
+        }
+        let path = parent.join("cgroup.subtree_control");
+        let enabled = read(&path)?;
+        let missing = controllers.iter()
+            .filter(|c| !enabled.split_whitespace().any(|e| e == **c))
+            .map(|c| format!("+{c}"))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        match fs::write(&path, missing.join(" ")) {
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                bail!("{} holds other processes besides this one, which keeps {} from being enabled for its children",
+                    parent.display(), missing.join(" "))
+            },
+            result => result.wrap_err_with(|| format!("failed to write {:?} to {}", missing.join(" "), path.display())),
+        }
+    }
+
+    fn read(path: &Path) -> Result<String> {
+        fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path.display()))
+    }
+
+    fn write(path: &Path, value: &str) -> Result<()> {
+        fs::write(path, value).wrap_err_with(|| format!("failed to write {value:?} to {}", path.display()))
+    }
+}
+
+/// Job Objects: puts the process into a new job, which the processes it starts join automatically.
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::io;
+    use std::mem;
+    use std::ptr;
+
+    use color_eyre::Result;
+    use color_eyre::eyre::{ensure, Context};
+
+    type Handle = *mut c_void;
+
+    const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: u32 = 9;
+    const JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION: u32 = 15;
+    const JOB_OBJECT_LIMIT_JOB_MEMORY: u32 = 0x200;
+    const JOB_OBJECT_CPU_RATE_CONTROL_ENABLE: u32 = 0x1;
+    const JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP: u32 = 0x4;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct BasicLimitInformation {
+        per_process_user_time_limit: i64,
+        per_job_user_time_limit: i64,
+        limit_flags: u32,
+        minimum_working_set_size: usize,
+        maximum_working_set_size: usize,
+        active_process_limit: u32,
+        affinity: usize,
+        priority_class: u32,
+        scheduling_class: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ExtendedLimitInformation {
+        basic_limit_information: BasicLimitInformation,
+        io_info: [u64; 6],
+        process_memory_limit: usize,
+        job_memory_limit: usize,
+        peak_process_memory_used: usize,
+        peak_job_memory_used: usize,
+    }
+
+    #[repr(C)]
+    struct CpuRateControlInformation {
+        control_flags: u32,
+        cpu_rate: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+        fn SetInformationJobObject(job: Handle, class: u32, info: *mut c_void, length: u32) -> i32;
+        fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+        fn GetCurrentProcess() -> Handle;
+    }
+
+    pub fn apply(cpu: Option<f64>, memory: Option<u64>) -> Result<()> {
+        // the handle is never closed, the job has to live as long as the process
+        let job = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+        ensure!(!job.is_null(), "failed to create a job object: {}", io::Error::last_os_error());
+
+        if let Some(percent) = cpu {
+            // in hundredths of a percent of all processors
+            let mut info = CpuRateControlInformation {
+                control_flags: JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+                cpu_rate: ((percent * 100.0) as u32).clamp(1, 10_000),
+            };
+            set_information(job, JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION, &mut info).wrap_err("failed to set --cpu-limit")?;
+        }
+        if let Some(bytes) = memory {
+            let mut info = ExtendedLimitInformation::default();
+            info.basic_limit_information.limit_flags = JOB_OBJECT_LIMIT_JOB_MEMORY;
+            info.job_memory_limit = bytes.try_into().unwrap_or(usize::MAX);
+            set_information(job, JOB_OBJECT_EXTENDED_LIMIT_INFORMATION, &mut info).wrap_err("failed to set --memory-limit")?;
+        }
+
+        let ok = unsafe { AssignProcessToJobObject(job, GetCurrentProcess()) };
+        ensure!(ok != 0, "failed to assign the process to a job object: {}", io::Error::last_os_error());
+        Ok(())
+    }
+
+    fn set_information<T>(job: Handle, class: u32, info: &mut T) -> Result<()> {
+        let ok = unsafe { SetInformationJobObject(job, class, info as *mut T as *mut c_void, mem::size_of::<T>() as u32) };
+        ensure!(ok != 0, "{}", io::Error::last_os_error());
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use color_eyre::Result;
+    use color_eyre::eyre::bail;
+
+    pub fn apply(_cpu: Option<f64>, _memory: Option<u64>) -> Result<()> {
+        bail!("--cpu-limit and --memory-limit are only supported on Linux and Windows")
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+use std::io;
+
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::cli::Args;
+use crate::encoder;
+use crate::progress;
+
+/// Prints the messages of this program and of ffmpeg: warnings and errors to stderr, everything else to stdout unless
+/// the output itself or the JSON progress goes there.
+///
+/// `--quiet` leaves only errors, `-v` adds details such as the decoder used for each input, `-vv` every frame
+/// decoded and everything ffmpeg reports.
+pub fn init(args: &Args) {
+    let level = match args.verbose {
+        _ if args.quiet => Level::ERROR,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let to_stderr = progress::is_json() || args.output.as_deref().map_or(false, encoder::is_stdout);
+    let subscriber = tracing_subscriber::fmt().event_format(Plain).with_max_level(level);
+    if to_stderr {
+        subscriber.with_writer(io::stderr).init();
+    } else {
+        subscriber.with_writer(io::stderr.with_max_level(Level::WARN).or_else(io::stdout)).init();
+    }
+}
+
+/// Just the message, after "Warning: " for warnings, the way this program printed them before it used tracing.
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        if *event.metadata().level() == Level::WARN {
+            write!(writer, "Warning: ")?;
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
@@ -1,208 +1,1233 @@
 #![feature(let_else)]
 #![feature(once_cell)]
 #![feature(scoped_threads)]
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::ffi::CString;
-use std::fs::{self, DirEntry, File};
-use std::io::BufWriter;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::process;
+use std::slice;
+use std::sync::{mpsc, Arc};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use camino::Utf8PathBuf;
-use color_eyre::eyre::{ensure, eyre, Context};
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{bail, ensure, eyre, Context};
 use color_eyre::owo_colors::OwoColorize;
-use color_eyre::Result;
+use color_eyre::{Report, Result};
 use ffmpeg_sys_next as f;
 use gifski::progress::ProgressReporter;
-use gifski::Repeat;
 use humansize::{file_size_opts, FileSize};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rgb::RGBA8;
+use serde_json::json;
+use tracing::{debug, error, info, warn};
 
-mod decoder;
+mod autocrop;
+mod capture;
+mod config;
+mod history;
+mod interlace;
+mod interrupt;
+mod jobs;
+mod layout;
+mod limits;
+mod log;
+mod metrics;
+mod optimize;
+mod palette;
+mod probe;
+mod remote;
+mod shrink;
+mod stages;
+mod taskbar;
+mod title;
+use webm2gif::{cleanup, cli, decoder, encoder, filter, grid, progress, scan, settings, throttle};
+use webm2gif::{decode_frames, Trim};
+use cli::{Args, Command, RecordArgs, ToVideoArgs};
+use config::ConfigWatcher;
 use decoder::*;
+use encoder::Format;
+use filter::{fps_cap, FilterChain, FpsCap, Frame, Orientation};
+use grid::GridLayout;
+use history::{History, Sample};
+use jobs::{JobPool, JobQueue};
+use layout::Label;
+use metrics::Reference;
+use palette::{Palette, PaletteBuilder};
+use progress::ProgressFormat;
+use remote::{Job, WorkerPool};
+use scan::{Globs, Input};
+use settings::EncodeSettings;
+use stages::Stages;
+use taskbar::Taskbar;
+use throttle::Throttled;
+use title::TitleGuard;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
-    let args = std::env::args().collect::<Vec<_>>();
+    interrupt::install()?;
+    let (args, argv) = config::parse_args()?;
+    limits::apply(args.cpu_limit, args.memory_limit)?;
+    if let Some(rate) = args.io_limit {
+        throttle::set_limit(rate);
+    }
+    let mut settings = EncodeSettings::from_args(&args);
+    progress::set_format(args.progress);
+    log::init(&args);
+    if args.list_decoders {
+        for decoder in Decoder::list() {
+            println!("{:<24} {}", decoder.name(), decoder.description());
+        }
+        return Ok(());
+    }
+    if let Some(Command::Worker(worker_args)) = &args.command {
+        return remote::run_worker(&worker_args.connect, args.worker_token.as_deref(), |args, input, output| {
+            let settings = EncodeSettings::from_args(&args);
+            let name = input.file_name().unwrap_or_else(|| unreachable!());
+            Converter::new(Arc::new(args), settings).convert(input, output, &Label::new(name, 0))
+        });
+    }
+    ensure!(!args.dry_run || args.command.is_none(), "--dry-run only works when converting files");
+    if args.save_palette.is_some() || args.use_palette.is_some() {
+        let output = match &args.command {
+            Some(Command::Record(record_args)) => Some(record_args.output.as_path()),
+            Some(Command::ToVideo(_)) => bail!("--save-palette and --use-palette only work when writing gif files"),
+            Some(Command::Worker(_)) => None,
+            None => args.output.as_deref(),
+        };
+        let format = match output {
+            Some(output) => settings.format_for(output)?,
+            None => settings.format.unwrap_or(Format::Gif),
+        };
+        ensure!(format == Format::Gif && !output.map_or(false, encoder::is_stdout),
+            "--save-palette and --use-palette only work when writing gif files");
+    }
+    if let Some(path) = &args.use_palette {
+        // fail before decoding anything
+        Palette::load(path)?;
+    }
+    if let Some(Command::Record(record_args)) = &args.command {
+        record(&args, &settings, record_args)?;
+        return save_palette(&args, slice::from_ref(&record_args.output));
+    }
+    if let Some(Command::ToVideo(to_video_args)) = &args.command {
+        return to_video(&args, to_video_args);
+    }
+    if let Some(output) = &args.output {
+        // fail before decoding anything
+        settings.format_for(output)?;
+        ensure!(!encoder::is_stdout(output) || args.max_output_size.is_none(),
+            "--max-output-size cannot be used when writing to stdout");
+        ensure!(!encoder::is_stdout(output) || args.progress != ProgressFormat::Json,
+            "--progress json cannot be used when writing to stdout");
+    }
+    if let Some(end) = Trim::from_args(&args).end {
+        ensure!(end > args.start.unwrap_or_default(), "--end and --duration must leave something after --start");
+    }
+    if let Some(layout) = args.grid {
+        return convert_grid(&args, &settings, layout);
+    }
+    ensure!(args.output.is_none() || args.inputs.len() == 1 && !args.inputs[0].is_dir(),
+        "--output can only be used with a single input file");
+    ensure!(!args.ask || args.files_from.as_deref() != Some(Utf8Path::new("-")),
+        "--ask cannot be used when --files-from reads stdin");
+    // shared with the --jobs threads, replaced when --watch reloads the config file
+    let mut args = Arc::new(args);
+    let mut config = match &args.watch {
+        Some(_) => ConfigWatcher::new(&args, argv.clone())?,
+        None => None,
+    };
 
-    let (files, skipped) = if args.len() <= 1 {
-        let mut files = fs::read_dir(".").wrap_err("failed to list files")?
-            .filter_map(|r| match r {
-                Ok(e) => check_webm(e).map(Ok),
-                Err(e) => Some(Err(e)),
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+    // directories are scanned in the background while the inputs found so far are converted
+    let (tx, rx) = mpsc::channel();
+    let mut dirs = Vec::new();
+    // with whether they came from --files-from, whose outputs are kept like those of scanned inputs
+    let mut inputs = args.inputs.iter().map(|path| (path.clone(), false)).collect::<Vec<_>>();
+    if let Some(list) = &args.files_from {
+        inputs.extend(read_file_list(list, args.null)?.into_iter().map(|path| (path, true)));
+    } else if inputs.is_empty() && args.watch.is_none() {
+        dirs.push(Utf8PathBuf::from("."));
+    }
+    for (mut path, listed) in inputs {
+        let mut metadata = fs::metadata(&path).wrap_err_with(|| eyre!("input file {}", path.clone()))?;
+        if metadata.is_dir() {
+            dirs.push(path);
+            continue;
+        }
 
-        let files_count = files.len();
-        if files_count == 0 {
-            println!("No input files are detected");
-            return Ok(());
+        while metadata.is_symlink() {
+            path = Utf8PathBuf::from_path_buf(fs::read_link(&path).wrap_err_with(|| eyre!("input file {}", path.clone()))?)
+                .map_err(|p| eyre!("invalid utf-8 path: {:?}", p))?;
+            metadata = fs::metadata(&path).wrap_err_with(|| eyre!("input file {}", path.clone()))?;
+        }
+        let root = path.parent().unwrap_or_else(|| Utf8Path::new("")).to_owned();
+        tx.send(Ok(Input { path, root, scanned: listed })).unwrap_or_else(|_| unreachable!());
+    }
+    let recursive = args.recursive;
+    // a leading dot is accepted for convenience
+    let extensions = args.scan_ext.iter().map(|ext| ext.trim_start_matches('.').to_owned()).collect::<Vec<_>>();
+    let globs = Globs { include: args.include.clone(), exclude: args.exclude.clone() };
+    let stages = match (&args.watch, args.stages) {
+        (Some(dir), true) => Some(Stages::create(dir, &extensions)?),
+        _ => None,
+    };
+    let watch = match &stages {
+        Some(stages) => Some(stages.incoming().to_owned()),
+        None => args.watch.clone(),
+    };
+    thread::spawn(move || {
+        for dir in dirs.iter().chain(&watch) {
+            scan::scan_dir(dir, recursive, &extensions, &globs, &tx);
+        }
+        if let Some(dir) = watch {
+            scan::watch_dir(&dir, recursive, &extensions, &globs, &tx);
         }
+    });
+    if let Some(dir) = stages.as_ref().map(Stages::incoming).or(args.watch.as_deref()) {
+        info!("Watching {dir} for new files, press Ctrl-C to stop");
+    }
 
-        files.retain(|(_, gif)| {
-            !match fs::metadata(gif) {
-                Ok(m) => m.is_file() && m.len() != 0,
-                Err(_) => false,
+    let mut outputs = HashMap::new();
+    let (mut found, mut converted) = (0, 0);
+    // number of inputs excluded by each filter
+    let mut skipped = BTreeMap::<&str, usize>::new();
+    let mut name_max_len = 0;
+    let mut extension = settings.format.map_or("gif", Format::extension);
+    let mut converter = Converter::new(Arc::clone(&args), settings);
+    // outputs the palette for --save-palette is built from
+    let mut palette_sources = Vec::new();
+    let workers = match &args.workers {
+        Some(addr) => {
+            ensure!(settings.format != Some(Format::Frames) && !args.output.as_deref().map_or(false, encoder::is_stdout),
+                "--workers can only send back single files");
+            let options = argv[1..].iter()
+                .map(|option| option.to_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| eyre!("options sent to workers must be valid utf-8"))?;
+            let pool = WorkerPool::listen(addr, options, args.worker_token.clone())?;
+            info!("Waiting for workers on {addr}");
+            Some(pool)
+        },
+        None => None,
+    };
+    let mut jobs = (args.jobs > 1).then(|| {
+        let (args, bars) = (Arc::clone(&args), MultiProgress::new());
+        JobPool::start(args.jobs, move |queue: JobQueue<FileJob>| {
+            let mut converter = Converter::new(Arc::clone(&args), settings);
+            if !progress::is_json() {
+                converter = converter.with_progress(bars.clone());
             }
+            while let Some((job, label, args)) = queue.next() {
+                if !Arc::ptr_eq(&args, &converter.args) {
+                    converter.reconfigure(Arc::clone(&args), EncodeSettings::from_args(&args));
+                }
+                let result = converter.convert(&job.input, &job.output, &label).and_then(|()| {
+                    match &args.on_success {
+                        Some(action) => cleanup::consume_input(action, &job.input, &job.output, args.validate),
+                        None => Ok(()),
+                    }
+                });
+                queue.done((job, label, args), result);
+            }
+        })
+    });
+    // the first error on a --jobs thread, returned once the others are done
+    let mut failure = None;
+    let mut taskbar = Taskbar::new();
+    let _title = TitleGuard::new();
+    // inputs already found, to tell how long the batch is so far
+    let mut queue = VecDeque::new();
+    let mut estimated = false;
+    loop {
+        if interrupt::requested() {
+            break;
+        }
+        queue.extend(rx.try_iter());
+        let Some(input) = queue.pop_front().or_else(|| next_input(&rx)) else { break };
+        let Input { path, root, scanned } = match input {
+            Ok(input) => input,
+            // a file that cannot be read is no reason to stop watching for the next one
+            Err(e) if args.watch.is_some() => {
+                error!("{e:#}");
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        if let Some(reloaded) = config.as_mut().and_then(ConfigWatcher::reload) {
+            args = Arc::new(reloaded);
+            settings = EncodeSettings::from_args(&args);
+            extension = settings.format.map_or("gif", Format::extension);
+            converter.reconfigure(Arc::clone(&args), settings);
+        }
+        let (path, root) = match &stages {
+            Some(stages) => match stages.start(&path) {
+                Ok(path) => (path, stages.processing().to_owned()),
+                Err(e) => {
+                    error!("{e:#}");
+                    continue;
+                },
+            },
+            None => (path, root),
+        };
+        let total = found + 1 + queue.len() as u64;
+        taskbar.set_progress(found, total);
+        found += 1;
+        let output: Result<Utf8PathBuf> = (|| {
+            let output = match &args.output {
+                Some(output) => output.clone(),
+                None => {
+                    let name = match &args.name {
+                        Some(template) => {
+                            let info = template.needs_info()
+                                .then(|| probe::stream_info(&path).wrap_err_with(|| format!("failed to probe {path}")))
+                                .transpose()?;
+                            Some(template.render(&path, info.as_ref()))
+                        },
+                        None => None,
+                    };
+                    let dir = args.output_dir.as_deref();
+                    scan::output_path(&path, &root, dir, args.flatten, name.as_deref(), extension)
+                },
+            };
+            // --watch sees a file again when it is saved over
+            if let Some(other) = outputs.insert(output.clone(), path.clone()).filter(|other| *other != path) {
+                bail!("{other} and {path} would both be written to {output}");
+            }
+            Ok(output)
+        })();
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                fail_input(&args, stages.as_ref(), &path, e)?;
+                taskbar.set_error();
+                continue;
+            },
+        };
+
+        let existing = fs::metadata(&output).ok().filter(|m| match settings.format {
+            Some(Format::Frames) => m.is_dir(),
+            _ => m.is_file() && m.len() != 0,
         });
-        files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        let outdated = existing.as_ref().map_or(false, |m| !args.no_freshness_check && is_newer(&path, m));
+        if existing.is_some() && !outdated && !should_overwrite(&args, &output, scanned)? {
+            if args.dry_run {
+                info!("Would skip {path}: {output} is already transcoded");
+            }
+            progress::emit(json!({ "event": "skipped", "input": path.as_str(), "reason": "already transcoded" }));
+            *skipped.entry("already transcoded").or_default() += 1;
+            if let Some(stages) = &stages {
+                stages.finish(&path, &output, true);
+            }
+            continue;
+        }
+        let skip = match probe::skip_reason(&args, &path).wrap_err_with(|| format!("failed to probe {path}")) {
+            Ok(skip) => skip,
+            Err(e) => {
+                fail_input(&args, stages.as_ref(), &path, e)?;
+                taskbar.set_error();
+                continue;
+            },
+        };
+        if let Some(skip) = skip {
+            info!("{} {path}: {}", if args.dry_run { "Would skip" } else { "Skipping" }, skip.reason);
+            progress::emit(json!({ "event": "skipped", "input": path.as_str(), "reason": skip.reason }));
+            *skipped.entry(skip.filter).or_default() += 1;
+            if let Some(stages) = &stages {
+                stages.finish(&path, &output, true);
+            }
+            continue;
+        }
+        if args.dry_run {
+            info!("Would convert {path} to {output}");
+            converted += 1;
+            continue;
+        }
 
-        if files.is_empty() {
-            println!("All input files are already transcoded");
-            return Ok(());
+        if let Some(pool) = &workers {
+            if let Some(dir) = output.parent().filter(|dir| !dir.as_str().is_empty()) {
+                fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {dir}"))?;
+            }
+            pool.submit(Job { input: path, output });
+            continue;
+        }
+
+        if !estimated {
+            estimated = true;
+            let queued = queue.iter().filter_map(|input| input.as_ref().ok()).map(|input| input.path.as_path());
+            print_estimate(&args, &converter.history, settings.quality, [path.as_path()].into_iter().chain(queued));
+        }
+        let name = path.file_name().unwrap_or_else(|| unreachable!());
+        name_max_len = name_max_len.max(layout::display_width(name));
+        let label = Label::new(name, name_max_len).with_title(format!("[{found}/{total}] {name}"));
+        if let Some(pool) = &mut jobs {
+            pool.submit((Job { input: path, output }, label, Arc::clone(&args)));
+            collect_jobs(&args, pool.finished(), &mut converted, &mut palette_sources, &mut failure);
+            if failure.is_some() {
+                break;
+            }
+            continue;
+        }
+        let result = converter.convert(&path, &output, &label).and_then(|()| {
+            match &args.on_success {
+                Some(action) => cleanup::consume_input(action, &path, &output, args.validate),
+                None => Ok(()),
+            }
+        });
+        if result.is_err() && interrupt::requested() {
+            break;
+        }
+        if let Some(stages) = &stages {
+            stages.finish(&path, &output, result.is_ok());
+        }
+        if let Err(e) = &result {
+            taskbar.set_error();
+            progress::emit(json!({ "event": "failed", "input": path.as_str(), "error": format!("{e:#}") }));
+        }
+        // one bad file doesn't stop --watch
+        if let (Err(e), true) = (&result, args.watch.is_some()) {
+            error!("Failed to convert {path}: {e:#}");
+            continue;
+        }
+        result?;
+        converted += 1;
+        if args.save_palette.is_some() {
+            palette_sources.push(output);
+        }
+    }
+    if let Some(pool) = jobs {
+        collect_jobs(&args, pool.finish(), &mut converted, &mut palette_sources, &mut failure);
+    }
+    if interrupt::requested() {
+        info!("Interrupted after transcoding {converted} {}", if converted == 1 { "file" } else { "files" });
+        // exiting skips the destructors, which restore the title and taskbar
+        drop((_title, taskbar));
+        process::exit(interrupt::EXIT_CODE);
+    }
+    if let Some(e) = failure {
+        taskbar.set_error();
+        return Err(e);
+    }
+    if let Some(pool) = workers {
+        let (done, failed) = pool.finish();
+        for Job { input, output } in done {
+            if let Some(action) = &args.on_success {
+                cleanup::consume_input(action, &input, &output, args.validate)?;
+            }
+            converted += 1;
+        }
+        if failed > 0 {
+            taskbar.set_error();
+            bail!("{failed} of the files failed to convert on the workers");
         }
+    }
+    taskbar.set_progress(found, found);
+    save_palette(&args, &palette_sources)?;
 
-        let skipped = files_count - files.len();
-        (files, skipped)
-    } else {
-        let mut files = Vec::with_capacity(1);
-        for name in args.into_iter().skip(1) {
-            let mut path = Utf8PathBuf::from(name);
-            let mut metadata = fs::metadata(&path).wrap_err_with(|| eyre!("input file {}", path.clone()))?;
-            while metadata.is_symlink() {
-                path = Utf8PathBuf::from_path_buf(fs::read_link(&path).wrap_err_with(|| eyre!("input file {}", path.clone()))?)
-                    .map_err(|p| eyre!("invalid utf-8 path: {:?}", p))?;
-                metadata = fs::metadata(&path).wrap_err_with(|| eyre!("input file {}", path.clone()))?;
+    match (found, converted) {
+        (0, _) => info!("No input files are detected"),
+        (_, 0) => info!("All input files are already transcoded or skipped"),
+        _ => {
+            let mut summary = format!("{} {} {}", if args.dry_run { "Would transcode" } else { "Transcoded" }, converted,
+                if converted > 1 { "files" } else { "file" });
+            if !skipped.is_empty() {
+                let counts = skipped.iter()
+                    .map(|(&filter, count)| match filter {
+                        "already transcoded" => format!("{count} {filter}"),
+                        _ => format!("{count} by {filter}"),
+                    })
+                    .collect::<Vec<_>>();
+                summary += &format!(" ({} skipped: {})", skipped.values().sum::<usize>(), counts.join(", "));
             }
+            info!("{summary}");
+        },
+    }
+    Ok(())
+}
 
-            let gif = path.with_extension("gif");
-            files.push((path, gif));
+/// A file for the `--jobs` threads, with the options it is converted with.
+type FileJob = (Job, Label, Arc<Args>);
+
+/// Counts the files converted on the `--jobs` threads, keeping the first error in `failure`.
+fn collect_jobs(args: &Args, results: Vec<(FileJob, Result<()>)>, converted: &mut usize,
+        palette_sources: &mut Vec<Utf8PathBuf>, failure: &mut Option<Report>) {
+    for ((job, _, _), result) in results {
+        if let Err(e) = &result {
+            progress::emit(json!({ "event": "failed", "input": job.input.as_str(), "error": format!("{e:#}") }));
         }
-        (files, 0)
-    };
+        match result {
+            Ok(()) => {
+                *converted += 1;
+                if args.save_palette.is_some() {
+                    palette_sources.push(job.output);
+                }
+            },
+            // the other threads stopped for Ctrl-C as well
+            Err(_) if interrupt::requested() => {},
+            Err(e) if failure.is_some() || args.watch.is_some() => error!("Failed to convert {}: {e:#}", job.input),
+            Err(e) => *failure = Some(e),
+        }
+    }
+}
 
-    print!("Transcoding {} {}", files.len(), if files.len() > 1 { "files" } else { "file" });
-    if skipped > 0 {
-        println!(" ({} skipped)", skipped);
-    } else {
-        println!();
+/// Returns `e` for an input that failed before it could be converted, unless `--watch` is to go on with the next
+/// input; then the error is only printed, and with `--stages` the input is moved to `failed`.
+fn fail_input(args: &Args, stages: Option<&Stages>, path: &Utf8Path, e: Report) -> Result<()> {
+    if args.watch.is_none() {
+        return Err(e);
+    }
+    error!("{e:#}");
+    progress::emit(json!({ "event": "failed", "input": path.as_str(), "error": format!("{e:#}") }));
+    if let Some(stages) = stages {
+        // there is no output to move along
+        stages.finish(path, path, false);
     }
+    Ok(())
+}
 
-    let name_max_len = files.iter()
-        .map(|(n, _)| n.file_name().unwrap_or_else(|| unreachable!()))
-        .map(unicode_width::UnicodeWidthStr::width_cjk)
-        .max().unwrap_or_else(|| unreachable!());
-    let progress_style = ProgressStyle::default_bar()
-        .template(" {prefix:.green.bright} {msg} [{bar:50}]{percent:>3}%")
-        .progress_chars("=> ");
+/// Whether to replace the existing output of an input: always for `--force`, never for `--skip-existing`, as answered
+/// for `--ask`, and otherwise only if the input was given directly rather than found by scanning a directory or
+/// listed with `--files-from`.
+fn should_overwrite(args: &Args, output: &Utf8Path, scanned: bool) -> Result<bool> {
+    if args.ask {
+        eprint!("{output} already exists, replace it? [y/N] ");
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).wrap_err("failed to read the answer")?;
+        return Ok(matches!(answer.trim(), "y" | "Y" | "yes"));
+    }
+    Ok(args.force || !args.skip_existing && !scanned)
+}
 
-    for (input, output) in files {
-        let name = input.file_name().unwrap_or_else(|| unreachable!()).to_owned();
-        let time = Instant::now();
+/// Whether `input` was modified after the output with `output_metadata` was written.
+fn is_newer(input: &Utf8Path, output_metadata: &fs::Metadata) -> bool {
+    match (fs::metadata(input).and_then(|m| m.modified()), output_metadata.modified()) {
+        (Ok(input), Ok(output)) => input > output,
+        _ => false,
+    }
+}
 
-        let input = CString::new(input.into_string())?;
-        let mut ctx = WebmContext::new(input.as_c_str()).wrap_err_with(|| format!("failed to parse webm file: {name}"))?;
-        let duration = ctx.duration();
-        let mut stream = ctx.best_stream()?;
-        let fps = stream.fps();
+/// Reads the paths listed for `--files-from`, one per line or separated by NUL characters if `null` is set.
+fn read_file_list(list: &Utf8Path, null: bool) -> Result<Vec<Utf8PathBuf>> {
+    let mut text = String::new();
+    match list.as_str() {
+        "-" => io::stdin().read_to_string(&mut text),
+        _ => File::open(list).and_then(|mut file| file.read_to_string(&mut text)),
+    }.wrap_err_with(|| format!("failed to read the list of input files from {list}"))?;
+    let paths = if null { text.split('\0').collect::<Vec<_>>() } else { text.lines().collect() };
+    Ok(paths.into_iter().filter(|path| !path.is_empty()).map(Utf8PathBuf::from).collect())
+}
 
-        let estimated_frames = (duration * fps.0 as u64) / f::AV_TIME_BASE as u64 / fps.1 as u64;
-        ensure!(estimated_frames > 0, "invalid duration");
+/// Waits for the next input to be found, giving up on Ctrl-C since `--watch` never runs out of them.
+fn next_input(rx: &Receiver<Result<Input>>) -> Option<Result<Input>> {
+    loop {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(input) => return Some(input),
+            Err(RecvTimeoutError::Timeout) if !interrupt::requested() => {},
+            Err(_) => return None,
+        }
+    }
+}
 
-        struct ProgressAdapter<'a>(&'a ProgressBar);
+/// Prints how long the inputs found so far should take going by earlier conversions, before any of them is
+/// decoded. Without history, or if an input can't be estimated, the progress bars are left to tell.
+fn print_estimate<'a>(args: &Args, history: &History, quality: u8, inputs: impl Iterator<Item = &'a Utf8Path>) {
+    if history.is_empty() {
+        return;
+    }
+    let (mut seconds, mut count) = (0.0, 0);
+    for path in inputs {
+        let Ok(Some(estimate)) = history.estimate_file(path, quality) else { return };
+        seconds += estimate;
+        count += 1;
+    }
+    info!("The {count} {} found so far should take about {}, going by {} earlier conversions",
+        if count > 1 { "files" } else { "file" }, history::format_eta(seconds / args.jobs as f64), history.len());
+}
 
-        impl ProgressReporter for ProgressAdapter<'_> {
-            fn increase(&mut self) -> bool {
-                self.0.inc(1);
-                true
-            }
+/// Converts input files one after another with the same options, keeping what the next file can reuse.
+///
+/// gifski starts and stops its own threads for every output, so those are not kept.
+struct Converter {
+    args: Arc<Args>,
+    settings: EncodeSettings,
+    /// Decoder picked from `--decoder-priority` for each codec name and whether the input is a webm with alpha.
+    decoders: HashMap<(String, bool), Decoder>,
+    /// Scale context of the previous file, reused if the next one has the same frame size and pixel format.
+    scaler: Option<Scaler>,
+    /// Past conversions, extended with every file converted here.
+    history: History,
+    /// Where the progress bars go while other files are converted at the same time.
+    bars: Option<MultiProgress>,
+}
+
+impl Converter {
+    fn new(args: Arc<Args>, settings: EncodeSettings) -> Self {
+        Self { args, settings, decoders: HashMap::new(), scaler: None, history: History::load(), bars: None }
+    }
+
+    /// Converts the next files with options reloaded from the config file.
+    fn reconfigure(&mut self, args: Arc<Args>, settings: EncodeSettings) {
+        self.args = args;
+        self.settings = settings;
+        // --decoder-priority may have changed
+        self.decoders.clear();
+    }
+
+    fn with_progress(mut self, bars: MultiProgress) -> Self {
+        self.bars = Some(bars);
+        self
+    }
 
-            fn done(&mut self, _: &str) {}
+    fn find_decoder(&mut self, stream: &WebmStream, name: &str) -> Result<Decoder> {
+        let StreamInfo { codec, webm_alpha, .. } = stream.info();
+        let decoder = match self.decoders.get(&(codec.clone(), webm_alpha)) {
+            Some(decoder) if self.args.decoder.is_none() => *decoder,
+            _ => pick_decoder(stream, &self.args)?,
+        };
+        if webm_alpha && !decoder.decodes_webm_alpha() {
+            warn!("{name} has an alpha channel, which the {} decoder drops; only libvpx decodes it", decoder.name());
+        }
+        if self.args.decoder.is_none() {
+            self.decoders.insert((codec, webm_alpha), decoder);
         }
+        Ok(decoder)
+    }
 
-        let (mut collector, writer) = gifski::new(gifski::Settings {
-            width: None,
-            height: None,
-            quality: 100,
-            fast: false,
-            repeat: Repeat::Infinite,
-        })?;
+    fn convert(&mut self, path: &Utf8Path, output: &Utf8Path, label: &Label) -> Result<()> {
+        // apart from self, which is borrowed mutably below
+        let args = Arc::clone(&self.args);
+        let args = &*args;
+        let (part, settings) = part_path(output, &self.settings)?;
+        let settings = &settings;
+        let name = path.file_name().unwrap_or_else(|| unreachable!());
+        let time = Instant::now();
+        progress::emit(json!({ "event": "started", "input": path.as_str(), "output": output.as_str() }));
 
-        thread::scope(|scope| {
-            let pb = ProgressBar::new(estimated_frames);
-            pb.set_style(progress_style.clone());
-            pb.set_message(left_pad(&name, name_max_len));
-            pb.set_prefix("Processing");
-
-            let handle = scope.spawn(move |_| {
-                let mut decoder = stream.decode(VpxCodec::VP9)?;
-                let mut frame_index = 0;
-                while let Some((frame, pts)) = decoder.decode_frame()? {
-                    // thread::sleep(std::time::Duration::from_millis(500));
-                    collector.add_frame_rgba(frame_index, frame, pts)?;
-                    frame_index += 1;
-                }
-                Result::<_>::Ok(())
-            });
+        if let Some(dir) = output.parent().filter(|dir| !dir.as_str().is_empty()) {
+            fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {dir}"))?;
+        }
+        let crop = if args.autocrop {
+            let rect = open_input(path)
+                .and_then(|ctx| autocrop::detect(ctx, args.decoder.as_deref(), &args.decoder_priority))
+                .wrap_err_with(|| format!("failed to detect borders: {name}"))?;
+            if let Some(rect) = rect {
+                info!("Cropping {name} to {rect}");
+            }
+            rect
+        } else {
+            None
+        };
 
-            let result = writer.write(BufWriter::new(File::create(&output)?),
-                &mut ProgressAdapter(&pb)).map_err(Into::into);
-            let result = match handle.join().unwrap().and(result) {
-                Ok(_) => Result::<_>::Ok(()),
-                Err(e) => {
-                    fs::remove_file(&output).ok();
-                    Err(e)
+        let mut ctx = open_input(path).wrap_err_with(|| format!("failed to parse webm file: {name}"))?;
+        let trim = Trim::from_args(args);
+        let total_duration = ctx.duration();
+        let duration = trim.apply(total_duration);
+        let mut stream = ctx.best_stream()?;
+        let decoder = self.find_decoder(&stream, name)?.with_fps_cap(fps_cap(args));
+        debug!("Decoding {name} with {}", decoder.name());
+        let orientation = if args.no_autorotate { Orientation::default() } else { Orientation::from_info(&stream.info()) };
+
+        // live captures often have no duration, their bar only counts the frames
+        let estimated_frames = if total_duration > 0 {
+            ensure!(duration > 0 || trim.start.is_zero(), "--start is past the end of {name}");
+            let frames = estimate_frames(path, &stream, trim, duration)
+                .wrap_err_with(|| format!("failed to count the frames of {name}"))?;
+            ensure!(frames > 0, "invalid duration");
+            Some(frames)
+        } else {
+            None
+        };
+
+        let duration = match estimated_frames {
+            Some(_) => duration as f64 / f::AV_TIME_BASE as f64,
+            None => f64::INFINITY,
+        };
+        let bars = self.bars.clone();
+        let new_progress_bar = |frames: Option<u64>, prefix: &'static str| {
+            let pb = match frames {
+                Some(frames) => {
+                    let pb = ProgressBar::new(settings.max_frames.map_or(frames, |max| frames.min(max as u64)));
+                    pb.set_style(progress_style(args));
+                    pb
+                },
+                None => {
+                    let pb = ProgressBar::new_spinner();
+                    pb.set_style(ProgressStyle::default_spinner().template(" {prefix:.green.bright} {msg} {pos} frames"));
+                    pb
                 },
             };
+            pb.set_prefix(prefix);
+            match &bars {
+                Some(bars) => bars.add(pb),
+                None => pb,
+            }
+        };
+
+        // decoded frames are kept for --auto-shrink retries as long as they fit in memory
+        let mut cache = args.auto_shrink.then(Vec::new);
+        let filters = FilterChain::from_args(args, Some(path), crop, orientation, duration)?;
+        // the decoder already drops the frames --fps leaves out, at the rate before --speed
+        let estimated_frames = match fps_cap(args) {
+            Some(cap) => estimated_frames.map(|frames| frames.min((duration * cap).ceil() as u64).max(1)),
+            None => estimated_frames,
+        };
+        let pb = new_progress_bar(estimated_frames, "Processing");
+        let mut decode_stats = DecodeStats::default();
+        let mut stats = transcode(&part, settings, filters, &pb, label, estimated_frames, |emit| {
+            decode_stats = decode_frames(&mut stream, decoder, trim, 1, &mut self.scaler, &mut caching(&mut cache, emit))?;
+            Ok(())
+        })?;
 
-            pb.finish_and_clear();
+        let requested = settings;
+        let mut settings = *requested;
+        let mut shrunk = false;
+        if let Some(max_size) = args.max_output_size {
+            let mut steps = shrink::STEPS.iter();
+            let mut landed = None;
+            while output_size(&part)?.map_or(false, |size| size > max_size) {
+                let Some(step) = steps.next().filter(|_| args.auto_shrink) else {
+                    warn!("{output} is larger than --max-output-size");
+                    landed = None;
+                    break;
+                };
+                info!("Shrinking {name} to {step}");
+                landed = Some(step);
+                settings = step.apply(requested, stream.size().0);
+                shrunk = true;
 
-            if result.is_ok() {
-                let size = fs::metadata(&output)?.len();
-                println!(
-                    "Finished {} in {}s, {}",
-                    output.file_name().unwrap_or_else(|| unreachable!()).bright_cyan(),
-                    time.elapsed().as_secs(),
-                    size.file_size(file_size_opts::CONVENTIONAL).unwrap_or_else(|_| unreachable!())
-                );
+                let filters = FilterChain::from_args(args, Some(path), crop, orientation, duration)?;
+                let estimated_frames = estimated_frames.map(|frames| frames / step.frame_step as u64);
+                let pb = new_progress_bar(estimated_frames, "Shrinking");
+                stats = match &cache {
+                    Some(frames) => transcode(&part, &settings, filters, &pb, label, estimated_frames, |emit| {
+                        for frame in frames.iter().step_by(step.frame_step) {
+                            if !emit(frame.clone())? {
+                                break;
+                            }
+                        }
+                        Ok(())
+                    })?,
+                    None => {
+                        let mut ctx = open_input(path).wrap_err_with(|| format!("failed to parse webm file: {name}"))?;
+                        let mut stream = ctx.best_stream()?;
+                        transcode(&part, &settings, filters, &pb, label, estimated_frames, |emit| {
+                            decode_frames(&mut stream, decoder, trim, step.frame_step, &mut self.scaler,
+                                &mut caching(&mut None, emit)).map(drop)
+                        })?
+                    },
+                };
             }
-            result
-        })?;
+            if let Some(step) = landed {
+                info!("{name} fits in --max-output-size at {step}");
+            }
+        }
+        // retries decode the same input again, the problems found the first time are the ones to report
+        stats.decode = decode_stats;
+        let frames = stats.decoded;
+        finish_output(args, &settings, &part, output, Some(fs::metadata(path)?.len()), time, stats)?;
+        // shrinking repeats the encoding, which would make the file look slower than it is
+        if !shrunk {
+            let (width, height) = stream.size();
+            let seconds = time.elapsed().as_secs_f64();
+            self.history.record(Sample { width, height, frames, quality: settings.quality, seconds });
+        }
+        Ok(())
     }
+}
 
-    Ok(())
+/// Opens an input for decoding, reading it through the `--io-limit` throttle if there is one.
+fn open_input(path: &Utf8Path) -> Result<WebmContext> {
+    if throttle::is_limited() {
+        let file = File::open(path).wrap_err_with(|| format!("failed to open {path}"))?;
+        return WebmContext::from_reader(Throttled(file));
+    }
+    WebmContext::new(&CString::new(path.as_str())?)
 }
 
-fn check_webm(entry: DirEntry) -> Option<(Utf8PathBuf, Utf8PathBuf)> {
-    let mut file_type = entry.file_type().ok()?;
-    if file_type.is_dir() {
-        return None;
+/// Frames of `stream` within `trim`, which lasts `duration` in `AV_TIME_BASE` units. Inputs with a variable
+/// frame rate are counted in a pass over their packets unless the container declares the number, as the duration
+/// and frame rate would be far off; the others are estimated from those.
+fn estimate_frames(path: &Utf8Path, stream: &WebmStream, trim: Trim, duration: u64) -> Result<u64> {
+    let whole = trim.start.is_zero() && trim.end.is_none();
+    if let Some(frames) = stream.frame_count().filter(|_| whole) {
+        return Ok(frames);
     }
+    if stream.is_variable_rate() {
+        let mut ctx = open_input(path)?;
+        let end = trim.end.map(|end| end.as_secs_f64());
+        return ctx.best_stream()?.count_packets(trim.start.as_secs_f64(), end);
+    }
+    let fps = stream.fps();
+    Ok((duration * fps.0 as u64) / f::AV_TIME_BASE as u64 / fps.1 as u64)
+}
 
-    let mut path = entry.path();
-    if path.extension().and_then(|ext| ext.to_str()) != Some("webm") {
-        return None;
+/// Finds the decoder for `stream` that the options ask for, converting its frames the way they ask.
+fn pick_decoder(stream: &WebmStream, args: &Args) -> Result<Decoder> {
+    Ok(stream.find_decoder(args.decoder.as_deref(), &args.decoder_priority)?
+        .with_scale_filter(args.scale_filter)
+        .with_tone_map(args.tonemap))
+}
+
+/// Wraps `emit` to stop for Ctrl-C and to keep the frames in `cache` until it would grow beyond
+/// `shrink::CACHE_LIMIT`, at which point the cache is dropped.
+fn caching<'a>(cache: &'a mut Option<Vec<Frame>>, emit: &'a mut dyn FnMut(Frame) -> Result<bool>)
+        -> impl FnMut(Frame) -> Result<bool> + 'a {
+    let mut cached_bytes = 0;
+    move |frame| {
+        interrupt::check()?;
+        if let Some(frames) = cache {
+            cached_bytes += frame.image.buf().len() * mem::size_of::<RGBA8>();
+            if cached_bytes <= shrink::CACHE_LIMIT {
+                frames.push(frame.clone());
+            } else {
+                *cache = None;
+            }
+        }
+        emit(frame)
     }
+}
 
-    while file_type.is_symlink() {
-        path = fs::read_link(path).ok()?;
-        file_type = entry.file_type().ok()?;
+fn convert_grid(args: &Args, settings: &EncodeSettings, layout: GridLayout) -> Result<()> {
+    let first = args.inputs.first().ok_or_else(|| eyre!("--grid requires input files"))?;
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => first.with_file_name(format!("{}_grid", first.file_stem().unwrap_or("output")))
+            .with_extension(settings.format.map_or("gif", Format::extension)),
+    };
+    let (part, settings) = part_path(&output, settings)?;
+    let settings = &settings;
+    let time = Instant::now();
+
+    let mut contexts = args.inputs.iter()
+        .map(|path| open_input(path).wrap_err_with(|| format!("failed to parse webm file: {path}")))
+        .collect::<Result<Vec<_>>>()?;
+    let duration = contexts.iter().map(|c| c.duration()).max().unwrap_or(0) as f64 / f::AV_TIME_BASE as f64;
+    let mut streams = contexts.iter_mut().map(|c| c.best_stream()).collect::<Result<Vec<_>>>()?;
+    let decoders = streams.iter().map(|s| pick_decoder(s, args)).collect::<Result<Vec<_>>>()?;
+    for (path, decoder) in args.inputs.iter().zip(&decoders) {
+        debug!("Decoding {path} with {}", decoder.name());
     }
+    let fps = streams.iter().map(|s| s.fps())
+        .max_by(|a, b| (a.0 as u64 * b.1 as u64).cmp(&(b.0 as u64 * a.1 as u64)))
+        .unwrap_or_else(|| unreachable!());
+
+    let estimated_frames = (duration * fps.0 as f64 / fps.1 as f64).ceil() as u64;
+    ensure!(estimated_frames > 0, "invalid duration");
 
-    if !file_type.is_file() {
-        return None;
+    let mut filters = FilterChain::from_args(args, None, None, Orientation::default(), duration)?;
+    // the inputs are decoded at their own rates, the cap goes on the composed frames
+    if let Some(fps) = fps_cap(args) {
+        filters.push_front(FpsCap::new(fps));
     }
+    let pb = ProgressBar::new(settings.max_frames.map_or(estimated_frames, |max| estimated_frames.min(max as u64)));
+    pb.set_style(progress_style(args));
+    pb.set_prefix("Processing");
 
-    let webm = match Utf8PathBuf::from_path_buf(path) {
-        Ok(p) => p,
-        Err(p) => {
-            eprintln!("Warning: skipping file with invalid utf-8 name: {:?}", p);
-            return None;
+    let label = Label::new(output.file_name().unwrap_or_else(|| unreachable!()), 0);
+    let stats = transcode(&part, settings, filters, &pb, &label, Some(estimated_frames), |emit| {
+        grid::compose(&mut streams, &decoders, layout, fps, duration, args.grid_loop, &mut |frame| {
+            interrupt::check()?;
+            emit(frame)
+        })
+    })?;
+    let input_size = args.inputs.iter().map(|path| Ok(fs::metadata(path)?.len())).sum::<Result<u64>>()?;
+    finish_output(args, settings, &part, &output, Some(input_size), time, stats)?;
+    save_palette(args, &[output])
+}
+
+fn record(args: &Args, settings: &EncodeSettings, record: &RecordArgs) -> Result<()> {
+    ensure!(record.fps > 0, "invalid frame rate");
+    let (mut ctx, crop) = match &record.device {
+        Some(device) => (capture::open_camera(device, record.fps).wrap_err_with(|| format!("failed to open {device}"))?, record.region),
+        None => capture::open_screen(record.region, record.fps).wrap_err("failed to start screen capture")?,
+    };
+    let mut stream = ctx.best_stream()?;
+    // capture devices mostly produce rawvideo, which ffmpeg picks the decoder for
+    let decoder = pick_decoder(&stream, args)?.with_fps_cap(fps_cap(args));
+    let duration = record.duration.map_or(f64::INFINITY, |d| d.as_secs_f64());
+    let output = &record.output;
+    let (part, settings) = part_path(output, settings)?;
+    let settings = &settings;
+    let time = Instant::now();
+
+    let filters = FilterChain::from_args(args, None, crop, Orientation::default(), duration)?;
+    let estimated_frames = duration.is_finite().then(|| (duration * record.fps as f64).ceil() as u64);
+    let pb = match estimated_frames {
+        Some(estimated_frames) => {
+            let pb = ProgressBar::new(settings.max_frames.map_or(estimated_frames, |max| estimated_frames.min(max as u64)));
+            pb.set_style(progress_style(args));
+            pb
+        },
+        None => {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::default_spinner().template(" {prefix:.green.bright} {msg} {pos} frames, press Enter or Ctrl-C to stop"));
+            pb
         },
     };
+    pb.set_prefix("Recording");
+    let label = Label::new(output.file_name().unwrap_or_else(|| unreachable!()), 0);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    if record.duration.is_none() {
+        let stop = stop.clone();
+        // the thread stays blocked on stdin if recording ends otherwise, which is fine as we exit afterwards
+        thread::spawn(move || {
+            io::stdin().read_line(&mut String::new()).ok();
+            stop.store(true, Ordering::Relaxed);
+        });
+    }
+
+    let stats = transcode(&part, settings, filters, &pb, &label, estimated_frames, |emit| {
+        let mut start = None;
+        for frame in stream.decode(decoder)? {
+            let (image, pts) = frame?;
+            // capture devices use wall clock timestamps
+            let pts = pts - *start.get_or_insert(pts);
+            let stopped = stop.load(Ordering::Relaxed) || interrupt::requested();
+            if pts >= duration || stopped || !emit(Frame { image, pts })? {
+                break;
+            }
+        }
+        Ok(())
+    })?;
+    finish_output(args, settings, &part, output, None, time, stats)
+}
+
+/// Encodes the frames of an input, typically a gif, into a regular video without going through the filters.
+fn to_video(args: &Args, to_video: &ToVideoArgs) -> Result<()> {
+    let (input, output) = (&to_video.input, &to_video.output);
+    let time = Instant::now();
+    let mut ctx = open_input(input).wrap_err_with(|| format!("failed to open {input}"))?;
+    let duration = ctx.duration() as f64 / f::AV_TIME_BASE as f64;
+    let mut stream = ctx.best_stream()?;
+    let decoder = pick_decoder(&stream, args)?;
+    let (num, den) = stream.fps();
+
+    let pb = ProgressBar::new((duration * num as f64 / den as f64).ceil().max(1.0) as u64);
+    pb.set_style(progress_style(args));
+    pb.set_prefix("Converting");
+    if progress::is_json() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    pb.set_message(Label::new(output.file_name().unwrap_or_else(|| unreachable!()), 0).render(layout::terminal_width()));
+
+    let mut frames = 0;
+    let result: Result<()> = (|| {
+        let mut encoder = None;
+        for frame in stream.decode(decoder)? {
+            interrupt::check()?;
+            let (image, pts) = frame?;
+            let encoder = match &mut encoder {
+                Some(encoder) => encoder,
+                None => encoder.insert(encoder::open_video(output, image.width(), image.height(), args.quality)?),
+            };
+            encoder.encode(image.as_ref(), pts)?;
+            frames += 1;
+            pb.inc(1);
+        }
+        encoder.ok_or_else(|| eyre!("{input} has no frames"))?.finish()
+    })();
+    pb.finish_and_clear();
+    if result.is_err() {
+        fs::remove_file(output).ok();
+    }
+    result?;
+
+    let human = |size: u64| size.file_size(file_size_opts::CONVENTIONAL).unwrap_or_else(|_| unreachable!());
+    let (size, input_size) = (fs::metadata(output)?.len(), fs::metadata(input)?.len());
+    progress::emit(json!({
+        "event": "finished", "input": input.as_str(), "output": output.as_str(), "size": size,
+        "seconds": time.elapsed().as_secs_f64(), "frames": frames,
+    }));
+    info!("Finished {} in {}s, {} from {} ({:.2}x), {frames} frames",
+        output.file_name().unwrap_or_else(|| unreachable!()).bright_cyan(), time.elapsed().as_secs(), human(size),
+        human(input_size), size as f64 / input_size.max(1) as f64);
+    Ok(())
+}
+
+/// Builds the palette for `--save-palette` from the gifs written, if it was given.
+fn save_palette(args: &Args, outputs: &[Utf8PathBuf]) -> Result<()> {
+    let (Some(path), false) = (&args.save_palette, outputs.is_empty()) else { return Ok(()) };
+    let mut builder = PaletteBuilder::default();
+    for output in outputs {
+        builder.add_gif(output)?;
+    }
+    builder.save(path)?;
+    info!("Saved the palette to {path}");
+    Ok(())
+}
+
+/// Builds the bar style from `--progress-template` and `--progress-chars`; `{fps}` is accepted for indicatif's
+/// `{per_sec}`.
+fn progress_style(args: &Args) -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template(&args.progress_template.replace("{fps", "{per_sec"))
+        .progress_chars(&args.progress_chars)
+}
+
+/// What `transcode` wrote, for the summary line.
+struct TranscodeStats {
+    frames: usize,
+    /// Seconds between the first and the last frame.
+    span: f64,
+    /// Whether frames were dropped because of `--max-frames`.
+    truncated: bool,
+    /// Number of frames `produce` emitted before they went through the filters.
+    decoded: u64,
+    /// Problems in the input, filled in by the caller where it knows them.
+    decode: DecodeStats,
+    /// The encoded frames, for `--metrics`.
+    reference: Option<Reference>,
+}
+
+/// Relative difference between the estimated and the real frame count above which a warning is printed.
+const ESTIMATE_TOLERANCE: f64 = 0.2;
+
+/// Encodes the frames produced by `produce` on a worker thread into `output`, removing it again on failure.
+///
+/// `label` is shown as the message of `pb` and laid out again whenever the terminal is resized.
+/// `produce` should stop once the callback returns `false`. `estimated_frames` is the number of frames `produce`
+/// is expected to emit; the length of `pb` is corrected as soon as the real number turns out to be different.
+fn transcode<F>(output: &Utf8Path, settings: &EncodeSettings, mut filters: FilterChain, pb: &ProgressBar, label: &Label,
+        estimated_frames: Option<u64>, produce: F) -> Result<TranscodeStats>
+where
+    F: FnOnce(&mut dyn FnMut(Frame) -> Result<bool>) -> Result<()> + Send,
+{
+    struct ProgressAdapter<'a> {
+        pb: &'a ProgressBar,
+        label: &'a Label,
+        /// Name of the output once it is finished, for `--progress json`.
+        output: &'a str,
+        columns: Option<usize>,
+        /// Last percentage shown in the terminal title.
+        percent: Option<u64>,
+    }
+
+    impl ProgressAdapter<'_> {
+        fn relayout(&mut self) {
+            let columns = layout::terminal_width();
+            if columns != self.columns {
+                self.columns = columns;
+                self.pb.set_message(self.label.render(columns));
+            }
+        }
+
+        fn update_title(&mut self) {
+            let Some(title) = self.label.title() else { return };
+            let percent = match self.pb.length() {
+                0 => 0,
+                length => (self.pb.position() * 100 / length).min(100),
+            };
+            if self.percent != Some(percent) {
+                self.percent = Some(percent);
+                title::set(&format!("{title} {percent}%"));
+            }
+        }
+    }
+
+    impl ProgressReporter for ProgressAdapter<'_> {
+        fn increase(&mut self) -> bool {
+            self.relayout();
+            self.pb.inc(1);
+            self.update_title();
+            // spinners are bars of length u64::MAX
+            let frames = Some(self.pb.length()).filter(|length| *length != u64::MAX);
+            let frame = self.pb.position();
+            progress::emit(json!({ "event": "progress", "output": self.output, "frame": frame, "frames": frames }));
+            true
+        }
 
-    let gif = webm.with_extension("gif");
-    Some((webm, gif))
+        fn done(&mut self, _: &str) {}
+    }
+
+    if progress::is_json() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    let columns = layout::terminal_width();
+    pb.set_message(label.render(columns));
+    // the `.part` file is only renamed once it is complete
+    let name = output.as_str().strip_suffix(".part").unwrap_or(output.as_str());
+    let mut progress = ProgressAdapter { pb, label, output: name, columns, percent: None };
+    progress.update_title();
+    let (mut collector, writer) = encoder::new(settings.format_for(output)?, settings)?;
+
+    let result = thread::scope(|scope| {
+        let handle = scope.spawn(move |_| {
+            let max_frames = settings.max_frames.unwrap_or(usize::MAX);
+            let mut frame_index = 0;
+            let mut pts_range = None;
+            let mut truncated = false;
+            let mut decoded = 0;
+            let mut length = estimated_frames.map(|n| n.min(max_frames as u64));
+            let mut reference = settings.metrics.then(Reference::default);
+            let mut ready = Vec::new();
+            // returns false once frames start being dropped
+            let mut add = |ready: &mut Vec<Frame>| {
+                for frame in ready.drain(..) {
+                    if frame_index >= max_frames {
+                        truncated = true;
+                        break;
+                    }
+                    let (first, _) = *pts_range.get_or_insert((frame.pts, frame.pts));
+                    pts_range = Some((first, frame.pts));
+                    if let Some(reference) = &mut reference {
+                        let size = encoder::output_size(settings, frame.image.width(), frame.image.height());
+                        reference.add(frame.image.as_ref(), frame.pts, size);
+                    }
+                    collector.add_frame(frame_index, frame.image, frame.pts)?;
+                    frame_index += 1;
+
+                    // keep the bar from sitting at 100% while frames are still coming in
+                    if let Some(length) = length.as_mut().filter(|length| frame_index as u64 >= **length) {
+                        *length = frame_index as u64 * 11 / 10 + 1;
+                        pb.set_length(*length);
+                    }
+                }
+                Result::<_>::Ok(!truncated)
+            };
+
+            produce(&mut |frame| {
+                decoded += 1;
+                filters.process(frame, &mut ready)?;
+                add(&mut ready)
+            })?;
+            filters.finish(&mut ready)?;
+            add(&mut ready)?;
+            if length.is_some() {
+                pb.set_length(frame_index as u64);
+            }
+            Ok(TranscodeStats {
+                frames: frame_index,
+                span: pts_range.map_or(0.0, |(first, last)| last - first),
+                truncated,
+                decoded,
+                decode: DecodeStats::default(),
+                reference,
+            })
+        });
+
+        let result = writer.write(output, &mut progress);
+        handle.join().unwrap().and_then(|stats| result.map(|()| stats))
+    });
+
+    if let (Ok(stats), Some(estimate)) = (&result, estimated_frames) {
+        if !stats.truncated && stats.decoded.abs_diff(estimate) as f64 > estimate as f64 * ESTIMATE_TOLERANCE {
+            pb.suspend(|| warn!("{output} has {} frames, but its metadata suggested about {estimate}", stats.decoded));
+        }
+    }
+
+    pb.finish_and_clear();
+    if result.is_err() {
+        if !encoder::is_stdout(output) {
+            fs::remove_file(output).ok();
+        }
+    }
+    result
 }
 
-fn left_pad(str: &str, target_width: usize) -> String {
-    let input_width = unicode_width::UnicodeWidthStr::width_cjk(str);
-    if target_width > input_width {
-        let mut s = String::with_capacity(target_width);
-        for _ in 0..(target_width - input_width) {
-            s.push(' ');
+/// Where `output` is written until it is complete, e.g. `clip.gif.part`, so that a crash or Ctrl-C never leaves a
+/// file that looks finished and gets skipped as already transcoded. The settings returned don't need the extension
+/// of `output` to tell the format. Standard output and frame directories are written in place.
+fn part_path(output: &Utf8Path, settings: &EncodeSettings) -> Result<(Utf8PathBuf, EncodeSettings)> {
+    let format = settings.format_for(output)?;
+    let settings = EncodeSettings { format: Some(format), ..*settings };
+    if encoder::is_stdout(output) || format == Format::Frames {
+        return Ok((output.to_owned(), settings));
+    }
+    Ok((Utf8PathBuf::from(format!("{output}.part")), settings))
+}
+
+/// Runs the post-processing steps on an output written to `part`, moves it to `output` and prints the summary
+/// line.
+fn finish_output(args: &Args, settings: &EncodeSettings, part: &Utf8Path, output: &Utf8Path, input_size: Option<u64>,
+        time: Instant, stats: TranscodeStats) -> Result<()> {
+    let unoptimized_size = output_size(part)?;
+    let is_gif = settings.format_for(part)? == Format::Gif && !encoder::is_stdout(part);
+    let result: Result<()> = (|| {
+        if let (Some(path), true) = (&args.use_palette, is_gif) {
+            let palette = Palette::load(path)?;
+            palette::rewrite_with_palette(part, &palette, settings.repeat)
+                .wrap_err_with(|| format!("failed to apply the palette to {output}"))?;
+        }
+        let mut interlaced = false;
+        if args.optimize && is_gif {
+            match optimize::gifsicle(part, args.interlace) {
+                Ok(()) => interlaced = args.interlace,
+                Err(e) => warn!("failed to optimize {}: {:#}", output, e),
+            }
+        }
+        if args.interlace && is_gif && !interlaced {
+            interlace::rewrite_interlaced(part, settings.repeat)
+                .wrap_err_with(|| format!("failed to interlace {output}"))?;
+        }
+        if part != output {
+            fs::rename(part, output).wrap_err_with(|| format!("failed to move {part} to {output}"))?;
+        }
+        Ok(())
+    })();
+    if result.is_err() && part != output {
+        fs::remove_file(part).ok();
+    }
+    result?;
+
+    progress::emit(json!({
+        "event": "finished", "output": output.as_str(), "size": output_size(output)?,
+        "seconds": time.elapsed().as_secs_f64(), "frames": stats.frames,
+    }));
+    let human = |size: u64| size.file_size(file_size_opts::CONVENTIONAL).unwrap_or_else(|_| unreachable!());
+    let mut summary = format!(
+        "Finished {} in {}s",
+        output.file_name().unwrap_or("stdout").bright_cyan(),
+        time.elapsed().as_secs(),
+    );
+    if let Some(size) = output_size(output)? {
+        summary += &format!(", {}", human(size));
+        if let Some(unoptimized_size) = unoptimized_size.filter(|s| args.optimize && *s != size) {
+            summary += &format!(" (optimized from {})", human(unoptimized_size));
         }
-        s
-    } else {
-        str.to_string()
+        if let Some(input_size) = input_size.filter(|s| *s > 0) {
+            summary += &format!(" from {} ({:.2}x)", human(input_size), size as f64 / input_size as f64);
+        }
+    }
+    summary += &format!(", {} frames", stats.frames);
+    if stats.frames > 1 && stats.span > 0.0 {
+        // the last frame is shown for about as long as the others
+        summary += &format!(" at {:.1} fps", (stats.frames - 1) as f64 / stats.span);
+    }
+    if let (true, Some(max)) = (stats.truncated, settings.max_frames) {
+        summary += &format!(", truncated to {} frames", max);
+    }
+    if !stats.decode.is_clean() || args.verbose > 0 && stats.decode.discarded_frames > 0 {
+        summary += &format!(", {}", stats.decode.to_string().yellow());
+    }
+    if args.verbose > 0 && stats.decode.other_stream_packets > 0 {
+        summary += &format!(", {} packets of other streams skipped", stats.decode.other_stream_packets);
+    }
+    if let (Some(reference), true) = (&stats.reference, is_gif) {
+        match metrics::compare(output, reference) {
+            Ok(metrics) => summary += &format!(", {metrics}"),
+            Err(e) => warn!("failed to measure the quality of {}: {:#}", output, e),
+        }
+    }
+    info!("{summary} [{settings}]");
+    Ok(())
+}
+
+/// Size of a written output, adding up the files for `--format frames`; unknown for stdout.
+fn output_size(output: &Utf8Path) -> Result<Option<u64>> {
+    if encoder::is_stdout(output) {
+        return Ok(None);
+    }
+    let metadata = fs::metadata(output).wrap_err_with(|| format!("failed to read {output}"))?;
+    if !metadata.is_dir() {
+        return Ok(Some(metadata.len()));
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(output)? {
+        size += entry?.metadata()?.len();
     }
+    Ok(Some(size))
 }
@@ -0,0 +1,145 @@
+use std::ffi::CString;
+use std::fmt;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{ensure, Context};
+use imgref::{ImgRef, ImgVec};
+use rgb::RGBA8;
+
+use crate::decoder::WebmContext;
+use crate::filter::resample_bilinear;
+
+/// Side of the square windows SSIM is computed over.
+const WINDOW: usize = 8;
+/// Distance between neighbouring windows, so that they overlap by half.
+const WINDOW_STEP: usize = 4;
+/// PSNR of identical frames, which would be infinite.
+const MAX_PSNR: f64 = 100.0;
+/// How much earlier than a source frame an output frame may start and still count as showing it, as gifs store
+/// delays in hundredths of a second.
+const TOLERANCE: f64 = 0.005;
+
+/// The luma of the frames that went into the encoder, scaled to the output size, to compare the output against.
+#[derive(Default)]
+pub struct Reference {
+    frames: Vec<(f64, ImgVec<u8>)>,
+}
+
+impl Reference {
+    /// Adds a frame shown from `pts` seconds on, which the encoder scales to `size`.
+    pub fn add(&mut self, image: ImgRef<RGBA8>, pts: f64, size: (usize, usize)) {
+        let scaled;
+        let image = if (image.width(), image.height()) == size {
+            image
+        } else {
+            scaled = resample_bilinear(image, 0.0, 0.0, image.width() as f32, image.height() as f32, size.0, size.1);
+            scaled.as_ref()
+        };
+        self.frames.push((pts, luma(image)));
+    }
+}
+
+/// How close an output is to its source, over all source frames.
+pub struct Metrics {
+    frames: usize,
+    ssim_sum: f64,
+    ssim_min: f64,
+    psnr_sum: f64,
+    psnr_min: f64,
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = self.frames.max(1) as f64;
+        write!(f, "SSIM {:.4} avg {:.4} min, PSNR {:.1} dB avg {:.1} dB min",
+            self.ssim_sum / frames, self.ssim_min, self.psnr_sum / frames, self.psnr_min)
+    }
+}
+
+/// Decodes `output` and compares every reference frame with the output frame shown at the same time.
+pub fn compare(output: &Utf8Path, reference: &Reference) -> Result<Metrics> {
+    let mut ctx = WebmContext::new(&CString::new(output.as_str())?)
+        .wrap_err_with(|| format!("failed to open {output} to measure its quality"))?;
+    let mut stream = ctx.best_stream()?;
+    let decoder = stream.find_decoder(None, &[])?;
+    let mut frames = stream.decode(decoder)?.peekable();
+
+    let mut metrics = Metrics { frames: 0, ssim_sum: 0.0, ssim_min: 1.0, psnr_sum: 0.0, psnr_min: MAX_PSNR };
+    let first_pts = reference.frames.first().map_or(0.0, |(pts, _)| *pts);
+    let mut shown = None;
+    for (pts, source) in &reference.frames {
+        let t = pts - first_pts;
+        // errors are taken out of the iterator to be returned
+        while matches!(frames.peek(), Some(Err(_)))
+                || matches!(frames.peek(), Some(Ok((_, next))) if *next <= t + TOLERANCE) {
+            let (image, _) = frames.next().unwrap_or_else(|| unreachable!())?;
+            shown = Some(luma(image.as_ref()));
+        }
+        let Some(shown) = &shown else { continue };
+        ensure!((shown.width(), shown.height()) == (source.width(), source.height()),
+            "{output} is {}x{}, but the frames were scaled to {}x{}", shown.width(), shown.height(), source.width(),
+            source.height());
+
+        let ssim = ssim(source.as_ref(), shown.as_ref());
+        let psnr = psnr(source.as_ref(), shown.as_ref());
+        metrics.frames += 1;
+        metrics.ssim_sum += ssim;
+        metrics.ssim_min = metrics.ssim_min.min(ssim);
+        metrics.psnr_sum += psnr;
+        metrics.psnr_min = metrics.psnr_min.min(psnr);
+    }
+    ensure!(metrics.frames > 0, "{output} has no frames to compare");
+    Ok(metrics)
+}
+
+/// Luma from 0 to 255, with transparent pixels counting as black.
+fn luma(image: ImgRef<RGBA8>) -> ImgVec<u8> {
+    let pixels = image.pixels()
+        .map(|px| ((px.r as u32 * 299 + px.g as u32 * 587 + px.b as u32 * 114) * px.a as u32 / (1000 * 255)) as u8)
+        .collect();
+    ImgVec::new(pixels, image.width(), image.height())
+}
+
+/// Mean structural similarity over overlapping windows, from 0 to 1.
+fn ssim(a: ImgRef<u8>, b: ImgRef<u8>) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let window_width = WINDOW.min(a.width());
+    let window_height = WINDOW.min(a.height());
+    let (mut sum, mut count) = (0.0, 0);
+    for top in (0..=a.height() - window_height).step_by(WINDOW_STEP) {
+        for left in (0..=a.width() - window_width).step_by(WINDOW_STEP) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in top..top + window_height {
+                for x in left..left + window_width {
+                    let (pa, pb) = (a[(x, y)] as f64, b[(x, y)] as f64);
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+            let n = (window_width * window_height) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covar = sum_ab / n - mean_a * mean_b;
+            sum += (2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2)
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            count += 1;
+        }
+    }
+    sum / count.max(1) as f64
+}
+
+/// Peak signal-to-noise ratio in dB, capped at `MAX_PSNR`.
+fn psnr(a: ImgRef<u8>, b: ImgRef<u8>) -> f64 {
+    let squared_error = a.pixels().zip(b.pixels()).map(|(pa, pb)| (pa as f64 - pb as f64).powi(2)).sum::<f64>();
+    let mse = squared_error / (a.width() * a.height()).max(1) as f64;
+    if mse == 0.0 {
+        return MAX_PSNR;
+    }
+    (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR)
+}
@@ -0,0 +1,23 @@
+use std::io::ErrorKind;
+use std::process::Command;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{bail, ensure, Context};
+
+/// Optimizes a gif in place with gifsicle (frame differencing, transparency substitution and LZW tuning).
+pub fn gifsicle(path: &Utf8Path, interlace: bool) -> Result<()> {
+    let mut command = Command::new("gifsicle");
+    command.args(["--batch", "-O3", "--no-warnings"]);
+    if interlace {
+        command.arg("--interlace");
+    }
+    let output = match command.arg(path).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => bail!("gifsicle is not installed or not in PATH"),
+        Err(e) => return Err(e).wrap_err("failed to run gifsicle"),
+    };
+    ensure!(output.status.success(), "gifsicle failed ({}): {}",
+        output.status, String::from_utf8_lossy(&output.stderr).trim());
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{bail, ensure, eyre, Context};
+use gif::{ColorOutput, DecodeOptions, Decoder, Encoder, Repeat};
+use image::{ImageBuffer, Rgba};
+use imagequant::HistogramEntry;
+use rgb::{RGB8, RGBA8};
+
+/// A palette shared by several gifs, stored as a PNG with one pixel per color.
+///
+/// Pixels with an alpha below 128 all stand for the transparent entry, which gifski's gifs need for the parts of a
+/// frame that do not change.
+pub struct Palette {
+    colors: Vec<RGB8>,
+    transparent: Option<u8>,
+}
+
+impl Palette {
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        let image = image::open(path).wrap_err_with(|| format!("failed to load palette {path}"))?.to_rgba8();
+        let mut colors = Vec::new();
+        let mut transparent = None;
+        for &Rgba([r, g, b, a]) in image.pixels() {
+            if a < 128 {
+                if transparent.is_none() {
+                    transparent = Some(colors.len());
+                    colors.push(RGB8::new(0, 0, 0));
+                }
+            } else if !colors.contains(&RGB8::new(r, g, b)) {
+                colors.push(RGB8::new(r, g, b));
+            }
+        }
+        ensure!(!colors.is_empty(), "palette {path} is empty");
+        ensure!(colors.len() <= 256, "palette {path} has {} colors, gifs can have at most 256", colors.len());
+        Ok(Self { colors, transparent: transparent.map(|i| i as u8) })
+    }
+
+    /// Index of the color closest to `color`.
+    fn nearest(&self, color: RGB8) -> u8 {
+        let distance = |c: &RGB8| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            // weighted like the eye's sensitivity to each channel
+            d(c.r, color.r) * 3 + d(c.g, color.g) * 4 + d(c.b, color.b) * 2
+        };
+        self.colors.iter().enumerate()
+            .filter(|&(i, _)| Some(i as u8) != self.transparent)
+            .min_by_key(|(_, c)| distance(c))
+            .map_or(0, |(i, _)| i as u8)
+    }
+}
+
+/// Rewrites every frame of a gif to use the colors of `palette` as its global palette.
+///
+/// Each frame's own colors are mapped to the closest palette colors, so frames that were quantized to very
+/// different colors lose detail. The frames are re-encoded with a plain LZW encoder, so gains from
+/// `--lossy-quality` are partially lost.
+pub fn rewrite_with_palette(path: &Utf8Path, palette: &Palette, repeat: Repeat) -> Result<()> {
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let decoder = options.read_info(BufReader::new(File::open(path)?))
+        .wrap_err_with(|| format!("failed to read {path}"))?;
+
+    let tmp = path.with_extension("gif.tmp");
+    match write_with_palette(decoder, &tmp, palette, repeat) {
+        Ok(()) => fs::rename(&tmp, path).wrap_err_with(|| format!("failed to replace {path}")),
+        Err(e) => {
+            fs::remove_file(&tmp).ok();
+            Err(e)
+        },
+    }
+}
+
+fn write_with_palette<R: Read>(mut decoder: Decoder<R>, output: &Utf8Path, palette: &Palette, repeat: Repeat)
+        -> Result<()> {
+    let global_palette = decoder.global_palette().unwrap_or(&[]).to_vec();
+    let shared = palette.colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect::<Vec<_>>();
+    let mut encoder = Encoder::new(BufWriter::new(File::create(output)?), decoder.width(), decoder.height(), &shared)?;
+    encoder.set_repeat(repeat)?;
+
+    while let Some(frame) = decoder.read_next_frame()? {
+        let mut frame = frame.clone();
+        let colors = frame.palette.as_deref().unwrap_or(&global_palette);
+        let mut map = colors.chunks_exact(3).map(|c| palette.nearest(RGB8::new(c[0], c[1], c[2]))).collect::<Vec<_>>();
+        map.resize(256, 0);
+        if let Some(index) = frame.transparent {
+            let Some(transparent) = palette.transparent else {
+                bail!("the palette has no transparent entry, which the gif needs; add a transparent pixel to it");
+            };
+            map[index as usize] = transparent;
+            frame.transparent = Some(transparent);
+        }
+        frame.buffer = frame.buffer.iter().map(|&i| map[i as usize]).collect::<Vec<_>>().into();
+        frame.palette = None;
+        encoder.write_frame(&frame)?;
+    }
+
+    encoder.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Counts the colors of several gifs to build one palette that suits all of them.
+#[derive(Default)]
+pub struct PaletteBuilder {
+    counts: HashMap<RGB8, u32>,
+    transparent: bool,
+}
+
+impl PaletteBuilder {
+    pub fn add_gif(&mut self, path: &Utf8Path) -> Result<()> {
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let mut decoder = options.read_info(BufReader::new(File::open(path)?))
+            .wrap_err_with(|| format!("failed to read {path}"))?;
+        let global_palette = decoder.global_palette().unwrap_or(&[]).to_vec();
+
+        while let Some(frame) = decoder.read_next_frame()? {
+            let mut uses = [0u32; 256];
+            for &i in frame.buffer.iter() {
+                uses[i as usize] += 1;
+            }
+            let colors = frame.palette.as_deref().unwrap_or(&global_palette);
+            for (i, c) in colors.chunks_exact(3).enumerate() {
+                if uses[i] == 0 {
+                    continue;
+                }
+                if frame.transparent == Some(i as u8) {
+                    self.transparent = true;
+                    continue;
+                }
+                let count = self.counts.entry(RGB8::new(c[0], c[1], c[2])).or_default();
+                *count = count.saturating_add(uses[i]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Quantizes the colors seen so far to at most 256 and writes them to `path` as a PNG.
+    pub fn save(&self, path: &Utf8Path) -> Result<()> {
+        ensure!(!self.counts.is_empty(), "no colors to build a palette from");
+        let mut attr = imagequant::new();
+        attr.set_max_colors(if self.transparent { 255 } else { 256 }).map_err(|e| eyre!("{e}"))?;
+        let entries = self.counts.iter()
+            .map(|(color, &count)| HistogramEntry { color: RGBA8::new(color.r, color.g, color.b, 255), count })
+            .collect::<Vec<_>>();
+        let mut histogram = imagequant::Histogram::new(&attr);
+        histogram.add_colors(&entries, 0.0).map_err(|e| eyre!("{e}"))?;
+        let mut result = histogram.quantize(&attr).map_err(|e| eyre!("failed to build the palette: {e}"))?;
+
+        let mut colors = result.palette().to_vec();
+        if self.transparent {
+            colors.push(RGBA8::new(0, 0, 0, 0));
+        }
+        let pixels = colors.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect();
+        let image = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(colors.len() as u32, 1, pixels)
+            .unwrap_or_else(|| unreachable!());
+        image.save(path).wrap_err_with(|| format!("failed to write palette {path}"))
+    }
+}
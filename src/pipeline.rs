@@ -0,0 +1,219 @@
+use std::ffi::CString;
+use std::io::{Read, Seek, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use color_eyre::eyre::{eyre, Context};
+use ffmpeg_sys_next as f;
+use gifski::progress::ProgressReporter;
+use tracing::{trace, warn};
+
+use crate::cli::Args;
+use crate::decoder::{Decoder, DecodeStats, Scaler, ScaleFilter, ToneMap, WebmContext, WebmStream,
+    DEFAULT_DECODER_PRIORITY};
+use crate::encoder;
+use crate::filter::{FilterChain, Frame, Orientation, Rotate};
+use crate::settings::EncodeSettings;
+
+/// Part of an input to convert, from `--start`, `--end` and `--duration`.
+#[derive(Clone, Copy, Default)]
+pub struct Trim {
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl Trim {
+    pub fn from_args(args: &Args) -> Self {
+        let start = args.start.unwrap_or_default();
+        Self { start, end: args.end.or_else(|| args.duration.map(|duration| start + duration)) }
+    }
+
+    /// Shortens `duration` in `AV_TIME_BASE` units to the trimmed part.
+    pub fn apply(&self, duration: u64) -> u64 {
+        let to_base = |d: Duration| (d.as_secs_f64() * f::AV_TIME_BASE as f64) as u64;
+        let end = self.end.map_or(duration, |end| duration.min(to_base(end)));
+        end.saturating_sub(to_base(self.start))
+    }
+}
+
+/// Decodes every `frame_step`th frame of the `trim`med part of `stream` into `emit` until it returns `false`.
+/// Timestamps start over at the start of `trim`.
+///
+/// `scaler` is used if it fits the frames and replaced by the one used for them. Returns the problems the decoder
+/// got past.
+pub fn decode_frames(stream: &mut WebmStream, decoder: Decoder, trim: Trim, frame_step: usize,
+        scaler: &mut Option<Scaler>, emit: &mut dyn FnMut(Frame) -> Result<bool>) -> Result<DecodeStats> {
+    let start_time = stream.start_time();
+    let mut decoder = stream.decode(decoder)?;
+    decoder.set_scaler(scaler.take());
+    if !trim.start.is_zero() {
+        decoder.seek(trim.start)?;
+    }
+    for (index, frame) in decoder.by_ref().enumerate() {
+        let (image, pts) = frame?;
+        trace!("Decoded frame {index} at {pts:.3}s");
+        if trim.end.map_or(false, |end| pts - start_time >= end.as_secs_f64()) {
+            break;
+        }
+        // gifski expects the first frame at 0; the one showing at the start may begin a bit earlier
+        let pts = if trim.start.is_zero() { pts } else { (pts - start_time - trim.start.as_secs_f64()).max(0.0) };
+        if index % frame_step != 0 {
+            continue;
+        }
+        if !emit(Frame { image, pts })? {
+            break;
+        }
+    }
+    *scaler = decoder.take_scaler();
+    Ok(decoder.stats())
+}
+
+/// Converts a single input to a gif written to any sink, the way the binary converts each of its inputs.
+///
+/// Embedding programs build `EncodeSettings` and `FilterChain` themselves, or from `cli::Args` like the binary;
+/// `--fps` is not part of the chain, it goes to [`Pipeline::with_fps_cap`] as [`fps_cap`](crate::filter::fps_cap).
+pub struct Pipeline {
+    ctx: WebmContext,
+    settings: EncodeSettings,
+    filters: FilterChain,
+    decoder: Option<String>,
+    scale_filter: ScaleFilter,
+    tone_map: ToneMap,
+    fps_cap: Option<f64>,
+    trim: Trim,
+    autorotate: bool,
+}
+
+impl Pipeline {
+    /// Opens a file to convert with the default settings and no filters.
+    pub fn open(path: &Utf8Path) -> Result<Self> {
+        let ctx = WebmContext::new(&CString::new(path.as_str())?).wrap_err_with(|| format!("failed to open {path}"))?;
+        Ok(Self::from_context(ctx))
+    }
+
+    /// Reads the input from memory or anywhere else instead of a file.
+    pub fn from_reader(reader: impl Read + Seek + Send + 'static) -> Result<Self> {
+        Ok(Self::from_context(WebmContext::from_reader(reader)?))
+    }
+
+    fn from_context(ctx: WebmContext) -> Self {
+        Self {
+            ctx,
+            settings: EncodeSettings::default(),
+            filters: FilterChain::default(),
+            decoder: None,
+            scale_filter: ScaleFilter::default(),
+            tone_map: ToneMap::default(),
+            fps_cap: None,
+            trim: Trim::default(),
+            autorotate: true,
+        }
+    }
+
+    /// Settings for gifski; `format` is ignored, as the output is always a gif.
+    pub fn with_settings(mut self, settings: EncodeSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Filters every decoded frame goes through before it is encoded.
+    pub fn with_filters(mut self, filters: FilterChain) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Decodes with the libavcodec decoder called `name` instead of the first one that can.
+    pub fn with_decoder(mut self, name: &str) -> Self {
+        self.decoder = Some(name.to_owned());
+        self
+    }
+
+    /// Converts the decoded frames to RGBA with `filter` instead of the fast bilinear default.
+    pub fn with_scale_filter(mut self, filter: ScaleFilter) -> Self {
+        self.scale_filter = filter;
+        self
+    }
+
+    /// Tone maps HDR video with `curve` instead of Hable.
+    pub fn with_tone_map(mut self, curve: ToneMap) -> Self {
+        self.tone_map = curve;
+        self
+    }
+
+    /// Decodes at most `fps` frames per second, dropping the others before they are converted; `None` keeps every
+    /// frame.
+    pub fn with_fps_cap(mut self, fps: Option<f64>) -> Self {
+        self.fps_cap = fps;
+        self
+    }
+
+    /// Converts only the part of the input within `trim`.
+    pub fn with_trim(mut self, trim: Trim) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Whether frames are turned upright according to the rotation stored with the video, which they are by default.
+    pub fn with_autorotate(mut self, autorotate: bool) -> Self {
+        self.autorotate = autorotate;
+        self
+    }
+
+    /// Decodes, filters and encodes the whole input, writing the gif to `sink`. `progress` is told about every
+    /// encoded frame.
+    pub fn run(self, sink: impl Write, progress: &mut dyn ProgressReporter) -> Result<()> {
+        let Self { mut ctx, settings, mut filters, decoder, scale_filter, tone_map, fps_cap, trim, autorotate } = self;
+        let mut stream = ctx.best_stream()?;
+        let priority = DEFAULT_DECODER_PRIORITY.split(',').map(str::to_owned).collect::<Vec<_>>();
+        let decoder = stream.find_decoder(decoder.as_deref(), &priority)?
+            .with_scale_filter(scale_filter)
+            .with_tone_map(tone_map)
+            .with_fps_cap(fps_cap);
+        let info = stream.info();
+        if info.webm_alpha && !decoder.decodes_webm_alpha() {
+            warn!("the input has an alpha channel, which the {} decoder drops; only libvpx decodes it", decoder.name());
+        }
+        let orientation = if autorotate { Orientation::from_info(&info) } else { Orientation::default() };
+        if !orientation.is_upright() {
+            // the filters of the caller see the frames the way they are shown
+            filters.push_front(Rotate(orientation));
+        }
+        let (collector, writer) = settings.new_gifski()?;
+        let (tx, rx) = mpsc::sync_channel(settings.queue_length);
+        let feeder = encoder::feed_gifski(rx, collector);
+
+        thread::scope(|scope| {
+            let handle = scope.spawn(move |_| {
+                let max_frames = settings.max_frames.unwrap_or(usize::MAX);
+                let mut index = 0;
+                let mut ready = Vec::new();
+                // returns false once `max_frames` is reached
+                let mut add = |ready: &mut Vec<Frame>| {
+                    for frame in ready.drain(..) {
+                        if index >= max_frames {
+                            return Ok(false);
+                        }
+                        // the feeder only hangs up after failing, its error is reported instead
+                        tx.send((frame.image, frame.pts)).map_err(|_| eyre!("the encoder stopped early"))?;
+                        index += 1;
+                    }
+                    Result::<_>::Ok(true)
+                };
+
+                decode_frames(&mut stream, decoder, trim, 1, &mut None, &mut |frame| {
+                    filters.process(frame, &mut ready)?;
+                    add(&mut ready)
+                })?;
+                filters.finish(&mut ready)?;
+                add(&mut ready).map(drop)
+            });
+
+            let result = writer.write(sink, progress).map_err(Into::into).and(feeder.join().unwrap());
+            // a failed writer makes the decoding fail too, its own error is the one to report
+            result.and(handle.join().unwrap())
+        })
+    }
+}
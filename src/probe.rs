@@ -0,0 +1,68 @@
+use std::ffi::CString;
+use std::fs;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use humansize::{file_size_opts, FileSize};
+
+use crate::cli::Args;
+use crate::decoder::{StreamInfo, WebmContext};
+
+pub struct Skip {
+    /// Flag that excluded the input.
+    pub filter: &'static str,
+    pub reason: String,
+}
+
+impl Skip {
+    fn new(filter: &'static str, reason: String) -> Option<Self> {
+        Some(Self { filter, reason })
+    }
+}
+
+/// Reads what the container header says about the video stream of `path`, without decoding it.
+pub fn stream_info(path: &Utf8Path) -> Result<StreamInfo> {
+    let mut ctx = WebmContext::probe(&CString::new(path.as_str())?)?;
+    let info = ctx.best_stream()?.info();
+    Ok(info)
+}
+
+/// Checks the input against the file size, duration and frame size filters, returning why it should be skipped.
+///
+/// Only the container header is read, so this stays cheap for long recordings.
+pub fn skip_reason(args: &Args, path: &Utf8Path) -> Result<Option<Skip>> {
+    if args.min_size.is_some() || args.max_size.is_some() {
+        let size = fs::metadata(path)?.len();
+        let human = |size: u64| size.file_size(file_size_opts::CONVENTIONAL).unwrap_or_else(|_| unreachable!());
+        if let Some(min) = args.min_size.filter(|min| size < *min) {
+            return Ok(Skip::new("--min-size", format!("smaller than {}", human(min))));
+        }
+        if let Some(max) = args.max_size.filter(|max| size > *max) {
+            return Ok(Skip::new("--max-size", format!("larger than {}", human(max))));
+        }
+    }
+
+    if args.min_duration.is_none() && args.max_duration.is_none() && args.min_width.is_none() && args.min_height.is_none() {
+        return Ok(None);
+    }
+
+    let StreamInfo { width, height, duration, .. } = stream_info(path)?;
+
+    if let (Some(min), Some(duration)) = (args.min_duration, duration) {
+        if duration < min {
+            return Ok(Skip::new("--min-duration", format!("shorter than {:.1}s", min.as_secs_f64())));
+        }
+    }
+    if let (Some(max), Some(duration)) = (args.max_duration, duration) {
+        if duration > max {
+            return Ok(Skip::new("--max-duration", format!("longer than {:.1}s", max.as_secs_f64())));
+        }
+    }
+    if let Some(min) = args.min_width.filter(|min| width < *min) {
+        return Ok(Skip::new("--min-width", format!("narrower than {min}px")));
+    }
+    if let Some(min) = args.min_height.filter(|min| height < *min) {
+        return Ok(Skip::new("--min-height", format!("shorter than {min}px")));
+    }
+    Ok(None)
+}
@@ -0,0 +1,56 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::Value;
+
+/// Whether `emit` writes anything, set once by `set_format`.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// How the progress of conversions is shown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgressFormat {
+    /// Progress bars on the terminal.
+    Bar,
+    /// One JSON object per line on stdout instead of the bars, for front-ends; status lines go to stderr.
+    Json,
+}
+
+impl Default for ProgressFormat {
+    fn default() -> Self {
+        ProgressFormat::Bar
+    }
+}
+
+/// Parses the names listed in `--progress`.
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(ProgressFormat::Bar),
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(format!("expected bar or json, got {s:?}")),
+        }
+    }
+}
+
+pub fn set_format(format: ProgressFormat) {
+    JSON.store(format == ProgressFormat::Json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Writes `event` as one line on stdout for `ProgressFormat::Json`, e.g.
+/// `{"event":"progress","frame":12,"frames":240,"output":"clip.gif"}`. Lines written by different threads never
+/// interleave.
+pub fn emit(event: Value) {
+    if !is_json() {
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    // a front-end that stopped reading is no reason to stop converting
+    writeln!(stdout, "{event}").and_then(|()| stdout.flush()).ok();
+}
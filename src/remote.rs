@@ -0,0 +1,377 @@
+//! Spreading a batch over other machines.
+//!
+//! Workers connect to the coordinator, which sends them one input at a time together with its command line. The
+//! worker converts the input with those options and sends the output back. Both transfers carry a CRC-32 that the
+//! receiving side checks. A job whose worker disconnects or sends damaged data is handed to the next worker.
+//!
+//! Workers open with a greeting that carries the token of `--worker-token`, and the coordinator hangs up on the
+//! ones that send a different token. Without a token the coordinator only listens on loopback addresses, since
+//! anybody who can connect gets to run conversions with options of their choosing.
+//!
+//! Messages are a tag byte followed by fields; strings and files are prefixed with their length as a little endian
+//! u64, files are also followed by their CRC-32.
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::iter;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use color_eyre::Result;
+use color_eyre::eyre::{bail, ensure, eyre, Context};
+use tracing::{error, info, warn};
+
+use crate::cli::Args;
+
+/// Sent by workers when they connect, followed by the token as a string.
+const HELLO: &[u8; 4] = b"W2G\x02";
+const TAG_JOB: u8 = b'J';
+const TAG_OUTPUT: u8 = b'O';
+const TAG_ERROR: u8 = b'E';
+/// Longest string accepted, to fail early on garbage instead of allocating it.
+const MAX_STRING: u64 = 1 << 20;
+/// How often `WorkerPool::finish` says what it is waiting for.
+const WAIT_NOTICE: Duration = Duration::from_secs(60);
+/// How long `WorkerPool::finish` waits without any worker connected before it fails the remaining jobs.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// An input to convert and where its output goes.
+pub struct Job {
+    pub input: Utf8PathBuf,
+    pub output: Utf8PathBuf,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Job>,
+    /// Jobs queued or being converted.
+    pending: usize,
+    done: Vec<Job>,
+    failed: usize,
+    closed: bool,
+    /// Workers connected right now.
+    workers: usize,
+}
+
+/// Hands out jobs to the workers connecting to an address.
+pub struct WorkerPool {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl WorkerPool {
+    /// Listens on `addr`; `options` is the command line the workers convert with, without the program name. Only
+    /// workers sending `token` are served; without one `addr` has to be a loopback address.
+    pub fn listen(addr: &str, options: Vec<String>, token: Option<String>) -> Result<Self> {
+        if token.is_none() {
+            let addrs = addr.to_socket_addrs().wrap_err_with(|| format!("invalid address {addr}"))?.collect::<Vec<_>>();
+            ensure!(addrs.iter().all(|addr| addr.ip().is_loopback()),
+                "anybody who can reach {addr} could convert files on this machine; \
+                 set --worker-token or W2G_WORKER_TOKEN for the batch and its workers");
+        }
+        let listener = TcpListener::bind(addr).wrap_err_with(|| format!("failed to listen on {addr}"))?;
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let (options, token) = (Arc::new(options), Arc::new(token.unwrap_or_default()));
+        let pool_state = state.clone();
+        // the listener is left running until the process exits
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (state, options, token) = (pool_state.clone(), options.clone(), token.clone());
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().map_or_else(|_| "unknown".to_owned(), |addr| addr.to_string());
+                    if let Err(e) = serve(stream, &peer, &state, &options, &token) {
+                        warn!("lost worker {peer}: {e:#}");
+                    }
+                });
+            }
+        });
+        Ok(Self { state })
+    }
+
+    pub fn submit(&self, job: Job) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+        state.queue.push_back(job);
+        state.pending += 1;
+        condvar.notify_all();
+    }
+
+    /// Waits for every submitted job and returns the ones that were converted and the number that failed. Jobs
+    /// still queued after `IDLE_TIMEOUT` without any worker connected count as failed.
+    pub fn finish(self) -> (Vec<Job>, usize) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+        state.closed = true;
+        condvar.notify_all();
+        let mut notice = Instant::now();
+        let mut idle_since = None;
+        while state.pending > 0 {
+            if state.workers > 0 {
+                idle_since = None;
+            } else if idle_since.get_or_insert_with(Instant::now).elapsed() >= IDLE_TIMEOUT {
+                let left = state.queue.len();
+                error!("No worker connected for {} minutes, giving up on {left} files", IDLE_TIMEOUT.as_secs() / 60);
+                state.queue.clear();
+                state.pending -= left;
+                state.failed += left;
+                continue;
+            }
+            if notice.elapsed() >= WAIT_NOTICE {
+                match state.workers {
+                    0 => warn!("{} files are waiting for a worker to connect", state.pending),
+                    workers => info!("Waiting for {} files on {workers} workers", state.pending),
+                }
+                notice = Instant::now();
+            }
+            state = condvar.wait_timeout(state, WAIT_NOTICE).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        (state.done.drain(..).collect(), state.failed)
+    }
+}
+
+/// Feeds jobs to one worker until the batch is over or the connection fails.
+fn serve(stream: TcpStream, peer: &str, state: &(Mutex<State>, Condvar), options: &[String], token: &str)
+        -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut hello = [0; 4];
+    reader.read_exact(&mut hello)?;
+    ensure!(&hello == HELLO, "not a webm2gif worker of this version");
+    ensure!(same_token(&read_string(&mut reader)?, token), "wrong --worker-token");
+    info!("Worker {peer} connected");
+
+    let (lock, condvar) = state;
+    lock.lock().unwrap_or_else(|e| e.into_inner()).workers += 1;
+    let _connected = scopeguard::guard((), |()| {
+        lock.lock().unwrap_or_else(|e| e.into_inner()).workers -= 1;
+        condvar.notify_all();
+    });
+    loop {
+        let job = {
+            let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+            loop {
+                if let Some(job) = state.queue.pop_front() {
+                    break job;
+                }
+                if state.closed {
+                    return Ok(());
+                }
+                state = condvar.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+
+        let time = Instant::now();
+        match run_job(&mut reader, &mut writer, &job, options) {
+            Ok(result) => {
+                let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+                match result {
+                    Ok(()) => {
+                        info!("Finished {} on {peer} in {}s", job.output, time.elapsed().as_secs());
+                        state.done.push(job);
+                    },
+                    Err(message) => {
+                        error!("Failed to convert {} on {peer}: {message}", job.input);
+                        state.failed += 1;
+                    },
+                }
+                state.pending -= 1;
+                condvar.notify_all();
+            },
+            Err(e) => {
+                // somebody else gets to try
+                let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+                state.queue.push_front(job);
+                condvar.notify_all();
+                return Err(e);
+            },
+        }
+    }
+}
+
+/// Sends a job and receives its output; the inner result is the worker's own error message.
+fn run_job(reader: &mut impl Read, writer: &mut impl Write, job: &Job, options: &[String])
+        -> Result<Result<(), String>> {
+    writer.write_all(&[TAG_JOB])?;
+    write_u64(writer, options.len() as u64)?;
+    for option in options {
+        write_string(writer, option)?;
+    }
+    write_string(writer, job.input.file_name().unwrap_or("input"))?;
+    write_string(writer, job.output.file_name().unwrap_or("output"))?;
+    send_file(writer, &job.input)?;
+    writer.flush()?;
+
+    match read_u8(reader)? {
+        TAG_OUTPUT => {
+            let part = Utf8PathBuf::from(format!("{}.part", job.output));
+            let result = receive_file(reader, &part).and_then(|()| {
+                fs::rename(&part, &job.output).wrap_err_with(|| format!("failed to move {part} to {}", job.output))
+            });
+            if result.is_err() {
+                fs::remove_file(&part).ok();
+            }
+            result.map(Ok)
+        },
+        TAG_ERROR => Ok(Err(read_string(reader)?)),
+        tag => bail!("unexpected message {tag:#x}"),
+    }
+}
+
+/// Connects to the coordinator at `addr` with `token` and converts what it sends with `convert` until it hangs up.
+pub fn run_worker<F>(addr: &str, token: Option<&str>, mut convert: F) -> Result<()>
+where
+    F: FnMut(Args, &Utf8Path, &Utf8Path) -> Result<()>,
+{
+    let addrs = addr.to_socket_addrs().wrap_err_with(|| format!("invalid address {addr}"))?.collect::<Vec<_>>();
+    let stream = TcpStream::connect(addrs.as_slice()).wrap_err_with(|| format!("failed to connect to {addr}"))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(HELLO)?;
+    write_string(&mut writer, token.unwrap_or_default())?;
+    writer.flush()?;
+    info!("Connected to {addr}, waiting for files");
+
+    let dir = Utf8PathBuf::from_path_buf(env::temp_dir())
+        .map_err(|p| eyre!("invalid utf-8 path: {:?}", p))?
+        .join(format!("webm2gif-worker-{}", process::id()));
+    // separate directories, as inputs and outputs may have the same name
+    let (input_dir, output_dir) = (dir.join("in"), dir.join("out"));
+    for dir in [&input_dir, &output_dir] {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {dir}"))?;
+    }
+    let _cleanup = scopeguard::guard(dir, |dir| {
+        fs::remove_dir_all(dir).ok();
+    });
+
+    loop {
+        match read_u8(&mut reader) {
+            Ok(TAG_JOB) => {},
+            Ok(tag) => bail!("unexpected message {tag:#x} from {addr}"),
+            // the batch is over
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let count = read_u64(&mut reader)?;
+        let options = (0..count).map(|_| read_string(&mut reader)).collect::<io::Result<Vec<_>>>()?;
+        // only the name is used, so that the coordinator cannot write outside of the directory
+        let input = input_dir.join(file_name(&read_string(&mut reader)?)?);
+        let output = output_dir.join(file_name(&read_string(&mut reader)?)?);
+        receive_file(&mut reader, &input)?;
+
+        info!("Converting {}", input.file_name().unwrap_or_default());
+        let result = job_args(options, &input, &output).and_then(|args| convert(args, &input, &output));
+        match result {
+            Ok(()) => {
+                writer.write_all(&[TAG_OUTPUT])?;
+                send_file(&mut writer, &output)?;
+            },
+            Err(e) => {
+                writer.write_all(&[TAG_ERROR])?;
+                write_string(&mut writer, &format!("{e:#}"))?;
+            },
+        }
+        writer.flush()?;
+        fs::remove_file(&input).ok();
+        fs::remove_file(&output).ok();
+    }
+}
+
+/// Parses the coordinator's options for converting the single `input` into `output`.
+fn job_args(options: Vec<String>, input: &Utf8Path, output: &Utf8Path) -> Result<Args> {
+    let mut args = Args::try_parse_from(iter::once("webm2gif".to_owned()).chain(options))
+        .map_err(|e| eyre!("invalid options from the coordinator: {e}"))?;
+    // what to convert and where to are up to the coordinator
+    args.command = None;
+    args.inputs = vec![input.to_owned()];
+    args.output = Some(output.to_owned());
+    args.output_dir = None;
+    args.on_success = None;
+    args.save_palette = None;
+    args.workers = None;
+    Ok(args)
+}
+
+/// Compares tokens in a time that does not depend on where they differ.
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn file_name(name: &str) -> Result<&str> {
+    match Utf8Path::new(name).file_name() {
+        Some(file_name) if file_name == name => Ok(name),
+        _ => bail!("invalid file name {name:?}"),
+    }
+}
+
+fn send_file(writer: &mut impl Write, path: &Utf8Path) -> Result<()> {
+    let mut file = File::open(path).wrap_err_with(|| format!("failed to open {path}"))?;
+    write_u64(writer, file.metadata()?.len())?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+    }
+    writer.write_all(&hasher.finalize().to_le_bytes())?;
+    Ok(())
+}
+
+fn receive_file(reader: &mut impl Read, path: &Utf8Path) -> Result<()> {
+    let mut remaining = read_u64(reader)?;
+    let mut file = BufWriter::new(File::create(path).wrap_err_with(|| format!("failed to create {path}"))?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    while remaining > 0 {
+        let n = buf.len().min(remaining as usize);
+        reader.read_exact(&mut buf[..n])?;
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n])?;
+        remaining -= n as u64;
+    }
+    file.flush()?;
+    let mut crc = [0; 4];
+    reader.read_exact(&mut crc)?;
+    ensure!(u32::from_le_bytes(crc) == hasher.finalize(), "{path} was damaged in transfer");
+    Ok(())
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let len = read_u64(reader)?;
+    if len > MAX_STRING {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "string too long"));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_string(writer: &mut impl Write, s: &str) -> io::Result<()> {
+    write_u64(writer, s.len() as u64)?;
+    writer.write_all(s.as_bytes())
+}
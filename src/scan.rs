@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::Result;
+use color_eyre::eyre::{eyre, Context};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use jwalk::WalkDir;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tracing::warn;
+
+use crate::decoder::StreamInfo;
+
+/// How long a file has to go without changes before `watch_dir` takes it as finished, since recorders keep
+/// appending to a clip until it is saved.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+pub struct Input {
+    pub path: Utf8PathBuf,
+    /// Directory the input was found in, or its parent if it was given directly.
+    pub root: Utf8PathBuf,
+    /// Whether the input was found by scanning a directory or listed with `--files-from` rather than given directly.
+    pub scanned: bool,
+}
+
+/// The `--include` and `--exclude` globs, matched like the patterns of .gifignore relative to the scanned directory,
+/// so that `*.webm` matches in any subdirectory and `raw/` matches a directory. Excludes win over includes.
+#[derive(Clone, Default)]
+pub struct Globs {
+    /// When not empty, only files matching one of these are picked up.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Globs {
+    fn build(&self, root: &Path) -> Result<Override> {
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.include {
+            builder.add(glob).wrap_err_with(|| format!("invalid --include glob {glob:?}"))?;
+        }
+        // the last glob matching a path decides
+        for glob in &self.exclude {
+            builder.add(&format!("!{glob}")).wrap_err_with(|| format!("invalid --exclude glob {glob:?}"))?;
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Walks `dir` on a thread pool, descending into subdirectories if `recursive` is set, and sends every file with
+/// one of `extensions` through `tx` as soon as it is found so that conversions can start while the scan is still
+/// running.
+///
+/// Paths matching the gitignore-style patterns in `dir/.gifignore` or left out by `globs` are skipped. Symlinks to
+/// files are followed, symlinks to directories are not. Returns early once the receiver is gone.
+pub fn scan_dir(dir: &Utf8Path, recursive: bool, extensions: &[String], globs: &Globs, tx: &Sender<Result<Input>>) {
+    let ignore = load_gifignore(dir).and_then(|ignore| Ok((ignore, globs.build(dir.as_std_path())?)));
+    let (ignore, globs) = match ignore {
+        Ok(ignore) => ignore,
+        Err(e) => {
+            tx.send(Err(e)).ok();
+            return;
+        },
+    };
+
+    let walk = WalkDir::new(dir)
+        .sort(true)
+        .skip_hidden(false)
+        .follow_links(false)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        // dropping ignored directories here keeps them from being read at all
+        .process_read_dir(move |_, _, _, children| {
+            children.retain(|entry| match entry {
+                Ok(entry) => {
+                    let is_dir = entry.file_type().is_dir();
+                    !ignore.matched(entry.path(), is_dir).is_ignore() && !globs.matched(entry.path(), is_dir).is_ignore()
+                },
+                Err(_) => true,
+            });
+        });
+
+    for entry in walk {
+        let found = match entry {
+            Ok(entry) if entry.depth() == 0 || entry.file_type().is_dir() => continue,
+            Ok(entry) => check_input(entry.path(), dir, extensions).map(Ok),
+            Err(e) => Some(Err(e).wrap_err_with(|| format!("failed to list files in {dir}"))),
+        };
+        if let Some(found) = found {
+            if tx.send(found).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Watches `dir` for files with one of `extensions` that are created or changed, descending into subdirectories if
+/// `recursive` is set, and sends each through `tx` once it has been left alone for `SETTLE_TIME`. Paths matching
+/// `dir/.gifignore` or left out by `globs` are skipped. Runs until the receiver is gone.
+pub fn watch_dir(dir: &Utf8Path, recursive: bool, extensions: &[String], globs: &Globs, tx: &Sender<Result<Input>>) {
+    let (events_tx, events) = mpsc::channel();
+    let watcher = load_gifignore(dir).and_then(|ignore| {
+        // events name the files by their full path, which .gifignore patterns are matched relative to
+        let root = dir.canonicalize()?;
+        let globs = globs.build(&root)?;
+        let mut watcher = notify::recommended_watcher(events_tx)?;
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(&root, mode)?;
+        Ok((watcher, ignore, globs, root))
+    });
+    // the watcher stops when dropped
+    let (_watcher, ignore, globs, root) = match watcher.wrap_err_with(|| format!("failed to watch {dir}")) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tx.send(Err(e)).ok();
+            return;
+        },
+    };
+
+    // files changed lately, with the time of their last change
+    let mut pending = HashMap::<PathBuf, Instant>::new();
+    loop {
+        match events.recv_timeout(SETTLE_TIME / 4) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    pending.insert(path, Instant::now());
+                }
+            },
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {},
+            Ok(Err(e)) => {
+                if tx.send(Err(eyre!(e)).wrap_err_with(|| format!("failed to watch {dir}"))).is_err() {
+                    return;
+                }
+            },
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let settled = pending.iter()
+            .filter(|(_, changed)| changed.elapsed() >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in settled {
+            pending.remove(&path);
+            let ignored = path.strip_prefix(&root)
+                .map_or(false, |relative| ignore.matched_path_or_any_parents(relative, false).is_ignore())
+                || globs.matched(&path, false).is_ignore();
+            // files removed or renamed again before settling are gone by now
+            if ignored || !path.is_file() {
+                continue;
+            }
+            // the input keeps the directory as it was given, for its output to be placed relative to it
+            let path = match path.strip_prefix(&root) {
+                Ok(relative) => dir.as_std_path().join(relative),
+                Err(_) => path,
+            };
+            if let Some(found) = check_input(path, dir, extensions) {
+                if tx.send(Ok(found)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn load_gifignore(dir: &Utf8Path) -> Result<Gitignore> {
+    let path = dir.join(".gifignore");
+    if !path.is_file() {
+        return Ok(Gitignore::empty());
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(&path) {
+        return Err(e).wrap_err_with(|| format!("failed to read {path}"));
+    }
+    builder.build().wrap_err_with(|| format!("failed to read {path}"))
+}
+
+fn check_input(path: PathBuf, root: &Utf8Path, extensions: &[String]) -> Option<Input> {
+    let ext = path.extension().and_then(|ext| ext.to_str())?;
+    // cameras often write CLIP.WEBM
+    if !extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)) {
+        return None;
+    }
+    let path = match Utf8PathBuf::from_path_buf(path) {
+        Ok(p) => p,
+        Err(p) => {
+            warn!("skipping file with invalid utf-8 name: {:?}", p);
+            return None;
+        },
+    };
+    match fs::metadata(&path) {
+        Ok(m) if m.is_file() => Some(Input { path, root: root.to_owned(), scanned: true }),
+        Ok(_) => None,
+        // e.g. removed since it was listed, which the rest of the batch does not care about
+        Err(e) => {
+            warn!("skipping {path}: {e}");
+            None
+        },
+    }
+}
+
+/// Picks where the output for `input` is written, replacing its extension with `extension`. `root` is the
+/// directory `input` was found in, whose structure is recreated under `output_dir` unless `flatten` is set.
+///
+/// `name` replaces the file name, e.g. one rendered from a `NameTemplate`; `extension` is added unless it already
+/// ends with it.
+pub fn output_path(input: &Utf8Path, root: &Utf8Path, output_dir: Option<&Utf8Path>, flatten: bool,
+        name: Option<&str>, extension: &str) -> Utf8PathBuf {
+    let path = match output_dir {
+        Some(output_dir) => {
+            let file_name = input.file_name().unwrap_or_else(|| unreachable!());
+            let relative = match input.strip_prefix(root) {
+                Ok(relative) if !flatten => relative,
+                _ => Utf8Path::new(file_name),
+            };
+            output_dir.join(relative)
+        },
+        None => input.to_owned(),
+    };
+    match name {
+        // frames are written to a directory, which has no extension
+        Some(name) if extension.is_empty() => path.with_file_name(name),
+        Some(name) => {
+            let stem = name.strip_suffix(&format!(".{extension}")).unwrap_or(name);
+            path.with_file_name(format!("{stem}.{extension}"))
+        },
+        None => path.with_extension(extension),
+    }
+}
+
+/// Output file name with placeholders for properties of the input, e.g. `{stem}_{width}x{height}.gif`.
+#[derive(Clone)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    const PLACEHOLDERS: [&'static str; 5] = ["stem", "ext", "width", "height", "fps"];
+
+    /// Whether rendering needs the `StreamInfo` of the input.
+    pub fn needs_info(&self) -> bool {
+        ["{width}", "{height}", "{fps}"].iter().any(|placeholder| self.0.contains(placeholder))
+    }
+
+    pub fn render(&self, input: &Utf8Path, info: Option<&StreamInfo>) -> String {
+        let mut name = self.0
+            .replace("{stem}", input.file_stem().unwrap_or_default())
+            .replace("{ext}", input.extension().unwrap_or_default());
+        if let Some(info) = info {
+            let (num, den) = info.frame_rate;
+            let fps = match den {
+                0 => "0".to_owned(),
+                _ if num % den == 0 => (num / den).to_string(),
+                _ => format!("{:.2}", num as f64 / den as f64),
+            };
+            name = name
+                .replace("{width}", &info.width.to_string())
+                .replace("{height}", &info.height.to_string())
+                .replace("{fps}", &fps);
+        }
+        name
+    }
+}
+
+/// Checks that every `{...}` is a known placeholder and that the name stays in the output directory.
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.contains(['/', '\\']) {
+            return Err(format!("expected a file name without directories, got {s:?}"));
+        }
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed {{ in {s:?}"));
+            };
+            let placeholder = &rest[start + 1..start + len];
+            if !Self::PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!("unknown placeholder {{{placeholder}}}, expected one of {}",
+                    Self::PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")));
+            }
+            rest = &rest[start + len + 1..];
+        }
+        Ok(Self(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_templates() {
+        let template = "{stem}_{width}x{height}.gif".parse::<NameTemplate>().unwrap();
+        assert!(template.needs_info());
+        let template = "{stem}-small.{ext}".parse::<NameTemplate>().unwrap();
+        assert!(!template.needs_info());
+        assert_eq!(template.render(Utf8Path::new("clips/intro.webm"), None), "intro-small.webm");
+        assert_eq!("plain".parse::<NameTemplate>().unwrap().render(Utf8Path::new("a.webm"), None), "plain");
+    }
+
+    #[test]
+    fn rejects_invalid_name_templates() {
+        assert_eq!("".parse::<NameTemplate>().err().unwrap(), "expected a file name without directories, got \"\"");
+        assert!("out/{stem}".parse::<NameTemplate>().is_err());
+        assert!("out\\{stem}".parse::<NameTemplate>().is_err());
+        assert_eq!("{stem".parse::<NameTemplate>().err().unwrap(), "unclosed { in \"{stem\"");
+        assert_eq!("{size}.gif".parse::<NameTemplate>().err().unwrap(),
+            "unknown placeholder {size}, expected one of {stem}, {ext}, {width}, {height}, {fps}");
+    }
+}
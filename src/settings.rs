@@ -0,0 +1,122 @@
+use std::fmt;
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use gifski::{Collector, Repeat, Writer};
+use rgb::RGB8;
+
+use crate::cli::Args;
+use crate::encoder::{Format, QUEUE_LENGTH};
+
+#[derive(Clone, Copy)]
+pub struct EncodeSettings {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub quality: u8,
+    /// Overrides `quality` for temporal denoising and frame merging.
+    pub motion_quality: Option<u8>,
+    /// Enables lossy LZW compression, see gifsicle's `--lossy`.
+    pub lossy_quality: Option<u8>,
+    /// Background color used in place of partially transparent pixels.
+    pub matte: Option<RGB8>,
+    pub fast: bool,
+    pub repeat: Repeat,
+    /// Frames beyond this many are dropped and decoding stops.
+    pub max_frames: Option<usize>,
+    /// Overrides the format implied by the extension of the output.
+    pub format: Option<Format>,
+    /// Keeps a scaled-down copy of the encoded frames to measure the quality of the output against.
+    pub metrics: bool,
+    /// Frames decoding may get ahead of encoding before it waits, which bounds the memory taken by long videos.
+    pub queue_length: usize,
+}
+
+impl Default for EncodeSettings {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            quality: 100,
+            motion_quality: None,
+            lossy_quality: None,
+            matte: None,
+            fast: false,
+            repeat: Repeat::Infinite,
+            max_frames: None,
+            format: None,
+            metrics: false,
+            queue_length: QUEUE_LENGTH,
+        }
+    }
+}
+
+impl EncodeSettings {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            width: args.width,
+            height: args.height,
+            fast: args.fast,
+            repeat: if args.no_loop { Repeat::Finite(0) } else { args.repeat.unwrap_or(Repeat::Infinite) },
+            quality: args.quality.unwrap_or(100),
+            motion_quality: args.motion_quality,
+            lossy_quality: args.lossy_quality,
+            matte: args.matte,
+            max_frames: args.max_frames,
+            format: args.format,
+            metrics: args.metrics,
+            queue_length: args.queue,
+        }
+    }
+
+    pub fn format_for(&self, output: &Utf8Path) -> Result<Format> {
+        self.format.map_or_else(|| Format::from_path(output), Ok)
+    }
+
+    pub fn new_gifski(&self) -> Result<(Collector, Writer)> {
+        let (collector, mut writer) = gifski::new(gifski::Settings {
+            width: self.width,
+            height: self.height,
+            quality: self.quality,
+            fast: self.fast,
+            repeat: self.repeat,
+        })?;
+
+        if let Some(q) = self.motion_quality {
+            writer.set_motion_quality(q);
+        }
+        if let Some(q) = self.lossy_quality {
+            writer.set_lossy_quality(q);
+        }
+        if let Some(color) = self.matte {
+            writer.set_matte_color(color);
+        }
+        Ok((collector, writer))
+    }
+}
+
+/// Lists the settings that differ from plain gifski, e.g. `quality 100, lossy quality 60`.
+impl fmt::Display for EncodeSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "quality {}", self.quality)?;
+        if let Some(q) = self.motion_quality {
+            write!(f, ", motion quality {q}")?;
+        }
+        if let Some(q) = self.lossy_quality {
+            write!(f, ", lossy quality {q}")?;
+        }
+        if let Some(c) = self.matte {
+            write!(f, ", matte #{:02X}{:02X}{:02X}", c.r, c.g, c.b)?;
+        }
+        if let (Some(w), Some(h)) = (self.width, self.height) {
+            write!(f, ", {w}x{h}")?;
+        } else if let Some(w) = self.width {
+            write!(f, ", width {w}")?;
+        } else if let Some(h) = self.height {
+            write!(f, ", height {h}")?;
+        }
+        if self.fast {
+            f.write_str(", fast")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+use crate::settings::EncodeSettings;
+
+/// Decoded frames are kept for retries as long as they take up less memory than this.
+pub const CACHE_LIMIT: usize = 1 << 30;
+
+/// One retry of `--auto-shrink`.
+pub struct ShrinkStep {
+    /// Output width relative to the input.
+    pub scale: f32,
+    /// Only every `frame_step`th frame is kept.
+    pub frame_step: usize,
+    pub quality: u8,
+}
+
+/// Retries in order, each one giving up more than the one before.
+pub const STEPS: &[ShrinkStep] = &[
+    ShrinkStep { scale: 1.0, frame_step: 1, quality: 80 },
+    ShrinkStep { scale: 0.75, frame_step: 1, quality: 80 },
+    ShrinkStep { scale: 0.75, frame_step: 2, quality: 70 },
+    ShrinkStep { scale: 0.5, frame_step: 2, quality: 70 },
+    ShrinkStep { scale: 0.5, frame_step: 3, quality: 50 },
+    ShrinkStep { scale: 0.33, frame_step: 3, quality: 50 },
+];
+
+impl ShrinkStep {
+    /// Applies the step to the settings the user asked for, for an input `width` pixels wide.
+    pub fn apply(&self, settings: &EncodeSettings, width: u32) -> EncodeSettings {
+        let scale = |v: u32| ((v as f32 * self.scale).round() as u32).max(1);
+        EncodeSettings {
+            width: Some(scale(settings.width.unwrap_or(width))),
+            height: settings.height.map(scale),
+            quality: settings.quality.min(self.quality),
+            ..*settings
+        }
+    }
+}
+
+impl fmt::Display for ShrinkStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0}% size", self.scale * 100.0)?;
+        match self.frame_step {
+            1 => {},
+            2 => f.write_str(", every 2nd frame")?,
+            3 => f.write_str(", every 3rd frame")?,
+            n => write!(f, ", every {n}th frame")?,
+        }
+        write!(f, ", quality {}", self.quality)
+    }
+}
@@ -0,0 +1,94 @@
+//! The folders of `--stages`: files to convert are dropped into `incoming`, sit in `processing` while they are
+//! converted and end up in `done` with their output, or in `failed`. Files only change folders by being renamed, so
+//! other programs can tell how far a file got from the folder it is in.
+
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::Result;
+use color_eyre::eyre::{eyre, Context};
+use tracing::{error, info};
+
+pub struct Stages {
+    incoming: Utf8PathBuf,
+    processing: Utf8PathBuf,
+    done: Utf8PathBuf,
+    failed: Utf8PathBuf,
+}
+
+impl Stages {
+    /// Creates the folders under `dir`. Inputs left in `processing` by a run that was stopped go back to `incoming`,
+    /// to be converted again; inputs are the files with one of `extensions`.
+    pub fn create(dir: &Utf8Path, extensions: &[String]) -> Result<Self> {
+        let stages = Self {
+            incoming: dir.join("incoming"),
+            processing: dir.join("processing"),
+            done: dir.join("done"),
+            failed: dir.join("failed"),
+        };
+        for dir in [&stages.incoming, &stages.processing, &stages.done, &stages.failed] {
+            fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {dir}"))?;
+        }
+        stages.restore(&stages.processing, extensions)?;
+        Ok(stages)
+    }
+
+    /// Where new files are picked up.
+    pub fn incoming(&self) -> &Utf8Path {
+        &self.incoming
+    }
+
+    /// Where inputs are converted, the directory their outputs are placed relative to.
+    pub fn processing(&self) -> &Utf8Path {
+        &self.processing
+    }
+
+    /// Moves `input` from `incoming` into `processing` and returns its new path.
+    pub fn start(&self, input: &Utf8Path) -> Result<Utf8PathBuf> {
+        let relative = input.strip_prefix(&self.incoming).map_err(|_| eyre!("{input} is not in {}", self.incoming))?;
+        let path = self.processing.join(relative);
+        move_file(input, &path)?;
+        Ok(path)
+    }
+
+    /// Moves `input` on from `processing`, to `done` together with `output` if that was written next to it, or to
+    /// `failed`. Errors are only printed, the next file can be converted all the same.
+    pub fn finish(&self, input: &Utf8Path, output: &Utf8Path, converted: bool) {
+        let result: Result<()> = (|| {
+            let Ok(relative) = input.strip_prefix(&self.processing) else { return Ok(()) };
+            let stage = if converted { &self.done } else { &self.failed };
+            move_file(input, &stage.join(relative))?;
+            match output.strip_prefix(&self.processing) {
+                Ok(relative) if converted && output.exists() => move_file(output, &self.done.join(relative)),
+                _ => Ok(()),
+            }
+        })();
+        if let Err(e) = result {
+            error!("{e:#}");
+        }
+    }
+
+    fn restore(&self, dir: &Utf8Path, extensions: &[String]) -> Result<()> {
+        for entry in fs::read_dir(dir).wrap_err_with(|| format!("failed to list files in {dir}"))? {
+            let path = Utf8PathBuf::from_path_buf(entry?.path()).map_err(|p| eyre!("invalid utf-8 path: {:?}", p))?;
+            if path.is_dir() {
+                self.restore(&path, extensions)?;
+                continue;
+            }
+            let is_input = path.extension().map_or(false, |ext| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)));
+            if is_input {
+                let relative = path.strip_prefix(&self.processing).unwrap_or_else(|_| unreachable!());
+                info!("Converting {relative} again, it was left in {}", self.processing);
+                move_file(&path, &self.incoming.join(relative))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn move_file(from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {dir}"))?;
+    }
+    fs::rename(from, to).wrap_err_with(|| format!("failed to move {from} to {to}"))
+}
@@ -0,0 +1,167 @@
+/// Shows how far a batch is on the taskbar button of the console window on Windows; does nothing elsewhere, or
+/// when the console has no taskbar button.
+pub struct Taskbar {
+    inner: Option<platform::TaskbarList>,
+    failed: bool,
+}
+
+impl Taskbar {
+    pub fn new() -> Self {
+        Self { inner: platform::TaskbarList::new(), failed: false }
+    }
+
+    /// `done` out of the `total` inputs known so far are finished.
+    pub fn set_progress(&mut self, done: u64, total: u64) {
+        if let Some(inner) = &self.inner {
+            inner.set_progress(done, total.max(1));
+        }
+    }
+
+    /// Turns the button red; the state is kept after exiting so that a failed batch stands out.
+    pub fn set_error(&mut self) {
+        if let Some(inner) = &self.inner {
+            inner.set_error();
+        }
+        self.failed = true;
+    }
+}
+
+impl Drop for Taskbar {
+    fn drop(&mut self) {
+        if let (Some(inner), false) = (&self.inner, self.failed) {
+            inner.clear();
+        }
+    }
+}
+
+/// `ITaskbarList3` through hand-written COM bindings.
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    type Hwnd = *mut c_void;
+    type HResult = i32;
+
+    #[repr(C)]
+    struct Guid(u32, u16, u16, [u8; 8]);
+
+    const CLSID_TASKBAR_LIST: Guid = Guid(0x56fdf344, 0xfd6d, 0x11d0, [0x95, 0x8a, 0x00, 0x60, 0x97, 0xc9, 0xa0, 0x90]);
+    const IID_ITASKBAR_LIST3: Guid = Guid(0xea1afb91, 0x9e28, 0x4b86, [0x90, 0xe9, 0x9e, 0x9f, 0x8a, 0x5e, 0xef, 0xaf]);
+    const COINIT_APARTMENTTHREADED: u32 = 0x2;
+    const CLSCTX_INPROC_SERVER: u32 = 0x1;
+    const TBPF_NOPROGRESS: u32 = 0x0;
+    const TBPF_NORMAL: u32 = 0x2;
+    const TBPF_ERROR: u32 = 0x4;
+
+    #[repr(C)]
+    struct Object {
+        vtbl: *const Vtbl,
+    }
+
+    /// The start of the `ITaskbarList3` vtable, up to the methods used here.
+    #[repr(C)]
+    struct Vtbl {
+        query_interface: *const c_void,
+        add_ref: *const c_void,
+        release: unsafe extern "system" fn(*mut Object) -> u32,
+        hr_init: unsafe extern "system" fn(*mut Object) -> HResult,
+        add_tab: *const c_void,
+        delete_tab: *const c_void,
+        activate_tab: *const c_void,
+        set_active_alt: *const c_void,
+        mark_fullscreen_window: *const c_void,
+        set_progress_value: unsafe extern "system" fn(*mut Object, Hwnd, u64, u64) -> HResult,
+        set_progress_state: unsafe extern "system" fn(*mut Object, Hwnd, u32) -> HResult,
+    }
+
+    #[link(name = "ole32")]
+    extern "system" {
+        fn CoInitializeEx(reserved: *mut c_void, coinit: u32) -> HResult;
+        fn CoCreateInstance(clsid: *const Guid, outer: *mut c_void, context: u32, iid: *const Guid, object: *mut *mut c_void)
+            -> HResult;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetConsoleWindow() -> Hwnd;
+    }
+
+    pub struct TaskbarList {
+        object: *mut Object,
+        window: Hwnd,
+    }
+
+    impl TaskbarList {
+        pub fn new() -> Option<Self> {
+            unsafe {
+                let window = GetConsoleWindow();
+                if window.is_null() {
+                    return None;
+                }
+                // fails harmlessly if COM is already initialized on this thread
+                CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED);
+                let mut object = ptr::null_mut();
+                if CoCreateInstance(&CLSID_TASKBAR_LIST, ptr::null_mut(), CLSCTX_INPROC_SERVER, &IID_ITASKBAR_LIST3, &mut object) < 0
+                    || object.is_null() {
+                    return None;
+                }
+                let list = Self { object: object as *mut Object, window };
+                if ((*(*list.object).vtbl).hr_init)(list.object) < 0 {
+                    return None;
+                }
+                Some(list)
+            }
+        }
+
+        pub fn set_progress(&self, done: u64, total: u64) {
+            unsafe {
+                ((*(*self.object).vtbl).set_progress_state)(self.object, self.window, TBPF_NORMAL);
+                ((*(*self.object).vtbl).set_progress_value)(self.object, self.window, done, total);
+            }
+        }
+
+        pub fn set_error(&self) {
+            unsafe {
+                ((*(*self.object).vtbl).set_progress_state)(self.object, self.window, TBPF_ERROR);
+            }
+        }
+
+        pub fn clear(&self) {
+            unsafe {
+                ((*(*self.object).vtbl).set_progress_state)(self.object, self.window, TBPF_NOPROGRESS);
+            }
+        }
+    }
+
+    impl Drop for TaskbarList {
+        fn drop(&mut self) {
+            unsafe {
+                ((*(*self.object).vtbl).release)(self.object);
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod platform {
+    pub enum TaskbarList {}
+
+    impl TaskbarList {
+        pub fn new() -> Option<Self> {
+            None
+        }
+
+        pub fn set_progress(&self, _done: u64, _total: u64) {
+            match *self {}
+        }
+
+        pub fn set_error(&self) {
+            match *self {}
+        }
+
+        pub fn clear(&self) {
+            match *self {}
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::lazy::SyncOnceCell;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest single read or write, so that a big buffer does not use up a whole second of the budget at once.
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// The budget shared by every `Throttled` reader and writer, set once by `set_limit`.
+static LIMIT: SyncOnceCell<Mutex<Bucket>> = SyncOnceCell::new();
+
+struct Bucket {
+    /// Bytes per second.
+    rate: f64,
+    /// Bytes that may be transferred right away; negative while earlier transfers are being paid off.
+    available: f64,
+    refilled: Instant,
+}
+
+/// Limits the combined rate of all reads and writes through `Throttled` to `bytes_per_second`.
+pub fn set_limit(bytes_per_second: u64) {
+    let bucket = Bucket { rate: bytes_per_second as f64, available: 0.0, refilled: Instant::now() };
+    LIMIT.set(Mutex::new(bucket)).ok();
+}
+
+/// Whether `set_limit` was called.
+pub fn is_limited() -> bool {
+    LIMIT.get().is_some()
+}
+
+/// Takes `bytes` out of the budget and sleeps for as long as it is overdrawn.
+fn consume(bytes: usize) {
+    let Some(limit) = LIMIT.get() else { return };
+    let wait = {
+        let mut bucket = limit.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        // at most a second's worth can be saved up for bursts
+        bucket.available = (bucket.available + now.duration_since(bucket.refilled).as_secs_f64() * bucket.rate)
+            .min(bucket.rate);
+        bucket.refilled = now;
+        bucket.available -= bytes as f64;
+        (bucket.available < 0.0).then(|| Duration::from_secs_f64(-bucket.available / bucket.rate))
+    };
+    if let Some(wait) = wait {
+        thread::sleep(wait);
+    }
+}
+
+/// A reader or writer whose transfers count against the limit set with `set_limit`, if any.
+pub struct Throttled<T>(pub T);
+
+impl<T: Read> Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_CHUNK);
+        let n = self.0.read(&mut buf[..len])?;
+        consume(n);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Throttled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_CHUNK);
+        let n = self.0.write(&buf[..len])?;
+        consume(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Seek> Seek for Throttled<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
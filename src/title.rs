@@ -0,0 +1,38 @@
+use console::Term;
+
+/// Saves the title of the terminal window or tab and restores it when dropped, so that progress shown with `set`
+/// does not stay behind after exiting.
+///
+/// Uses the xterm title stack, which most terminals and multiplexers either support or ignore.
+pub struct TitleGuard {
+    term: Option<Term>,
+}
+
+impl TitleGuard {
+    pub fn new() -> Self {
+        let term = Term::stderr();
+        if !term.is_term() {
+            return Self { term: None };
+        }
+        let _ = term.write_str("\x1b[22;0t");
+        Self { term: Some(term) }
+    }
+}
+
+impl Drop for TitleGuard {
+    fn drop(&mut self) {
+        if let Some(term) = &self.term {
+            let _ = term.write_str("\x1b[23;0t");
+        }
+    }
+}
+
+/// Sets the title of the terminal window or tab, unless stderr is not a terminal.
+pub fn set(title: &str) {
+    let term = Term::stderr();
+    if term.is_term() {
+        // control characters would end the escape sequence early
+        let title = title.replace(char::is_control, " ");
+        let _ = term.write_str(&format!("\x1b]0;{title}\x07"));
+    }
+}